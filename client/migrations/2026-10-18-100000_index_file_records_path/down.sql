@@ -0,0 +1 @@
+DROP INDEX file_records_namespace_id_path;
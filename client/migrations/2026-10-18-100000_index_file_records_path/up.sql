@@ -0,0 +1 @@
+CREATE INDEX file_records_namespace_id_path ON file_records (namespace_id, path);
@@ -0,0 +1,12 @@
+-- Remote records a download is writing to disk. Dropped together with the
+-- registry update of their batch, so left over ones belong to a download
+-- that was interrupted.
+CREATE TABLE download_intents (
+  id INTEGER PRIMARY KEY NOT NULL,
+  namespace_id INTEGER NOT NULL,
+  jid INTEGER NOT NULL,
+  path TEXT NOT NULL,
+  deleted BOOL NOT NULL,
+  chunk_ids TEXT NOT NULL,
+  checksum TEXT
+)
@@ -0,0 +1,11 @@
+-- Remote records of binary files that weren't downloaded on a metered
+-- network. At most one per path, the latest, fetched once the network
+-- isn't metered anymore.
+CREATE TABLE deferred_downloads (
+  id INTEGER PRIMARY KEY NOT NULL,
+  namespace_id INTEGER NOT NULL,
+  jid INTEGER NOT NULL,
+  path TEXT NOT NULL,
+  chunk_ids TEXT NOT NULL,
+  moved_from TEXT
+)
@@ -0,0 +1,74 @@
+//! Jittered exponential backoff for retrying after transient errors.
+//!
+//! Delays double with every attempt up to a cap, and each one is picked at
+//! random below that bound ("full jitter"), so clients that lost the
+//! server at the same moment don't all come back at the same moment too.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Backoff {
+        Backoff {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Delay before the next attempt, counting it as one.
+    pub fn next_delay(&mut self) -> Duration {
+        let bound = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        bound.mul_f64(fastrand::f64())
+    }
+
+    /// Attempts since the last success.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Starts over after a success.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_up_to_the_cap() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+
+        for attempt in 0..40 {
+            let bound = Duration::from_secs(2u64.pow(attempt.min(6))).min(Duration::from_secs(60));
+            assert!(backoff.next_delay() <= bound, "attempt {}", attempt);
+        }
+        assert_eq!(backoff.attempt(), 40);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(60));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+
+        backoff.reset();
+
+        assert_eq!(backoff.attempt(), 0);
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,228 @@
+//! Bandwidth limits and metered networks.
+//!
+//! The host app knows what network the device is on, the sync only follows
+//! what it's told: how many bytes per second it may move each way, and
+//! whether binary files should wait for a network that isn't metered, e.g.
+//! Wi-Fi instead of cellular. Changes apply right away, also mid-sync.
+
+use std::sync::Mutex;
+
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// Spreads transfers out to a number of bytes per second. Transfers that
+/// run at the same time share it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: watch::Sender<u64>,
+    clock: Mutex<Clock>,
+}
+
+/// Where the bytes let through so far stand against time: byte `bytes` is
+/// paid for at `at`, the ones after it at the current rate.
+#[derive(Debug)]
+struct Clock {
+    bytes: u64,
+    at: Instant,
+    /// Bytes let through so far.
+    reserved: u64,
+}
+
+impl Clock {
+    /// When `byte` is paid for at `rate`, `None` without a limit.
+    fn due(&self, byte: u64, rate: u64) -> Option<Instant> {
+        let ahead = byte.saturating_sub(self.bytes);
+        (rate != 0).then(|| self.at + Duration::from_secs_f64(ahead as f64 / rate as f64))
+    }
+
+    /// Restarts the count from what's let through and paid for by now.
+    /// Time left unused doesn't add up, so an idle connection doesn't get
+    /// to burst afterwards.
+    fn settle(&mut self, rate: u64) {
+        let now = Instant::now();
+        let paid = match rate {
+            0 => self.reserved,
+            _ => {
+                let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+                (self.bytes + (elapsed * rate as f64) as u64).min(self.reserved)
+            }
+        };
+        self.bytes = paid;
+        self.at = now;
+    }
+}
+
+impl RateLimiter {
+    /// Limited to `bytes_per_sec`, 0 for no limit.
+    pub fn new(bytes_per_sec: u64) -> RateLimiter {
+        RateLimiter {
+            bytes_per_sec: watch::Sender::new(bytes_per_sec),
+            clock: Mutex::new(Clock {
+                bytes: 0,
+                at: Instant::now(),
+                reserved: 0,
+            }),
+        }
+    }
+
+    /// Transfers waiting for their turn pick up the new limit right away.
+    pub fn set_limit(&self, bytes_per_sec: u64) {
+        let mut clock = self.clock();
+        // What's owed at the old rate is paid at the new one from now on.
+        clock.settle(self.limit());
+        self.bytes_per_sec.send_replace(bytes_per_sec);
+    }
+
+    pub fn limit(&self) -> u64 {
+        *self.bytes_per_sec.borrow()
+    }
+
+    /// Waits until `bytes` more may go through.
+    pub async fn acquire(&self, bytes: usize) {
+        // Before looking at the limit, so no change goes unnoticed.
+        let mut changes = self.bytes_per_sec.subscribe();
+
+        let start = {
+            let mut clock = self.clock();
+            let rate = self.limit();
+            if rate == 0 {
+                return;
+            }
+            if clock
+                .due(clock.reserved, rate)
+                .is_none_or(|due| due < Instant::now())
+            {
+                clock.settle(rate);
+            }
+            let start = clock.reserved;
+            clock.reserved += bytes as u64;
+            start
+        };
+
+        loop {
+            let Some(due) = self.clock().due(start, self.limit()) else {
+                return;
+            };
+            tokio::select! {
+                _ = sleep_until(due) => return,
+                // The sender lives as long as `self`, so this can't fail.
+                _ = changes.changed() => {}
+            }
+        }
+    }
+
+    fn clock(&self) -> std::sync::MutexGuard<'_, Clock> {
+        self.clock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// What the sync may use the network for. Shared by the `SyncContext` the
+/// app changes it on and the `Remote` that follows it.
+#[derive(Debug)]
+pub struct NetworkPolicy {
+    upload: RateLimiter,
+    download: RateLimiter,
+    metered: watch::Sender<bool>,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> Self {
+        NetworkPolicy {
+            upload: RateLimiter::new(0),
+            download: RateLimiter::new(0),
+            metered: watch::Sender::new(false),
+        }
+    }
+}
+
+impl NetworkPolicy {
+    pub fn upload(&self) -> &RateLimiter {
+        &self.upload
+    }
+
+    pub fn download(&self) -> &RateLimiter {
+        &self.download
+    }
+
+    /// While metered, binary files wait and text files sync as usual.
+    pub fn set_metered(&self, metered: bool) {
+        self.metered.send_replace(metered);
+    }
+
+    pub fn is_metered(&self) -> bool {
+        *self.metered.borrow()
+    }
+
+    /// Resolves once the network isn't metered, right away if it isn't.
+    pub async fn unmetered(&self) {
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = self.metered.subscribe().wait_for(|metered| !metered).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limiter_spreads_bytes_over_time() {
+        let limiter = RateLimiter::new(10_000);
+        let started = Instant::now();
+
+        for _ in 0..5 {
+            limiter.acquire(1_000).await;
+        }
+
+        // The first one goes right away, each of the rest 100ms later.
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn limiter_without_limit_doesnt_wait() {
+        let limiter = RateLimiter::new(10);
+        limiter.set_limit(0);
+        let started = Instant::now();
+
+        limiter.acquire(1_000_000).await;
+        limiter.acquire(1_000_000).await;
+
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn limiter_picks_up_a_new_limit_while_waiting() {
+        let limiter = std::sync::Arc::new(RateLimiter::new(100));
+        limiter.acquire(1_000).await;
+
+        // Due in 10s at the old limit.
+        let waiting = tokio::spawn({
+            let limiter = std::sync::Arc::clone(&limiter);
+            async move { limiter.acquire(1_000).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        let started = Instant::now();
+        limiter.set_limit(1_000_000);
+        waiting.await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn unmetered_waits_for_the_network_to_change() {
+        let network = std::sync::Arc::new(NetworkPolicy::default());
+        network.unmetered().await;
+
+        network.set_metered(true);
+        let waiting = tokio::spawn({
+            let network = std::sync::Arc::clone(&network);
+            async move { network.unmetered().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        network.set_metered(false);
+        waiting.await.unwrap();
+        assert!(!network.is_metered());
+    }
+}
@@ -0,0 +1,139 @@
+//! Chunk IDs are `<algorithm>:<full hex digest>`, e.g. `b3:af13...`.
+//! With end-to-end encryption on, IDs are keyed BLAKE3 hashes (`k3:`), so
+//! the server can't confirm a guess about a chunk's content.
+//!
+//! Older clients used bare SHA-256 hex prefixes: 10 chars for text lines and
+//! 32 chars for binary blocks. These are still accepted wherever an ID is
+//! read, but never produced anymore, as prefixes that short collide.
+
+use sha2::{Digest, Sha256};
+
+pub const BLAKE3_PREFIX: &str = "b3:";
+pub const KEYED_BLAKE3_PREFIX: &str = "k3:";
+
+const BLAKE3_HEX_LEN: usize = 64;
+const LEGACY_TEXT_LEN: usize = 10;
+const LEGACY_BINARY_LEN: usize = 32;
+
+/// ID of `data` under the current scheme.
+pub fn blake3(data: &[u8]) -> String {
+    format!("{}{}", BLAKE3_PREFIX, ::blake3::hash(data).to_hex())
+}
+
+/// ID of `data` hashed with `key`, see `crypto::Cipher`.
+pub fn keyed_blake3(key: &[u8; 32], data: &[u8]) -> String {
+    format!(
+        "{}{}",
+        KEYED_BLAKE3_PREFIX,
+        ::blake3::keyed_hash(key, data).to_hex()
+    )
+}
+
+/// Whether `id` was produced by `keyed_blake3`, i.e. its chunk is encrypted
+/// on the server.
+pub fn is_keyed(id: &str) -> bool {
+    id.starts_with(KEYED_BLAKE3_PREFIX)
+}
+
+/// Legacy ID: the first `size` hex chars of the SHA-256 digest.
+pub fn legacy_sha256(data: &[u8], size: usize) -> String {
+    let mut hasher = Sha256::new();
+
+    hasher.update(data);
+
+    let result = hasher.finalize();
+    let hex_string = format!("{:x}", result);
+
+    hex_string[0..size].to_string()
+}
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Whether `id` is well formed under either the current or legacy scheme.
+pub fn is_valid(id: &str) -> bool {
+    if let Some(digest) = id
+        .strip_prefix(BLAKE3_PREFIX)
+        .or_else(|| id.strip_prefix(KEYED_BLAKE3_PREFIX))
+    {
+        digest.len() == BLAKE3_HEX_LEN && is_hex(digest)
+    } else {
+        (id.len() == LEGACY_TEXT_LEN || id.len() == LEGACY_BINARY_LEN) && is_hex(id)
+    }
+}
+
+/// Whether `data` is the content `id` refers to. Keyed IDs can't be checked
+/// without the key and never verify here.
+pub fn verify(id: &str, data: &[u8]) -> bool {
+    if id.is_empty() {
+        return data.is_empty();
+    }
+
+    if !is_valid(id) || is_keyed(id) {
+        return false;
+    }
+
+    if id.starts_with(BLAKE3_PREFIX) {
+        blake3(data) == id
+    } else {
+        legacy_sha256(data, id.len()) == id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blake3_ids_are_tagged_and_full_length() {
+        let id = blake3(b"eggs\n");
+
+        assert!(id.starts_with("b3:"));
+        assert_eq!(id.len(), 3 + 64);
+        assert!(is_valid(&id));
+        assert_ne!(id, blake3(b"flour\n"));
+    }
+
+    #[test]
+    fn keyed_ids_depend_on_key() {
+        let id = keyed_blake3(&[1; 32], b"eggs\n");
+
+        assert!(id.starts_with("k3:"));
+        assert!(is_valid(&id));
+        assert!(is_keyed(&id));
+        assert_ne!(id, keyed_blake3(&[2; 32], b"eggs\n"));
+        assert!(!verify(&id, b"eggs\n"));
+    }
+
+    #[test]
+    fn legacy_ids_are_still_valid() {
+        assert!(is_valid(&legacy_sha256(b"eggs\n", 10)));
+        assert!(is_valid(&legacy_sha256(b"eggs\n", 32)));
+        assert!(!is_valid(&legacy_sha256(b"eggs\n", 16)));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        assert!(!is_valid(""));
+        assert!(!is_valid("b3:abc"));
+        assert!(!is_valid("md5:0123456789"));
+        assert!(!is_valid("../../etc/pass"));
+        assert!(!is_valid("ABCDEF0123"));
+        assert!(!is_valid(&format!("b3:{}", "g".repeat(64))));
+    }
+
+    #[test]
+    fn verify_checks_content_for_every_scheme() {
+        let data = b"eggs\n";
+
+        assert!(verify(&blake3(data), data));
+        assert!(verify(&legacy_sha256(data, 10), data));
+        assert!(verify(&legacy_sha256(data, 32), data));
+        assert!(verify("", b""));
+
+        assert!(!verify(&blake3(data), b"flour\n"));
+        assert!(!verify(&legacy_sha256(data, 10), b"flour\n"));
+        assert!(!verify("nonsense", data));
+    }
+}
@@ -0,0 +1,533 @@
+use log::{trace, warn};
+use quick_cache::{sync::Cache, Weighter};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::errors::SyncError;
+
+type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Content-addressed storage for chunk bodies, keyed by chunk ID.
+///
+/// The chunker writes every chunk it produces or downloads into a store and
+/// reads them back when it materializes a file, so an implementation only
+/// needs to answer "do you have this ID" and "give me its bytes".
+pub trait ChunkStore: Send {
+    fn get(&self, chunk_hash: &str) -> Result<Vec<u8>>;
+    fn set(&mut self, chunk_hash: &str, content: Vec<u8>) -> Result<()>;
+    fn contains(&self, chunk_hash: &str) -> bool;
+
+    /// Size of the chunk's content, without reading it if the store can
+    /// tell otherwise.
+    fn chunk_size(&self, chunk_hash: &str) -> Result<u64> {
+        Ok(self.get(chunk_hash)?.len() as u64)
+    }
+}
+
+#[derive(Clone)]
+pub struct BytesWeighter;
+
+impl Weighter<String, Vec<u8>> for BytesWeighter {
+    fn weight(&self, _key: &String, val: &Vec<u8>) -> u64 {
+        // Be cautions out about zero weights!
+        val.len().clamp(1, u64::MAX as usize) as u64
+    }
+}
+
+/// Volatile store, lost on restart. Useful for tests and one-off tools.
+pub struct InMemoryCache {
+    cache: Cache<String, Vec<u8>, BytesWeighter>,
+}
+
+impl InMemoryCache {
+    pub fn new(total_keys: usize, total_weight: u64) -> InMemoryCache {
+        InMemoryCache {
+            cache: Cache::with_weighter(total_keys, total_weight, BytesWeighter),
+        }
+    }
+}
+
+impl ChunkStore for InMemoryCache {
+    fn get(&self, chunk_hash: &str) -> Result<Vec<u8>> {
+        if chunk_hash.is_empty() {
+            return Ok(vec![]);
+        }
+
+        match self.cache.get(chunk_hash) {
+            Some(content) => Ok(content.clone()),
+            None => Err(SyncError::GetFromCacheError),
+        }
+    }
+
+    fn set(&mut self, chunk_hash: &str, content: Vec<u8>) -> Result<()> {
+        // trace!("setting hash {:?} data  {:?}", chunk_hash, content.len());
+        self.cache.insert(chunk_hash.to_string(), content);
+        Ok(())
+    }
+
+    fn contains(&self, chunk_hash: &str) -> bool {
+        self.cache.get(chunk_hash).is_some()
+    }
+}
+
+/// Persistent store keeping one file per chunk under `root`, sharded by the
+/// first two characters of the digest (`root/ab/abcdef...`).
+///
+/// The total size is bounded by `max_bytes`: when an insert pushes it over
+/// the limit the least recently used chunks are removed. Using a chunk sets
+/// its file's mtime, which is where recency comes from on the next open.
+pub struct DiskChunkStore {
+    root: PathBuf,
+    max_bytes: u64,
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    tick: u64,
+    total_bytes: u64,
+    // file name -> (last use tick, size)
+    entries: HashMap<String, (u64, u64)>,
+    // last use tick -> file name
+    order: BTreeMap<u64, String>,
+}
+
+impl LruState {
+    fn touch(&mut self, name: &str) {
+        if let Some((tick, _)) = self.entries.get_mut(name) {
+            self.order.remove(tick);
+            self.tick += 1;
+            *tick = self.tick;
+            self.order.insert(self.tick, name.to_string());
+        }
+    }
+
+    fn insert(&mut self, name: String, size: u64) {
+        self.tick += 1;
+        self.total_bytes += size;
+        self.order.insert(self.tick, name.clone());
+        self.entries.insert(name, (self.tick, size));
+    }
+
+    fn pop_oldest(&mut self) -> Option<(String, u64)> {
+        let (_, name) = self.order.pop_first()?;
+        let (_, size) = self.entries.remove(&name)?;
+        self.total_bytes -= size;
+        Some((name, size))
+    }
+}
+
+impl DiskChunkStore {
+    /// Opens (creating if needed) a store rooted at `root`. Leftovers of
+    /// interrupted writes are removed while the existing chunks are indexed.
+    pub fn open(root: impl Into<PathBuf>, max_bytes: u64) -> Result<DiskChunkStore> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(|e| SyncError::from_io_error(&root, e))?;
+
+        let mut found = Vec::new();
+        for shard in fs::read_dir(&root).map_err(|e| SyncError::from_io_error(&root, e))? {
+            let shard = shard.map_err(|e| SyncError::from_io_error(&root, e))?;
+            if !shard.path().is_dir() {
+                continue;
+            }
+
+            for entry in
+                fs::read_dir(shard.path()).map_err(|e| SyncError::from_io_error(shard.path(), e))?
+            {
+                let entry = entry.map_err(|e| SyncError::from_io_error(shard.path(), e))?;
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().into_owned();
+
+                if name.ends_with(TMP_SUFFIX) {
+                    trace!("removing interrupted chunk write {:?}", path);
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+
+                let metadata = entry
+                    .metadata()
+                    .map_err(|e| SyncError::from_io_error(&path, e))?;
+                let modified = metadata.modified().ok();
+                found.push((modified, name, metadata.len()));
+            }
+        }
+
+        // Oldest first so that they get the lowest ticks.
+        found.sort();
+
+        let mut state = LruState::default();
+        for (_, name, size) in found {
+            state.insert(name, size);
+        }
+
+        let store = DiskChunkStore {
+            root,
+            max_bytes,
+            state: Mutex::new(state),
+        };
+        store.evict(None);
+
+        Ok(store)
+    }
+
+    /// Total size of the chunks currently kept, in bytes.
+    pub fn size(&self) -> u64 {
+        self.lock().total_bytes
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruState> {
+        // Handle poisoned mutex by recovering the guard
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn file_name(chunk_hash: &str) -> String {
+        // `:` separates an algorithm prefix from the digest and isn't allowed
+        // in file names on every platform.
+        chunk_hash.replace(':', "_")
+    }
+
+    /// Marks a chunk as just used, in memory and on disk.
+    fn touch(&self, name: &str) {
+        self.lock().touch(name);
+
+        let path = self.chunk_path(name);
+        let touched = fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = touched {
+            // Only costs the order after a restart.
+            trace!("failed to touch chunk {:?}: {}", path, e);
+        }
+    }
+
+    fn chunk_path(&self, name: &str) -> PathBuf {
+        let digest = name.rsplit('_').next().unwrap_or(name);
+        let shard = digest.get(0..2).unwrap_or("__");
+
+        self.root.join(shard).join(name)
+    }
+
+    fn evict(&self, keep: Option<&str>) {
+        let mut state = self.lock();
+
+        while state.total_bytes > self.max_bytes {
+            let Some((name, size)) = state.pop_oldest() else {
+                break;
+            };
+
+            if Some(name.as_str()) == keep {
+                // Never evict what was just written, even if it alone is
+                // larger than the budget, otherwise `save` can't use it.
+                state.insert(name, size);
+                break;
+            }
+
+            let path = self.chunk_path(&name);
+            trace!("evicting chunk {:?}", path);
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("failed to evict chunk {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+impl ChunkStore for DiskChunkStore {
+    fn get(&self, chunk_hash: &str) -> Result<Vec<u8>> {
+        if chunk_hash.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let name = Self::file_name(chunk_hash);
+        if !self.lock().entries.contains_key(&name) {
+            return Err(SyncError::GetFromCacheError);
+        }
+
+        match fs::read(self.chunk_path(&name)) {
+            Ok(content) => {
+                self.touch(&name);
+                Ok(content)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Removed behind our back; forget about it.
+                let mut state = self.lock();
+                if let Some((tick, size)) = state.entries.remove(&name) {
+                    state.order.remove(&tick);
+                    state.total_bytes -= size;
+                }
+                Err(SyncError::GetFromCacheError)
+            }
+            Err(e) => Err(SyncError::from_io_error(self.chunk_path(&name), e)),
+        }
+    }
+
+    fn set(&mut self, chunk_hash: &str, content: Vec<u8>) -> Result<()> {
+        if chunk_hash.is_empty() {
+            return Ok(());
+        }
+
+        let name = Self::file_name(chunk_hash);
+        if self.lock().entries.contains_key(&name) {
+            // Content addressed, so whatever we have is already right.
+            self.touch(&name);
+            return Ok(());
+        }
+
+        let path = self.chunk_path(&name);
+        write_atomically(&path, &content)?;
+
+        self.lock().insert(name.clone(), content.len() as u64);
+        self.evict(Some(&name));
+
+        Ok(())
+    }
+
+    fn contains(&self, chunk_hash: &str) -> bool {
+        self.lock()
+            .entries
+            .contains_key(&Self::file_name(chunk_hash))
+    }
+
+    fn chunk_size(&self, chunk_hash: &str) -> Result<u64> {
+        if chunk_hash.is_empty() {
+            return Ok(0);
+        }
+
+        match self.lock().entries.get(&Self::file_name(chunk_hash)) {
+            Some((_, size)) => Ok(*size),
+            None => Err(SyncError::GetFromCacheError),
+        }
+    }
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| SyncError::from_io_error(parent, e))?;
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(TMP_SUFFIX);
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = fs::File::create(&tmp).map_err(|e| SyncError::from_io_error(&tmp, e))?;
+    file.write_all(content)
+        .map_err(|e| SyncError::from_io_error(&tmp, e))?;
+    drop(file);
+
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        SyncError::from_io_error(path, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_inmemory_cache_set_and_get() {
+        let mut cache = InMemoryCache::new(100, 1000);
+
+        let hash = "testhash123";
+        let data = vec![1, 2, 3, 4, 5];
+
+        cache.set(hash, data.clone()).unwrap();
+        let retrieved = cache.get(hash).unwrap();
+
+        assert_eq!(data, retrieved);
+    }
+
+    #[test]
+    fn test_inmemory_cache_get_nonexistent() {
+        let cache = InMemoryCache::new(100, 1000);
+
+        let result = cache.get("nonexistent");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_inmemory_cache_contains() {
+        let mut cache = InMemoryCache::new(100, 1000);
+
+        let hash = "testhash456";
+        let data = vec![1, 2, 3];
+
+        assert!(!cache.contains(hash));
+        cache.set(hash, data).unwrap();
+        assert!(cache.contains(hash));
+    }
+
+    #[test]
+    fn test_inmemory_cache_empty_hash() {
+        let cache = InMemoryCache::new(100, 1000);
+
+        // Empty hash should return empty vector
+        let result = cache.get("").unwrap();
+        assert_eq!(result, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_bytes_weighter() {
+        let weighter = BytesWeighter;
+
+        let key = "test".to_string();
+        let small_val = vec![1, 2, 3];
+        let large_val = vec![0u8; 1000];
+
+        assert_eq!(weighter.weight(&key, &small_val), 3);
+        assert_eq!(weighter.weight(&key, &large_val), 1000);
+    }
+
+    #[test]
+    fn test_bytes_weighter_empty_vec() {
+        let weighter = BytesWeighter;
+
+        let key = "test".to_string();
+        let empty_val = vec![];
+
+        // Should clamp to minimum of 1
+        assert_eq!(weighter.weight(&key, &empty_val), 1);
+    }
+
+    #[test]
+    fn disk_store_set_and_get_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let mut store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+
+        store.set("abcdef0123", b"eggs\n".to_vec()).unwrap();
+
+        assert!(store.contains("abcdef0123"));
+        assert_eq!(store.get("abcdef0123").unwrap(), b"eggs\n");
+        assert!(temp.path().join("ab").join("abcdef0123").exists());
+    }
+
+    #[test]
+    fn disk_store_get_missing_is_cache_error() {
+        let temp = TempDir::new().unwrap();
+        let store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+
+        assert!(!store.contains("0000000000"));
+        assert!(matches!(
+            store.get("0000000000"),
+            Err(SyncError::GetFromCacheError)
+        ));
+        assert_eq!(store.get("").unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn disk_store_knows_chunk_sizes_without_reading_them() {
+        let temp = TempDir::new().unwrap();
+        let mut store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+        store.set("abcdef0123", b"eggs\n".to_vec()).unwrap();
+        fs::remove_file(temp.path().join("ab").join("abcdef0123")).unwrap();
+
+        assert_eq!(store.chunk_size("abcdef0123").unwrap(), 5);
+        assert_eq!(store.chunk_size("").unwrap(), 0);
+        assert!(matches!(
+            store.chunk_size("0000000000"),
+            Err(SyncError::GetFromCacheError)
+        ));
+    }
+
+    #[test]
+    fn disk_store_survives_reopen() {
+        // The whole point of the disk store: chunks outlive the process, so
+        // a restart doesn't force rehashing or re-downloading.
+        let temp = TempDir::new().unwrap();
+        {
+            let mut store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+            store.set("abcdef0123", b"eggs\n".to_vec()).unwrap();
+        }
+
+        let store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+        assert!(store.contains("abcdef0123"));
+        assert_eq!(store.get("abcdef0123").unwrap(), b"eggs\n");
+        assert_eq!(store.size(), 5);
+    }
+
+    #[test]
+    fn disk_store_evicts_least_recently_used_over_budget() {
+        let temp = TempDir::new().unwrap();
+        let mut store = DiskChunkStore::open(temp.path(), 10).unwrap();
+
+        store.set("aa00000001", vec![1; 4]).unwrap();
+        store.set("bb00000002", vec![2; 4]).unwrap();
+        // Touch the first one so the second becomes the oldest.
+        store.get("aa00000001").unwrap();
+        store.set("cc00000003", vec![3; 4]).unwrap();
+
+        assert!(store.contains("aa00000001"));
+        assert!(!store.contains("bb00000002"), "LRU chunk must be evicted");
+        assert!(store.contains("cc00000003"));
+        assert!(!temp.path().join("bb").join("bb00000002").exists());
+        assert_eq!(store.size(), 8);
+    }
+
+    #[test]
+    fn disk_store_remembers_recency_across_reopen() {
+        let temp = TempDir::new().unwrap();
+        {
+            let mut store = DiskChunkStore::open(temp.path(), 10).unwrap();
+            store.set("aa00000001", vec![1; 4]).unwrap();
+            store.set("bb00000002", vec![2; 4]).unwrap();
+
+            let an_hour_ago = SystemTime::now() - std::time::Duration::from_secs(3600);
+            for path in [
+                temp.path().join("aa").join("aa00000001"),
+                temp.path().join("bb").join("bb00000002"),
+            ] {
+                let file = fs::File::options().write(true).open(path).unwrap();
+                file.set_modified(an_hour_ago).unwrap();
+            }
+
+            store.get("aa00000001").unwrap();
+        }
+
+        // Only room for one now: the one used last stays.
+        let store = DiskChunkStore::open(temp.path(), 4).unwrap();
+        assert!(store.contains("aa00000001"));
+        assert!(!store.contains("bb00000002"));
+    }
+
+    #[test]
+    fn disk_store_keeps_chunk_larger_than_budget() {
+        let temp = TempDir::new().unwrap();
+        let mut store = DiskChunkStore::open(temp.path(), 10).unwrap();
+
+        store.set("aa00000001", vec![1; 4]).unwrap();
+        store.set("bb00000002", vec![2; 32]).unwrap();
+
+        assert!(!store.contains("aa00000001"));
+        assert_eq!(store.get("bb00000002").unwrap().len(), 32);
+    }
+
+    #[test]
+    fn disk_store_handles_prefixed_ids() {
+        let temp = TempDir::new().unwrap();
+        let mut store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+
+        store.set("b3:abcdef", b"x".to_vec()).unwrap();
+
+        assert!(store.contains("b3:abcdef"));
+        assert!(temp.path().join("ab").join("b3_abcdef").exists());
+    }
+
+    #[test]
+    fn disk_store_open_removes_interrupted_writes() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("ab")).unwrap();
+        fs::write(temp.path().join("ab").join("abcdef0123.tmp"), b"half").unwrap();
+
+        let store = DiskChunkStore::open(temp.path(), 1_000).unwrap();
+
+        assert_eq!(store.size(), 0);
+        assert!(!temp.path().join("ab").join("abcdef0123.tmp").exists());
+    }
+}
@@ -1,48 +1,246 @@
-use log::trace;
-use quick_cache::{sync::Cache, Weighter};
-use sha2::{Digest, Sha256};
+use fastcdc::v2020::{self as cdc, StreamCDC};
+use log::{debug, trace};
 use std::path::{Path, PathBuf};
 use tokio::fs::{self, create_dir_all, File};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use walkdir::WalkDir;
 
+use crate::chunk_id;
+use crate::crypto::Cipher;
 use crate::errors::SyncError;
+use crate::file_format::FileFormatRegistry;
+use crate::models::FileFormat;
+use crate::sync_path;
+
+pub use crate::chunk_store::{BytesWeighter, ChunkStore, InMemoryCache};
 
 const BINARY_CHUNK_SIZE: usize = 1_024 * 1_024; // 1 MB
-const BINARY_HASH_SIZE: usize = 32;
-const TEXT_HASH_SIZE: usize = 10;
+const TEMP_FILE_SUFFIX: &str = ".cooksync-tmp";
+// Upload batches are cut after ~3 MB, the server accepts 5 MiB per request,
+// so a single chunk must stay well below the difference.
+const MAX_CONTENT_DEFINED_CHUNK_SIZE: u32 = 2 * 1_024 * 1_024; // 2 MB
+
+// Chunks read ahead of hashing when chunking by content.
+const CDC_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// How a file is split into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunking {
+    /// One chunk per line, used for text.
+    Lines,
+    /// Fixed 1 MB blocks, the format older clients produce for binaries.
+    FixedSize,
+    /// FastCDC cut points, so an edit only changes the chunks around it
+    /// instead of shifting every block after it.
+    ContentDefined {
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+    },
+}
+
+impl Chunking {
+    /// Content-defined chunking with sizes suited to photos.
+    pub const CONTENT_DEFINED_DEFAULT: Chunking = Chunking::ContentDefined {
+        min_size: 64 * 1_024,
+        avg_size: 256 * 1_024,
+        max_size: 1_024 * 1_024,
+    };
+
+    /// Validated constructor for the content-defined mode.
+    pub fn content_defined(min_size: u32, avg_size: u32, max_size: u32) -> Result<Chunking> {
+        if !(cdc::MINIMUM_MIN..=cdc::MINIMUM_MAX).contains(&min_size) {
+            return Err(SyncError::InvalidChunkingConfig(format!(
+                "min size {} is out of range {}..={}",
+                min_size,
+                cdc::MINIMUM_MIN,
+                cdc::MINIMUM_MAX
+            )));
+        }
+        if !(cdc::AVERAGE_MIN..=cdc::AVERAGE_MAX).contains(&avg_size) {
+            return Err(SyncError::InvalidChunkingConfig(format!(
+                "avg size {} is out of range {}..={}",
+                avg_size,
+                cdc::AVERAGE_MIN,
+                cdc::AVERAGE_MAX
+            )));
+        }
+        if !(cdc::MAXIMUM_MIN..=MAX_CONTENT_DEFINED_CHUNK_SIZE).contains(&max_size) {
+            return Err(SyncError::InvalidChunkingConfig(format!(
+                "max size {} is out of range {}..={}",
+                max_size,
+                cdc::MAXIMUM_MIN,
+                MAX_CONTENT_DEFINED_CHUNK_SIZE
+            )));
+        }
+        if min_size > avg_size || avg_size > max_size {
+            return Err(SyncError::InvalidChunkingConfig(format!(
+                "sizes must be ordered min <= avg <= max, got {}/{}/{}",
+                min_size, avg_size, max_size
+            )));
+        }
+
+        Ok(Chunking::ContentDefined {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+
+    /// Largest chunk it cuts, `None` for lines, which can be any length.
+    pub fn max_chunk_size(&self) -> Option<usize> {
+        match self {
+            Chunking::Lines => None,
+            Chunking::FixedSize => Some(BINARY_CHUNK_SIZE),
+            Chunking::ContentDefined { max_size, .. } => Some(*max_size as usize),
+        }
+    }
+}
 
 pub struct Chunker {
-    cache: InMemoryCache,
+    cache: Box<dyn ChunkStore>,
     base_path: PathBuf,
+    formats: FileFormatRegistry,
+    cipher: Option<Cipher>,
+    case_insensitive: Option<bool>,
+    device_name: Option<String>,
 }
 
 type Result<T, E = SyncError> = std::result::Result<T, E>;
 
 impl Chunker {
-    pub fn new(cache: InMemoryCache, base_path: PathBuf) -> Chunker {
-        Chunker { cache, base_path }
+    pub fn new(cache: impl ChunkStore + 'static, base_path: PathBuf) -> Chunker {
+        Chunker {
+            cache: Box::new(cache),
+            base_path,
+            formats: FileFormatRegistry::default(),
+            cipher: None,
+            case_insensitive: None,
+            device_name: None,
+        }
+    }
+
+    pub fn set_formats(&mut self, formats: FileFormatRegistry) {
+        self.formats = formats;
+    }
+
+    pub fn formats(&self) -> &FileFormatRegistry {
+        &self.formats
+    }
+
+    /// Turns on end-to-end encryption, new chunks get keyed IDs.
+    pub fn set_cipher(&mut self, cipher: Option<Cipher>) {
+        self.cipher = cipher;
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    /// Names this device in conflicted copies of files it edited.
+    pub fn set_device_name(&mut self, device_name: Option<String>) {
+        self.device_name = device_name;
+    }
+
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Whether the storage dir is on a case-insensitive filesystem, as APFS
+    /// and NTFS are by default. Probed with a temp file the first time.
+    pub fn is_case_insensitive(&mut self) -> bool {
+        if let Some(case_insensitive) = self.case_insensitive {
+            return case_insensitive;
+        }
+
+        match probe_case_insensitive(&self.base_path) {
+            Ok(case_insensitive) => {
+                self.case_insensitive = Some(case_insensitive);
+                case_insensitive
+            }
+            Err(e) => {
+                debug!(
+                    "can't probe case sensitivity of {:?}: {}",
+                    self.base_path, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Skips probing the filesystem.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = Some(case_insensitive);
     }
 
     fn full_path(&self, path: &str) -> PathBuf {
-        let mut base = self.base_path.clone();
-        base.push(path);
-        base
+        sync_path::to_local(&self.base_path, path)
     }
 
     pub async fn hashify(&mut self, path: &str) -> Result<Vec<String>> {
-        let p = Path::new(path);
+        match self.formats.detect(&self.full_path(path)) {
+            Some(format) => self.hashify_as(path, format).await,
+            None => Err(SyncError::UnlistedFileFormat(path.to_string())),
+        }
+    }
 
-        // TODO probably there's a better way to check if file is binary
-        if is_text(p) {
-            self.hashify_text(path).await
-        } else if is_binary(p) {
-            self.hashify_binary(path).await
-        } else {
-            Err(SyncError::UnlistedFileFormat(path.to_string()))
+    /// Like `hashify`, for a file whose format is already known, e.g. from
+    /// its registry record.
+    pub async fn hashify_as(&mut self, path: &str, format: FileFormat) -> Result<Vec<String>> {
+        match self.formats.chunking(format) {
+            Chunking::Lines => self.hashify_text(path).await,
+            Chunking::FixedSize => self.hashify_binary_fixed(path).await,
+            Chunking::ContentDefined {
+                min_size,
+                avg_size,
+                max_size,
+            } => {
+                self.hashify_binary_content_defined(path, min_size, avg_size, max_size)
+                    .await
+            }
+        }
+    }
+
+    async fn hashify_binary_content_defined(
+        &mut self,
+        path: &str,
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+    ) -> Result<Vec<String>> {
+        // Streamed, so only a few chunks are held whatever the file size.
+        // The chunking reads the file blocking, on a thread of its own.
+        let full_path = self.full_path(path);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(CDC_CHUNKS_IN_FLIGHT);
+        let reader = tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let file = std::fs::File::open(full_path)?;
+
+            for chunk in StreamCDC::new(file, min_size, avg_size, max_size) {
+                // Hashing stopped on an error of its own.
+                if tx.blocking_send(chunk?.data).is_err() {
+                    break;
+                }
+            }
+
+            Ok(())
+        });
+        let mut hashes = Vec::new();
+
+        while let Some(data) = rx.recv().await {
+            let hash = self.chunk_id(&data);
+            self.save_chunk(&hash, data)?;
+            hashes.push(hash);
         }
+
+        reader
+            .await
+            .map_err(join_error)?
+            .map_err(|e| SyncError::from_io_error(path, e))?;
+
+        Ok(hashes)
     }
 
-    async fn hashify_binary(&mut self, path: &str) -> Result<Vec<String>> {
+    async fn hashify_binary_fixed(&mut self, path: &str) -> Result<Vec<String>> {
         let file = File::open(self.full_path(path))
             .await
             .map_err(|e| SyncError::from_io_error(path, e))?;
@@ -60,7 +258,7 @@ impl Chunker {
             }
 
             let data = &buffer[..bytes_read].to_vec();
-            let hash = self.hash(data, BINARY_HASH_SIZE);
+            let hash = self.chunk_id(data);
             self.save_chunk(&hash, data.to_vec())?;
             hashes.push(hash);
         }
@@ -83,7 +281,7 @@ impl Chunker {
             > 0
         {
             let data: Vec<u8> = buffer.clone();
-            let hash = self.hash(&data, TEXT_HASH_SIZE);
+            let hash = self.chunk_id(&data);
             self.save_chunk(&hash, data)?;
             hashes.push(hash);
 
@@ -94,15 +292,26 @@ impl Chunker {
         Ok(hashes)
     }
 
-    pub fn hash(&self, data: &Vec<u8>, size: usize) -> String {
-        let mut hasher = Sha256::new();
-
-        hasher.update(data);
+    /// ID for a chunk with `data` as content.
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        match &self.cipher {
+            Some(cipher) => cipher.chunk_id(data),
+            None => chunk_id::blake3(data),
+        }
+    }
 
-        let result = hasher.finalize();
-        let hex_string = format!("{:x}", result);
+    /// Whether `data` is the content `id` refers to, for any kind of ID this
+    /// chunker could have produced.
+    pub fn verify_chunk(&self, id: &str, data: &[u8]) -> bool {
+        match &self.cipher {
+            Some(cipher) if chunk_id::is_keyed(id) => cipher.chunk_id(data) == id,
+            _ => chunk_id::verify(id, data),
+        }
+    }
 
-        hex_string[0..size].to_string()
+    /// Legacy truncated SHA-256 ID, see `chunk_id::legacy_sha256`.
+    pub fn hash(&self, data: &[u8], size: usize) -> String {
+        chunk_id::legacy_sha256(data, size)
     }
 
     pub fn exists(&mut self, path: &str) -> bool {
@@ -111,7 +320,11 @@ impl Chunker {
         full_path.exists()
     }
 
-    // TODO can be a problem as it expects cache to contain all chunks
+    /// Materializes `path` from chunks in the store.
+    ///
+    /// Content goes into a temp file next to the target which is fsynced and
+    /// then renamed over it, so a crash or a missing chunk never leaves a
+    /// half-written file behind for the indexer to pick up as an edit.
     pub async fn save(&mut self, path: &str, hashes: Vec<&str>) -> Result<()> {
         trace!("saving {:?}", path);
         let full_path = self.full_path(path);
@@ -122,7 +335,23 @@ impl Chunker {
                 .map_err(|e| SyncError::from_io_error(path, e))?;
         }
 
-        let file = File::create(full_path)
+        let tmp_path = temp_path_for(&full_path);
+
+        if let Err(e) = self.write_chunks(&tmp_path, path, hashes).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+
+        if let Err(e) = fs::rename(&tmp_path, &full_path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(SyncError::from_io_error(path, e));
+        }
+
+        Ok(())
+    }
+
+    async fn write_chunks(&self, tmp_path: &Path, path: &str, hashes: Vec<&str>) -> Result<()> {
+        let file = File::create(tmp_path)
             .await
             .map_err(|e| SyncError::from_io_error(path, e))?;
         let mut writer = BufWriter::new(file);
@@ -140,6 +369,11 @@ impl Chunker {
             .flush()
             .await
             .map_err(|e| SyncError::from_io_error(path, e))?;
+        writer
+            .get_ref()
+            .sync_all()
+            .await
+            .map_err(|e| SyncError::from_io_error(path, e))?;
 
         Ok(())
     }
@@ -152,6 +386,34 @@ impl Chunker {
             .await
             .map_err(|e| SyncError::from_io_error(path, e))?;
 
+        self.remove_empty_parents(&full_path).await;
+
+        Ok(())
+    }
+
+    /// Moves the file at `from` to `to`, keeping its content and mtime.
+    /// Directories left empty behind it are removed like in `delete`.
+    pub async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        trace!("renaming {:?} to {:?}", from, to);
+        let from_path = self.full_path(from);
+        let to_path = self.full_path(to);
+
+        if let Some(parent) = to_path.parent() {
+            create_dir_all(parent)
+                .await
+                .map_err(|e| SyncError::from_io_error(to, e))?;
+        }
+
+        fs::rename(&from_path, &to_path)
+            .await
+            .map_err(|e| SyncError::from_io_error(from, e))?;
+
+        self.remove_empty_parents(&from_path).await;
+
+        Ok(())
+    }
+
+    async fn remove_empty_parents(&self, full_path: &Path) {
         // Walk parents upward and remove empty directories. `remove_dir`
         // only succeeds on empty directories, which gives us strict-empty
         // semantics without manually counting entries. Stop at the storage
@@ -160,12 +422,13 @@ impl Chunker {
         // condition, not a failure to propagate).
         //
         // SAFETY: `dir == self.base_path` is a lexical comparison. It is
-        // sound here because `full_path` is built by `base_path.clone()`
-        // + `push(path)` with no canonicalization on either side, and the
-        // server-provided `path` is trusted to not contain `..` segments
-        // (the indexer produces forward-slash relative paths via WalkDir,
-        // which never emits parent components). If that contract ever
-        // weakens, harden this guard with `starts_with` or a depth check.
+        // sound here because `full_path` is built by `sync_path::to_local`
+        // joining components onto `base_path` with no canonicalization on
+        // either side, and server-provided paths with `..` segments are
+        // refused by `sync_path::from_remote` (the indexer produces
+        // forward-slash relative paths via WalkDir, which never emits parent
+        // components). If that contract ever weakens, harden this guard with
+        // `starts_with` or a depth check.
         let mut parent = full_path.parent();
         while let Some(dir) = parent {
             if dir == self.base_path {
@@ -177,14 +440,28 @@ impl Chunker {
             }
             parent = dir.parent();
         }
+    }
 
-        Ok(())
+    /// Checksum of the content these chunks make up, equal to
+    /// `file_checksum` of the file they're saved to. Doesn't touch the file.
+    pub fn checksum(&self, hashes: &[&str]) -> Result<String> {
+        let mut hasher = blake3::Hasher::new();
+
+        for hash in hashes {
+            hasher.update(&self.cache.get(hash)?);
+        }
+
+        Ok(hasher.finalize().to_hex().to_string())
     }
 
     pub fn read_chunk(&self, chunk_hash: &str) -> Result<Vec<u8>> {
         self.cache.get(chunk_hash)
     }
 
+    pub fn chunk_size(&self, chunk_hash: &str) -> Result<u64> {
+        self.cache.chunk_size(chunk_hash)
+    }
+
     pub fn save_chunk(&mut self, chunk_hash: &str, content: Vec<u8>) -> Result<()> {
         self.cache.set(chunk_hash, content)
     }
@@ -198,50 +475,76 @@ impl Chunker {
     }
 }
 
-#[derive(Clone)]
-pub struct BytesWeighter;
+/// BLAKE3 of a file's content, as stored in the registry.
+pub fn file_checksum(path: &Path) -> Result<String> {
+    let file = std::fs::File::open(path).map_err(|e| SyncError::from_io_error(path, e))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(file)
+        .map_err(|e| SyncError::from_io_error(path, e))?;
 
-impl Weighter<String, Vec<u8>> for BytesWeighter {
-    fn weight(&self, _key: &String, val: &Vec<u8>) -> u64 {
-        // Be cautions out about zero weights!
-        val.len().clamp(1, u64::MAX as usize) as u64
-    }
+    Ok(hasher.finalize().to_hex().to_string())
 }
 
-pub struct InMemoryCache {
-    cache: Cache<String, Vec<u8>, BytesWeighter>,
+/// `file_checksum` on a blocking thread, for async code.
+pub async fn file_checksum_async(path: PathBuf) -> Result<String> {
+    tokio::task::spawn_blocking(move || file_checksum(&path))
+        .await
+        .map_err(join_error)?
 }
 
-impl InMemoryCache {
-    pub fn new(total_keys: usize, total_weight: u64) -> InMemoryCache {
-        InMemoryCache {
-            cache: Cache::with_weighter(total_keys, total_weight, BytesWeighter),
-        }
+/// A task run with `spawn_blocking` panicked or was cancelled.
+pub(crate) fn join_error(e: tokio::task::JoinError) -> SyncError {
+    SyncError::Unknown(format!("blocking task failed: {}", e))
+}
+
+/// Name of the temp file `save` writes before renaming it to `target`,
+/// e.g. `dir/.recipe.cook.cooksync-tmp`.
+fn temp_path_for(target: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    if let Some(file_name) = target.file_name() {
+        name.push(file_name);
     }
+    name.push(TEMP_FILE_SUFFIX);
 
-    fn get(&self, chunk_hash: &str) -> Result<Vec<u8>> {
-        if chunk_hash.is_empty() {
-            return Ok(vec![]);
-        }
+    target.with_file_name(name)
+}
 
-        match self.cache.get(chunk_hash) {
-            Some(content) => Ok(content.clone()),
-            None => Err(SyncError::GetFromCacheError),
-        }
-    }
+fn probe_case_insensitive(base_path: &Path) -> std::io::Result<bool> {
+    let probe = temp_path_for(&base_path.join("case-probe"));
+    std::fs::write(&probe, b"")?;
 
-    fn set(&mut self, chunk_hash: &str, content: Vec<u8>) -> Result<()> {
-        // trace!("setting hash {:?} data  {:?}", chunk_hash, content.len());
-        self.cache.insert(chunk_hash.to_string(), content);
-        Ok(())
-    }
+    let case_insensitive = probe
+        .with_file_name(format!(".CASE-PROBE{}", TEMP_FILE_SUFFIX.to_uppercase()))
+        .exists();
+    std::fs::remove_file(&probe)?;
 
-    fn contains(&self, chunk_hash: &str) -> bool {
-        match self.cache.get(chunk_hash) {
-            Some(_content) => true,
-            None => false,
+    Ok(case_insensitive)
+}
+
+/// Whether `p` is a temp file left by `Chunker::save`.
+pub fn is_temp_file(p: &Path) -> bool {
+    p.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.') && n.ends_with(TEMP_FILE_SUFFIX))
+}
+
+/// Removes temp files left under `base_path` by writes that were interrupted
+/// by a crash or a kill. Returns how many were removed.
+pub fn remove_temp_files(base_path: &Path) -> Result<usize> {
+    let mut removed = 0;
+
+    for entry in WalkDir::new(base_path).into_iter().filter_map(|e| e.ok()) {
+        let p = entry.path();
+
+        if entry.file_type().is_file() && is_temp_file(p) {
+            debug!("removing leftover temp file {:?}", p);
+            std::fs::remove_file(p).map_err(|e| SyncError::from_io_error(p, e))?;
+            removed += 1;
         }
     }
+
+    Ok(removed)
 }
 
 pub fn is_binary(p: &Path) -> bool {
@@ -261,6 +564,7 @@ pub fn is_text(p: &Path) -> bool {
         if file_name_str == ".shopping-list"
             || file_name_str == ".shopping-checked"
             || file_name_str == ".bookmarks"
+            || file_name_str == crate::ignore_rules::IGNORE_FILE_NAME
         {
             return true;
         }
@@ -395,48 +699,6 @@ mod tests {
         assert!(hash_long.starts_with(&hash_short));
     }
 
-    #[test]
-    fn test_inmemory_cache_set_and_get() {
-        let mut cache = InMemoryCache::new(100, 1000);
-
-        let hash = "testhash123";
-        let data = vec![1, 2, 3, 4, 5];
-
-        cache.set(hash, data.clone()).unwrap();
-        let retrieved = cache.get(hash).unwrap();
-
-        assert_eq!(data, retrieved);
-    }
-
-    #[test]
-    fn test_inmemory_cache_get_nonexistent() {
-        let cache = InMemoryCache::new(100, 1000);
-
-        let result = cache.get("nonexistent");
-        assert!(result.is_err());
-    }
-
-    #[test]
-    fn test_inmemory_cache_contains() {
-        let mut cache = InMemoryCache::new(100, 1000);
-
-        let hash = "testhash456";
-        let data = vec![1, 2, 3];
-
-        assert!(!cache.contains(hash));
-        cache.set(hash, data).unwrap();
-        assert!(cache.contains(hash));
-    }
-
-    #[test]
-    fn test_inmemory_cache_empty_hash() {
-        let cache = InMemoryCache::new(100, 1000);
-
-        // Empty hash should return empty vector
-        let result = cache.get("").unwrap();
-        assert_eq!(result, Vec::<u8>::new());
-    }
-
     #[test]
     fn test_chunker_check_chunk_empty_hash() {
         let cache = InMemoryCache::new(100, 1000);
@@ -486,6 +748,43 @@ mod tests {
         for hash in &hashes {
             assert!(chunker.check_chunk(hash));
         }
+
+        // IDs are full-length and tagged with the algorithm
+        assert_eq!(hashes[0], chunk_id::blake3(b"Line 1\n"));
+        assert!(hashes.iter().all(|h| chunk_id::is_valid(h)));
+    }
+
+    #[tokio::test]
+    async fn hashify_uses_keyed_ids_with_cipher() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut chunker = Chunker::new(InMemoryCache::new(100, 10_000), temp_dir.path().into());
+        let cipher = Cipher::new([7; 32]);
+        chunker.set_cipher(Some(cipher.clone()));
+
+        std::fs::write(temp_dir.path().join("a.cook"), "eggs\n").unwrap();
+
+        let hashes = chunker.hashify("a.cook").await.unwrap();
+        assert_eq!(hashes, vec![cipher.chunk_id(b"eggs\n")]);
+        // The local store keeps plaintext
+        assert_eq!(chunker.read_chunk(&hashes[0]).unwrap(), b"eggs\n");
+    }
+
+    #[test]
+    fn verify_chunk_checks_every_kind_of_id() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut chunker = Chunker::new(InMemoryCache::new(100, 10_000), temp_dir.path().into());
+        let cipher = Cipher::new([7; 32]);
+        let keyed = cipher.chunk_id(b"eggs\n");
+
+        assert!(chunker.verify_chunk(&chunk_id::blake3(b"eggs\n"), b"eggs\n"));
+        assert!(chunker.verify_chunk(&chunker.hash(b"eggs\n", 10), b"eggs\n"));
+        assert!(!chunker.verify_chunk(&chunk_id::blake3(b"eggs\n"), b"flour\n"));
+        // Keyed IDs need the key
+        assert!(!chunker.verify_chunk(&keyed, b"eggs\n"));
+
+        chunker.set_cipher(Some(cipher));
+        assert!(chunker.verify_chunk(&keyed, b"eggs\n"));
+        assert!(!chunker.verify_chunk(&keyed, b"flour\n"));
     }
 
     #[tokio::test]
@@ -539,26 +838,117 @@ mod tests {
     }
 
     #[test]
-    fn test_bytes_weighter() {
-        let weighter = BytesWeighter;
+    fn content_defined_rejects_out_of_range_sizes() {
+        assert!(Chunking::content_defined(16, 256, 1_024).is_err());
+        assert!(Chunking::content_defined(64, 128, 1_024).is_err());
+        assert!(Chunking::content_defined(64, 256, 16 * 1_024 * 1_024).is_err());
+        assert!(Chunking::content_defined(4_096, 1_024, 8_192).is_err());
+        assert_eq!(
+            Chunking::content_defined(64, 256, 1_024).unwrap(),
+            Chunking::ContentDefined {
+                min_size: 64,
+                avg_size: 256,
+                max_size: 1_024
+            }
+        );
+    }
+
+    fn pseudo_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn content_defined_chunks_survive_insertion_at_start() {
+        let temp = TempDir::new().unwrap();
+        let cache = InMemoryCache::new(10_000, 100_000_000);
+        let mut chunker = Chunker::new(cache, temp.path().to_path_buf());
+        let mut formats = FileFormatRegistry::default();
+        formats.set_chunking(
+            FileFormat::Binary,
+            Chunking::content_defined(1_024, 4_096, 16_384).unwrap(),
+        );
+        chunker.set_formats(formats);
+
+        let original = pseudo_random_bytes(256 * 1_024, 7);
+        let mut edited = b"a few inserted bytes".to_vec();
+        edited.extend_from_slice(&original);
+
+        tokio::fs::write(temp.path().join("a.jpg"), &original)
+            .await
+            .unwrap();
+        tokio::fs::write(temp.path().join("b.jpg"), &edited)
+            .await
+            .unwrap();
+
+        let before = chunker.hashify("a.jpg").await.unwrap();
+        let after = chunker.hashify("b.jpg").await.unwrap();
 
-        let key = "test".to_string();
-        let small_val = vec![1, 2, 3];
-        let large_val = vec![0u8; 1000];
+        let shared = after.iter().filter(|h| before.contains(h)).count();
+        assert!(
+            shared >= before.len() - 2,
+            "only chunks around the edit should change: {shared} of {} shared",
+            before.len()
+        );
 
-        assert_eq!(weighter.weight(&key, &small_val), 3);
-        assert_eq!(weighter.weight(&key, &large_val), 1000);
+        // Chunks still reassemble into the exact file.
+        chunker
+            .save("c.jpg", after.iter().map(|h| h.as_str()).collect())
+            .await
+            .unwrap();
+        assert_eq!(
+            tokio::fs::read(temp.path().join("c.jpg")).await.unwrap(),
+            edited
+        );
     }
 
-    #[test]
-    fn test_bytes_weighter_empty_vec() {
-        let weighter = BytesWeighter;
+    #[tokio::test]
+    async fn fixed_size_chunking_is_the_default() {
+        let temp = TempDir::new().unwrap();
+        let cache = InMemoryCache::new(100, 10_000_000);
+        let mut chunker = Chunker::new(cache, temp.path().to_path_buf());
+
+        tokio::fs::write(temp.path().join("a.png"), vec![7u8; BINARY_CHUNK_SIZE + 10])
+            .await
+            .unwrap();
+
+        let hashes = chunker.hashify("a.png").await.unwrap();
+        assert_eq!(hashes.len(), 2, "1 MB block plus the remainder");
+    }
+
+    #[tokio::test]
+    async fn hashify_uses_registered_formats() {
+        let temp = TempDir::new().unwrap();
+        let cache = InMemoryCache::new(100, 10_000);
+        let mut chunker = Chunker::new(cache, temp.path().to_path_buf());
+        tokio::fs::write(temp.path().join("notes.txt"), b"a\nb\n")
+            .await
+            .unwrap();
 
-        let key = "test".to_string();
-        let empty_val = vec![];
+        assert!(matches!(
+            chunker.hashify("notes.txt").await,
+            Err(SyncError::UnlistedFileFormat(_))
+        ));
 
-        // Should clamp to minimum of 1
-        assert_eq!(weighter.weight(&key, &empty_val), 1);
+        let mut formats = FileFormatRegistry::default();
+        formats.add_extension("txt", FileFormat::Text);
+        chunker.set_formats(formats);
+
+        assert_eq!(chunker.hashify("notes.txt").await.unwrap().len(), 2);
+        // A known format bypasses detection altogether.
+        assert_eq!(
+            chunker
+                .hashify_as("notes.txt", FileFormat::Binary)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
     }
 
     #[tokio::test]
@@ -598,6 +988,94 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn save_with_missing_chunk_keeps_existing_file_intact() {
+        let temp = TempDir::new().unwrap();
+        let cache = InMemoryCache::new(100, 10_000);
+        let mut chunker = Chunker::new(cache, temp.path().to_path_buf());
+
+        tokio::fs::write(temp.path().join("recipe.cook"), b"eggs\n")
+            .await
+            .unwrap();
+        let known = chunker.hash(b"flour\n", 10);
+        chunker.save_chunk(&known, b"flour\n".to_vec()).unwrap();
+
+        let err = chunker
+            .save("recipe.cook", vec![&known, "deadbeef00"])
+            .await
+            .expect_err("second chunk is missing");
+        assert!(matches!(err, SyncError::GetFromCacheError));
+
+        assert_eq!(
+            tokio::fs::read(temp.path().join("recipe.cook"))
+                .await
+                .unwrap(),
+            b"eggs\n",
+            "the old content must survive a failed save"
+        );
+        assert!(
+            !temp.path().join(".recipe.cook.cooksync-tmp").exists(),
+            "failed save must not leave its temp file behind"
+        );
+    }
+
+    #[test]
+    fn is_temp_file_matches_only_save_temp_files() {
+        assert!(is_temp_file(Path::new("a/.recipe.cook.cooksync-tmp")));
+        assert!(is_temp_file(&temp_path_for(Path::new("a/b.jpg"))));
+        assert!(!is_temp_file(Path::new("a/recipe.cook")));
+        assert!(!is_temp_file(Path::new("a/.shopping-list")));
+    }
+
+    #[tokio::test]
+    async fn checksum_of_chunks_matches_file_checksum() {
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("a.cook"), b"Eggs\nFlour\n").unwrap();
+        let mut chunker = Chunker::new(InMemoryCache::new(100, 1000), temp.path().to_path_buf());
+
+        let ids = chunker.hashify("a.cook").await.unwrap();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+
+        assert_eq!(
+            chunker.checksum(&ids).unwrap(),
+            file_checksum(&temp.path().join("a.cook")).unwrap()
+        );
+        assert_eq!(
+            chunker.checksum(&[""]).unwrap(),
+            blake3::hash(b"").to_hex().to_string()
+        );
+    }
+
+    #[test]
+    fn is_case_insensitive_matches_filesystem_and_leaves_nothing_behind() {
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("x.cook"), b"").unwrap();
+        let expected = temp.path().join("X.COOK").exists();
+        std::fs::remove_file(temp.path().join("x.cook")).unwrap();
+
+        let mut chunker = Chunker::new(InMemoryCache::new(10, 1000), temp.path().to_path_buf());
+        assert_eq!(chunker.is_case_insensitive(), expected);
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 0);
+
+        chunker.set_case_insensitive(!expected);
+        assert_eq!(chunker.is_case_insensitive(), !expected);
+    }
+
+    #[test]
+    fn remove_temp_files_cleans_nested_leftovers_only() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join("a")).unwrap();
+        std::fs::write(temp.path().join(".x.cook.cooksync-tmp"), b"half").unwrap();
+        std::fs::write(temp.path().join("a/.y.cook.cooksync-tmp"), b"half").unwrap();
+        std::fs::write(temp.path().join("a/y.cook"), b"whole").unwrap();
+
+        assert_eq!(remove_temp_files(temp.path()).unwrap(), 2);
+
+        assert!(!temp.path().join(".x.cook.cooksync-tmp").exists());
+        assert!(!temp.path().join("a/.y.cook.cooksync-tmp").exists());
+        assert!(temp.path().join("a/y.cook").exists());
+    }
+
     #[tokio::test]
     async fn delete_removes_file_from_storage_dir() {
         let temp = tempfile::TempDir::new().unwrap();
@@ -0,0 +1,49 @@
+use log::{debug, warn};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::connection::{get_connection, ConnectionPool};
+use crate::control::SyncControl;
+use crate::errors::SyncError;
+use crate::registry;
+
+const COMPACT_INTERVAL_SEC: Duration = Duration::from_secs(60 * 60);
+
+/// Compacts the registry once an hour, unless sync is paused. Failures are
+/// only logged, an uncompacted registry is slower but still correct.
+pub async fn run(
+    token: CancellationToken,
+    control: &SyncControl,
+    pool: &ConnectionPool,
+    namespace_id: i32,
+) -> Result<(), SyncError> {
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => {
+                debug!("Compactor shutting down");
+                break;
+            }
+            _ = tokio::time::sleep(COMPACT_INTERVAL_SEC) => {},
+        };
+
+        // The syncer already tells the listener it's paused.
+        if !control.wait_while_paused(&token, None).await {
+            debug!("Compactor shutting down");
+            break;
+        }
+
+        if let Err(e) = compact_once(pool, namespace_id) {
+            warn!("Registry compaction failed: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn compact_once(pool: &ConnectionPool, namespace_id: i32) -> Result<usize, SyncError> {
+    let conn = &mut get_connection(pool)?;
+    let removed = registry::compact(conn, namespace_id)?;
+    debug!("Compacted registry, removed {} rows", removed);
+
+    Ok(removed)
+}
@@ -1,7 +1,14 @@
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
-use crate::models::SyncStatus;
+use crate::bandwidth::NetworkPolicy;
+use crate::chunker::Chunking;
+use crate::control::SyncControl;
+use crate::crypto::Cipher;
+use crate::errors::SyncError;
+use crate::file_format::FileFormatRegistry;
+use crate::models::{FileFormat, SyncStatus};
+use crate::remote::DEFAULT_UPLOAD_CONCURRENCY;
 
 /// Trait for receiving sync status updates
 /// Implementations of this trait in foreign languages (Swift, etc.) will receive
@@ -17,6 +24,12 @@ pub trait SyncStatusListener: Send + Sync {
 pub struct SyncContext {
     cancellation_token: CancellationToken,
     status_listener: std::sync::Mutex<Option<Arc<dyn SyncStatusListener>>>,
+    formats: std::sync::Mutex<FileFormatRegistry>,
+    cipher: std::sync::Mutex<Option<Cipher>>,
+    device_name: std::sync::Mutex<Option<String>>,
+    upload_concurrency: std::sync::Mutex<usize>,
+    network: Arc<NetworkPolicy>,
+    control: Arc<SyncControl>,
 }
 
 #[cfg_attr(feature = "ffi", uniffi::export)]
@@ -27,6 +40,12 @@ impl SyncContext {
         Arc::new(Self {
             cancellation_token: CancellationToken::new(),
             status_listener: std::sync::Mutex::new(None),
+            formats: std::sync::Mutex::new(FileFormatRegistry::default()),
+            cipher: std::sync::Mutex::new(None),
+            device_name: std::sync::Mutex::new(None),
+            upload_concurrency: std::sync::Mutex::new(DEFAULT_UPLOAD_CONCURRENCY),
+            network: Arc::new(NetworkPolicy::default()),
+            control: Arc::new(SyncControl::default()),
         })
     }
 
@@ -40,6 +59,132 @@ impl SyncContext {
         *listener_lock = Some(listener);
     }
 
+    /// Syncs files with this extension (without the dot) as text.
+    /// Must be called before `run`, like all the format settings below.
+    pub fn add_text_extension(&self, extension: String) {
+        self.lock_formats()
+            .add_extension(&extension, FileFormat::Text);
+    }
+
+    /// Syncs files with this exact name as text, e.g. `.shopping-list`.
+    pub fn add_text_filename(&self, filename: String) {
+        self.lock_formats()
+            .add_filename(&filename, FileFormat::Text);
+    }
+
+    /// Syncs files with this extension (without the dot) as binary.
+    pub fn add_binary_extension(&self, extension: String) {
+        self.lock_formats()
+            .add_extension(&extension, FileFormat::Binary);
+    }
+
+    /// Syncs files with this exact name as binary.
+    pub fn add_binary_filename(&self, filename: String) {
+        self.lock_formats()
+            .add_filename(&filename, FileFormat::Binary);
+    }
+
+    /// Classifies files with unknown names by their content.
+    pub fn set_content_sniffing(&self, enabled: bool) {
+        self.lock_formats().set_content_sniffing(enabled);
+    }
+
+    /// Splits binary files at content-defined boundaries (sizes in bytes)
+    /// instead of fixed 1 MB blocks, so edits re-upload only changed regions.
+    pub fn set_content_defined_chunking(
+        &self,
+        min_size: u32,
+        avg_size: u32,
+        max_size: u32,
+    ) -> Result<(), SyncError> {
+        let chunking = Chunking::content_defined(min_size, avg_size, max_size)?;
+        self.lock_formats()
+            .set_chunking(FileFormat::Binary, chunking);
+        Ok(())
+    }
+
+    /// Turns on end-to-end encryption of file contents and paths. Every
+    /// device syncing the namespace needs the same passphrase and salt (e.g.
+    /// the account's email). Deriving the key is deliberately slow, so call
+    /// this off the main thread. Must be called before `run`. Uploads refuse
+    /// to start in a namespace that has files synced unencrypted.
+    pub fn set_encryption_passphrase(
+        &self,
+        passphrase: String,
+        salt: String,
+    ) -> Result<(), SyncError> {
+        let cipher = Cipher::from_passphrase(&passphrase, &salt)?;
+        *self.lock_cipher() = Some(cipher);
+        Ok(())
+    }
+
+    /// Turns end-to-end encryption off for the next `run`.
+    pub fn clear_encryption_passphrase(&self) {
+        *self.lock_cipher() = None;
+    }
+
+    pub fn is_encryption_enabled(&self) -> bool {
+        self.lock_cipher().is_some()
+    }
+
+    /// Names this device in conflicted copies, e.g. "Pixel" gives
+    /// `Pancakes (conflicted copy from Pixel).cook` when the recipe was
+    /// edited here and elsewhere at the same time. Must be called before `run`.
+    pub fn set_device_name(&self, name: String) {
+        *self.device_name.lock().unwrap_or_else(|e| e.into_inner()) = Some(name);
+    }
+
+    /// Uploads up to this many batches of chunks at the same time, 4 by
+    /// default. Lower it on metered or slow connections. Must be called
+    /// before `run`.
+    pub fn set_upload_concurrency(&self, limit: u32) {
+        *self.lock_upload_concurrency() = (limit as usize).max(1);
+    }
+
+    /// Caps uploads at this many bytes per second, 0 (the default) for no
+    /// limit. Unlike the settings above, it can be changed while syncing.
+    pub fn set_upload_limit(&self, bytes_per_sec: u64) {
+        self.network.upload().set_limit(bytes_per_sec);
+    }
+
+    /// Caps downloads at this many bytes per second, 0 (the default) for no
+    /// limit. Can be changed while syncing.
+    pub fn set_download_limit(&self, bytes_per_sec: u64) {
+        self.network.download().set_limit(bytes_per_sec);
+    }
+
+    /// Tells whether the device is on a metered network, e.g. cellular.
+    /// Photos and other binary files wait until it isn't, recipes keep
+    /// syncing. Call it whenever the network changes, also while syncing.
+    pub fn set_metered(&self, metered: bool) {
+        self.network.set_metered(metered);
+    }
+
+    pub fn is_metered(&self) -> bool {
+        self.network.is_metered()
+    }
+
+    /// Stops syncing once whatever is under way is done, without ending
+    /// `run`. The file watcher keeps going, so nothing is missed.
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    /// Goes on with a paused sync, right away.
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Looks for changes here and on the server right away, instead of
+    /// waiting for the next check. Does nothing while paused.
+    pub fn sync_now(&self) {
+        self.control.sync_now();
+    }
+
     /// Cancels the sync operation
     pub fn cancel(&self) {
         self.cancellation_token.cancel();
@@ -80,4 +225,56 @@ impl SyncContext {
             .unwrap_or_else(|e| e.into_inner());
         listener_lock.clone()
     }
+
+    /// Returns a copy of the configured file formats (internal use only)
+    pub fn formats(&self) -> FileFormatRegistry {
+        self.lock_formats().clone()
+    }
+
+    /// Returns the encryption keys if a passphrase is set (internal use only)
+    pub fn cipher(&self) -> Option<Cipher> {
+        self.lock_cipher().clone()
+    }
+
+    /// Returns the name set with `set_device_name` (internal use only)
+    pub fn device_name(&self) -> Option<String> {
+        // Handle poisoned mutex by recovering the guard
+        self.device_name
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Returns the limit set with `set_upload_concurrency` (internal use only)
+    pub fn upload_concurrency(&self) -> usize {
+        *self.lock_upload_concurrency()
+    }
+
+    /// Returns the limits and metering the sync follows (internal use only)
+    pub fn network(&self) -> Arc<NetworkPolicy> {
+        Arc::clone(&self.network)
+    }
+
+    /// Returns the pause and sync-now switches the loops follow (internal
+    /// use only)
+    pub fn control(&self) -> Arc<SyncControl> {
+        Arc::clone(&self.control)
+    }
+
+    fn lock_cipher(&self) -> std::sync::MutexGuard<'_, Option<Cipher>> {
+        // Handle poisoned mutex by recovering the guard
+        self.cipher.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_formats(&self) -> std::sync::MutexGuard<'_, FileFormatRegistry> {
+        // Handle poisoned mutex by recovering the guard
+        self.formats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_upload_concurrency(&self) -> std::sync::MutexGuard<'_, usize> {
+        // Handle poisoned mutex by recovering the guard
+        self.upload_concurrency
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
 }
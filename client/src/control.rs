@@ -0,0 +1,144 @@
+//! Pausing and waking up a running sync.
+//!
+//! The indexer and syncer loops check for a pause at the top of every
+//! round, so whatever is in flight finishes first. The file watcher and the
+//! connection pool stay as they are, resuming just lets the loops go on.
+
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{SyncStatus, SyncStatusListener};
+
+/// Pause and sync-now requests, shared by the `SyncContext` the app calls
+/// them on and the loops of the sync.
+#[derive(Debug)]
+pub struct SyncControl {
+    paused: watch::Sender<bool>,
+    /// Bumped on every sync-now request.
+    wake: watch::Sender<u64>,
+}
+
+impl Default for SyncControl {
+    fn default() -> Self {
+        SyncControl {
+            paused: watch::Sender::new(false),
+            wake: watch::Sender::new(0),
+        }
+    }
+}
+
+impl SyncControl {
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Resolves once the sync isn't paused, right away if it isn't.
+    pub async fn resumed(&self) {
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+
+    /// Resolves once the sync is paused, right away if it is.
+    pub async fn paused(&self) {
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = self.paused.subscribe().wait_for(|paused| *paused).await;
+    }
+
+    /// Wakes every loop waiting for its next round.
+    pub fn sync_now(&self) {
+        self.wake.send_modify(|n| *n = n.wrapping_add(1));
+    }
+
+    /// Sync-now requests for one loop, from now on.
+    pub fn wake_ups(&self) -> WakeUps {
+        WakeUps(self.wake.subscribe())
+    }
+
+    /// Waits while paused, telling `listener`. Returns whether to go on,
+    /// i.e. false when cancelled meanwhile.
+    pub async fn wait_while_paused(
+        &self,
+        token: &CancellationToken,
+        listener: Option<&dyn SyncStatusListener>,
+    ) -> bool {
+        if !self.is_paused() {
+            return true;
+        }
+
+        if let Some(cb) = listener {
+            cb.on_status_changed(SyncStatus::Paused);
+        }
+
+        tokio::select! {
+            _ = token.cancelled() => false,
+            _ = self.resumed() => true,
+        }
+    }
+}
+
+/// Sync-now requests as one loop sees them. A request made while the loop
+/// is busy wakes it as soon as it waits again.
+#[derive(Debug)]
+pub struct WakeUps(watch::Receiver<u64>);
+
+impl WakeUps {
+    /// Resolves on the next request not seen yet.
+    pub async fn next(&mut self) {
+        if self.0.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn requests_made_while_busy_are_not_lost() {
+        let control = SyncControl::default();
+        let mut wake_ups = control.wake_ups();
+
+        control.sync_now();
+        control.sync_now();
+
+        timeout(Duration::from_secs(1), wake_ups.next())
+            .await
+            .expect("woken up");
+        assert!(timeout(Duration::from_millis(20), wake_ups.next())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn wait_while_paused_returns_on_resume_or_cancel() {
+        let control = std::sync::Arc::new(SyncControl::default());
+        let token = CancellationToken::new();
+        assert!(control.wait_while_paused(&token, None).await);
+
+        control.pause();
+        let waiting = tokio::spawn({
+            let control = std::sync::Arc::clone(&control);
+            let token = token.clone();
+            async move { control.wait_while_paused(&token, None).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        control.resume();
+        assert!(waiting.await.unwrap());
+
+        control.pause();
+        token.cancel();
+        assert!(!control.wait_while_paused(&token, None).await);
+    }
+}
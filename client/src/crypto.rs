@@ -0,0 +1,235 @@
+//! Optional end-to-end encryption of chunk contents and file paths.
+//!
+//! Everything is derived from a passphrase, so any device that knows it can
+//! read the namespace and the server never sees plaintext. Encryption is
+//! deterministic: equal plaintexts produce equal ciphertexts, which keeps
+//! chunk deduplication and per-path history working on the server at the
+//! cost of revealing which chunks and paths are equal.
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::chunk_id;
+use crate::errors::SyncError;
+
+type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+/// Marks a path as encrypted, anything without it was committed in plain.
+pub const ENCRYPTED_PATH_PREFIX: &str = "e2e:";
+
+const NONCE_SIZE: usize = 24;
+
+// BLAKE3 key derivation contexts, changing any of them changes every key.
+const SALT_CONTEXT: &str = "cooklang-sync 2026-10-18 passphrase salt";
+const CHUNK_ID_CONTEXT: &str = "cooklang-sync 2026-10-18 chunk id";
+const CHUNK_KEY_CONTEXT: &str = "cooklang-sync 2026-10-18 chunk key";
+const CHUNK_NONCE_CONTEXT: &str = "cooklang-sync 2026-10-18 chunk nonce";
+const PATH_KEY_CONTEXT: &str = "cooklang-sync 2026-10-18 path key";
+const PATH_NONCE_CONTEXT: &str = "cooklang-sync 2026-10-18 path nonce";
+
+/// Keys for one namespace, derived from the user's passphrase.
+#[derive(Clone)]
+pub struct Cipher {
+    id_key: [u8; 32],
+    chunks: DeterministicAead,
+    paths: DeterministicAead,
+}
+
+impl Cipher {
+    /// Derives keys from a master key, mostly useful for tests as it skips
+    /// the deliberately slow passphrase hashing.
+    pub fn new(master_key: [u8; 32]) -> Cipher {
+        Cipher {
+            id_key: blake3::derive_key(CHUNK_ID_CONTEXT, &master_key),
+            chunks: DeterministicAead::new(&master_key, CHUNK_KEY_CONTEXT, CHUNK_NONCE_CONTEXT),
+            paths: DeterministicAead::new(&master_key, PATH_KEY_CONTEXT, PATH_NONCE_CONTEXT),
+        }
+    }
+
+    /// Derives keys from a passphrase with Argon2id. `salt` has to be the
+    /// same on every device, e.g. the account's email.
+    pub fn from_passphrase(passphrase: &str, salt: &str) -> Result<Cipher> {
+        if passphrase.is_empty() {
+            return Err(SyncError::EncryptionError(
+                "passphrase can't be empty".to_string(),
+            ));
+        }
+
+        let salt = blake3::derive_key(SALT_CONTEXT, salt.as_bytes());
+        let mut master_key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut master_key)
+            .map_err(|e| SyncError::EncryptionError(e.to_string()))?;
+
+        Ok(Cipher::new(master_key))
+    }
+
+    /// Keyed ID of a plaintext chunk.
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        chunk_id::keyed_blake3(&self.id_key, data)
+    }
+
+    pub fn encrypt_chunk(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.chunks.seal(data)
+    }
+
+    pub fn decrypt_chunk(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.chunks.open(data)
+    }
+
+    pub fn encrypt_path(&self, path: &str) -> Result<String> {
+        let sealed = self.paths.seal(path.as_bytes())?;
+
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PATH_PREFIX,
+            URL_SAFE_NO_PAD.encode(sealed)
+        ))
+    }
+
+    /// Decrypts a path from the server. Paths committed before encryption
+    /// was turned on are returned as is.
+    pub fn decrypt_path(&self, path: &str) -> Result<String> {
+        let Some(encoded) = path.strip_prefix(ENCRYPTED_PATH_PREFIX) else {
+            return Ok(path.to_string());
+        };
+
+        let sealed = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|e| SyncError::EncryptionError(e.to_string()))?;
+        let plain = self.paths.open(&sealed)?;
+
+        String::from_utf8(plain).map_err(|e| SyncError::EncryptionError(e.to_string()))
+    }
+}
+
+pub fn is_encrypted_path(path: &str) -> bool {
+    path.starts_with(ENCRYPTED_PATH_PREFIX)
+}
+
+/// XChaCha20-Poly1305 with the nonce derived from the plaintext, sealed
+/// data is `nonce || ciphertext`.
+#[derive(Clone)]
+struct DeterministicAead {
+    key: [u8; 32],
+    nonce_key: [u8; 32],
+}
+
+impl DeterministicAead {
+    fn new(master_key: &[u8; 32], key_context: &str, nonce_context: &str) -> Self {
+        DeterministicAead {
+            key: blake3::derive_key(key_context, master_key),
+            nonce_key: blake3::derive_key(nonce_context, master_key),
+        }
+    }
+
+    fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce_hash = blake3::keyed_hash(&self.nonce_key, plain);
+        let nonce = XNonce::from_slice(&nonce_hash.as_bytes()[..NONCE_SIZE]);
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            XChaCha20Poly1305::new(&self.key.into())
+                .encrypt(nonce, plain)
+                .map_err(|_| SyncError::EncryptionError("can't encrypt".to_string()))?,
+        );
+
+        Ok(sealed)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE {
+            return Err(SyncError::EncryptionError(
+                "encrypted data is too short".to_string(),
+            ));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+        XChaCha20Poly1305::new(&self.key.into())
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SyncError::EncryptionError("can't decrypt, wrong passphrase?".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_round_trip_and_are_deterministic() {
+        let cipher = Cipher::new([7; 32]);
+        let data = b"Add @eggs{2} and mix.\n";
+
+        let sealed = cipher.encrypt_chunk(data).unwrap();
+        assert_ne!(&sealed[NONCE_SIZE..], data);
+        assert_eq!(sealed, cipher.encrypt_chunk(data).unwrap());
+        assert_eq!(cipher.decrypt_chunk(&sealed).unwrap(), data);
+    }
+
+    #[test]
+    fn chunk_ids_are_keyed() {
+        let data = b"eggs\n";
+
+        let id = Cipher::new([7; 32]).chunk_id(data);
+        assert!(chunk_id::is_keyed(&id));
+        assert_eq!(id, Cipher::new([7; 32]).chunk_id(data));
+        assert_ne!(id, Cipher::new([8; 32]).chunk_id(data));
+        assert_ne!(id, chunk_id::blake3(data));
+    }
+
+    #[test]
+    fn paths_round_trip() {
+        let cipher = Cipher::new([7; 32]);
+
+        let encrypted = cipher.encrypt_path("Breakfast/Pancakes.cook").unwrap();
+        assert!(is_encrypted_path(&encrypted));
+        assert!(!encrypted.contains("Pancakes"));
+        assert!(!encrypted.contains('/'));
+        assert_eq!(
+            encrypted,
+            cipher.encrypt_path("Breakfast/Pancakes.cook").unwrap()
+        );
+        assert_eq!(
+            cipher.decrypt_path(&encrypted).unwrap(),
+            "Breakfast/Pancakes.cook"
+        );
+    }
+
+    #[test]
+    fn plain_paths_pass_through() {
+        let cipher = Cipher::new([7; 32]);
+
+        assert_eq!(cipher.decrypt_path("Soup.cook").unwrap(), "Soup.cook");
+    }
+
+    #[test]
+    fn wrong_key_fails_to_decrypt() {
+        let sealed = Cipher::new([7; 32]).encrypt_chunk(b"eggs\n").unwrap();
+        let path = Cipher::new([7; 32]).encrypt_path("Soup.cook").unwrap();
+
+        let other = Cipher::new([8; 32]);
+        assert!(matches!(
+            other.decrypt_chunk(&sealed),
+            Err(SyncError::EncryptionError(_))
+        ));
+        assert!(matches!(
+            other.decrypt_path(&path),
+            Err(SyncError::EncryptionError(_))
+        ));
+        assert!(other.decrypt_chunk(&sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn passphrase_derivation_is_stable() {
+        let a = Cipher::from_passphrase("correct horse", "cook@example.com").unwrap();
+        let b = Cipher::from_passphrase("correct horse", "cook@example.com").unwrap();
+        let c = Cipher::from_passphrase("correct horse", "chef@example.com").unwrap();
+
+        assert_eq!(a.chunk_id(b"eggs\n"), b.chunk_id(b"eggs\n"));
+        assert_ne!(a.chunk_id(b"eggs\n"), c.chunk_id(b"eggs\n"));
+        assert!(Cipher::from_passphrase("", "cook@example.com").is_err());
+    }
+}
@@ -40,9 +40,61 @@ pub enum SyncError {
     Unknown(String),
     #[error("Batch download error {0}")]
     BatchDownloadError(String),
+    #[error("Invalid chunking config: {0}")]
+    InvalidChunkingConfig(String),
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+    #[error("Downloaded content doesn't match chunk IDs: {0}")]
+    IntegrityError(String),
+    #[error("Can't sync file, its name isn't valid UTF-8: {0}")]
+    UnrepresentablePath(String),
+    #[error("Invalid path from server: {0}")]
+    InvalidRemotePath(String),
+    #[error("Server unavailable: {0}")]
+    ServerUnavailable(String),
+}
+
+/// How a sync loop deals with an error, see `SyncError::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Likely gone on its own after a while, e.g. no network or a server
+    /// restart. Retried with backoff.
+    Transient,
+    /// The token isn't accepted, the app needs a new one.
+    Auth,
+    /// Won't go away by retrying, ends the sync.
+    Fatal,
 }
 
 impl SyncError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SyncError::Unauthorized => ErrorKind::Auth,
+            SyncError::ReqwestError(e)
+                if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() =>
+            {
+                ErrorKind::Transient
+            }
+            SyncError::ReqwestError(e) if e.status().is_some_and(|s| s.is_server_error()) => {
+                ErrorKind::Transient
+            }
+            // Responses cut off or mangled on the way.
+            SyncError::ServerUnavailable(_) | SyncError::BatchDownloadError(_) => {
+                ErrorKind::Transient
+            }
+            _ => ErrorKind::Fatal,
+        }
+    }
+
+    /// Whether the server couldn't be reached at all, as opposed to
+    /// answering with an error.
+    pub fn is_offline(&self) -> bool {
+        match self {
+            SyncError::ReqwestError(e) => e.is_connect() || e.is_timeout(),
+            _ => false,
+        }
+    }
+
     pub fn from_io_error(path: impl Into<PathBuf>, error: std::io::Error) -> Self {
         SyncError::IoError {
             path: path.into().display().to_string(),
@@ -80,6 +132,26 @@ mod tests {
         assert_eq!(format!("{err}"), "Unauthorized token");
     }
 
+    #[test]
+    fn errors_are_classified_for_retries() {
+        assert_eq!(SyncError::Unauthorized.kind(), ErrorKind::Auth);
+        assert_eq!(
+            SyncError::ServerUnavailable("503".into()).kind(),
+            ErrorKind::Transient
+        );
+        // Asked for again already, the server keeps sending the same.
+        assert_eq!(
+            SyncError::IntegrityError("c1".into()).kind(),
+            ErrorKind::Fatal
+        );
+        assert_eq!(SyncError::Unknown("400".into()).kind(), ErrorKind::Fatal);
+        assert_eq!(
+            SyncError::EncryptionError("wrong passphrase".into()).kind(),
+            ErrorKind::Fatal
+        );
+        assert!(!SyncError::ServerUnavailable("503".into()).is_offline());
+    }
+
     #[test]
     fn unknown_variant_includes_context() {
         let err = SyncError::Unknown("xyz".into());
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::chunker::{self, Chunking};
+use crate::models::FileFormat;
+
+// How much of a file is read when sniffing its content.
+const SNIFF_SIZE: usize = 8 * 1_024;
+
+// Signatures of binary formats worth syncing even when their extension
+// isn't registered.
+const MAGIC_BYTES: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n",
+    b"\xff\xd8\xff", // JPEG
+    b"GIF87a",
+    b"GIF89a",
+    b"%PDF-",
+];
+
+/// Decides which files are synced and how each of them is chunked.
+///
+/// Built-in extensions and filenames (see `chunker::is_text` and
+/// `chunker::is_binary`) are always recognised, callers can register more.
+/// Files matching neither can optionally be classified by their content.
+#[derive(Debug, Clone)]
+pub struct FileFormatRegistry {
+    text_extensions: HashSet<String>,
+    text_filenames: HashSet<String>,
+    binary_extensions: HashSet<String>,
+    binary_filenames: HashSet<String>,
+    sniff_content: bool,
+    text_chunking: Chunking,
+    binary_chunking: Chunking,
+}
+
+impl Default for FileFormatRegistry {
+    fn default() -> Self {
+        FileFormatRegistry {
+            text_extensions: HashSet::new(),
+            text_filenames: HashSet::new(),
+            binary_extensions: HashSet::new(),
+            binary_filenames: HashSet::new(),
+            sniff_content: false,
+            text_chunking: Chunking::Lines,
+            binary_chunking: Chunking::FixedSize,
+        }
+    }
+}
+
+impl FileFormatRegistry {
+    /// Registers an extension (without the dot, case-insensitive).
+    pub fn add_extension(&mut self, extension: &str, format: FileFormat) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+
+        match format {
+            FileFormat::Text => self.text_extensions.insert(extension),
+            FileFormat::Binary => self.binary_extensions.insert(extension),
+        };
+    }
+
+    /// Registers an exact filename, e.g. `.shopping-list`.
+    pub fn add_filename(&mut self, filename: &str, format: FileFormat) {
+        match format {
+            FileFormat::Text => self.text_filenames.insert(filename.to_string()),
+            FileFormat::Binary => self.binary_filenames.insert(filename.to_string()),
+        };
+    }
+
+    /// When enabled, files with an unknown name are classified by reading
+    /// their first bytes: known binary signatures make them binary, valid
+    /// UTF-8 makes them text. Costs a read per unknown file on every scan.
+    pub fn set_content_sniffing(&mut self, enabled: bool) {
+        self.sniff_content = enabled;
+    }
+
+    pub fn set_chunking(&mut self, format: FileFormat, chunking: Chunking) {
+        match format {
+            FileFormat::Text => self.text_chunking = chunking,
+            FileFormat::Binary => self.binary_chunking = chunking,
+        }
+    }
+
+    pub fn chunking(&self, format: FileFormat) -> Chunking {
+        match format {
+            FileFormat::Text => self.text_chunking,
+            FileFormat::Binary => self.binary_chunking,
+        }
+    }
+
+    /// Format of the file at `p`, or `None` if it shouldn't be synced.
+    pub fn detect(&self, p: &Path) -> Option<FileFormat> {
+        self.detect_by_name(p)
+            .or_else(|| if self.sniff_content { sniff(p) } else { None })
+    }
+
+    /// Format implied by the name alone, without touching the file.
+    pub fn detect_by_name(&self, p: &Path) -> Option<FileFormat> {
+        if let Some(file_name) = p.file_name().and_then(|n| n.to_str()) {
+            if self.text_filenames.contains(file_name) {
+                return Some(FileFormat::Text);
+            }
+            if self.binary_filenames.contains(file_name) {
+                return Some(FileFormat::Binary);
+            }
+        }
+
+        if chunker::is_text(p) {
+            return Some(FileFormat::Text);
+        }
+        if chunker::is_binary(p) {
+            return Some(FileFormat::Binary);
+        }
+
+        let ext = p.extension()?.to_str()?.to_ascii_lowercase();
+        if self.text_extensions.contains(&ext) {
+            Some(FileFormat::Text)
+        } else if self.binary_extensions.contains(&ext) {
+            Some(FileFormat::Binary)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a file at `p` could be synced under this configuration. Used
+    /// for paths that no longer exist, so content can't be consulted.
+    pub fn may_manage(&self, p: &Path) -> bool {
+        self.sniff_content || self.detect_by_name(p).is_some()
+    }
+}
+
+fn sniff(p: &Path) -> Option<FileFormat> {
+    let mut buf = Vec::with_capacity(SNIFF_SIZE);
+    File::open(p)
+        .ok()?
+        .take(SNIFF_SIZE as u64)
+        .read_to_end(&mut buf)
+        .ok()?;
+
+    if MAGIC_BYTES.iter().any(|m| buf.starts_with(m)) || is_webp(&buf) {
+        return Some(FileFormat::Binary);
+    }
+
+    if buf.contains(&0) {
+        return None;
+    }
+
+    match std::str::from_utf8(&buf) {
+        Ok(_) => Some(FileFormat::Text),
+        // A multi-byte character cut by the end of the sniffed window.
+        Err(e) if e.error_len().is_none() && buf.len() == SNIFF_SIZE => Some(FileFormat::Text),
+        Err(_) => None,
+    }
+}
+
+fn is_webp(buf: &[u8]) -> bool {
+    buf.len() >= 12 && &buf[0..4] == b"RIFF" && &buf[8..12] == b"WEBP"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn default_registry_recognises_builtin_formats() {
+        let formats = FileFormatRegistry::default();
+
+        assert_eq!(
+            formats.detect(Path::new("soup.cook")),
+            Some(FileFormat::Text)
+        );
+        assert_eq!(
+            formats.detect(Path::new(".shopping-list")),
+            Some(FileFormat::Text)
+        );
+        assert_eq!(
+            formats.detect(Path::new("soup.JPG")),
+            Some(FileFormat::Binary)
+        );
+        assert_eq!(formats.detect(Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn registered_extensions_and_filenames_are_recognised() {
+        let mut formats = FileFormatRegistry::default();
+        formats.add_extension(".TXT", FileFormat::Text);
+        formats.add_extension("heic", FileFormat::Binary);
+        formats.add_filename("Pantry", FileFormat::Text);
+
+        assert_eq!(
+            formats.detect(Path::new("notes.txt")),
+            Some(FileFormat::Text)
+        );
+        assert_eq!(
+            formats.detect(Path::new("a/photo.HEIC")),
+            Some(FileFormat::Binary)
+        );
+        assert_eq!(
+            formats.detect(Path::new("a/Pantry")),
+            Some(FileFormat::Text)
+        );
+        assert_eq!(formats.detect(Path::new("a/pantry")), None);
+    }
+
+    #[test]
+    fn sniffing_is_off_by_default() {
+        let temp = TempDir::new().unwrap();
+        let p = temp.path().join("README");
+        std::fs::write(&p, "plain text").unwrap();
+
+        assert_eq!(FileFormatRegistry::default().detect(&p), None);
+    }
+
+    #[test]
+    fn sniffing_classifies_by_content() {
+        let temp = TempDir::new().unwrap();
+        let mut formats = FileFormatRegistry::default();
+        formats.set_content_sniffing(true);
+
+        let text = temp.path().join("README");
+        std::fs::write(&text, "Crème brûlée\n").unwrap();
+        let png = temp.path().join("picture");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR").unwrap();
+        let webp = temp.path().join("picture2");
+        std::fs::write(&webp, b"RIFF\x10\x00\x00\x00WEBPVP8 ").unwrap();
+        let junk = temp.path().join(".DS_Store");
+        std::fs::write(&junk, b"\x00\x00\x00\x01Bud1\xff\xfe").unwrap();
+
+        assert_eq!(formats.detect(&text), Some(FileFormat::Text));
+        assert_eq!(formats.detect(&png), Some(FileFormat::Binary));
+        assert_eq!(formats.detect(&webp), Some(FileFormat::Binary));
+        assert_eq!(formats.detect(&junk), None);
+        assert_eq!(formats.detect(&temp.path().join("missing")), None);
+    }
+
+    #[test]
+    fn sniffing_tolerates_character_cut_at_window_end() {
+        let temp = TempDir::new().unwrap();
+        let mut formats = FileFormatRegistry::default();
+        formats.set_content_sniffing(true);
+
+        // 'é' is two bytes; put its first byte last in the sniffed window.
+        let mut content = vec![b'a'; SNIFF_SIZE - 1];
+        content.extend_from_slice("é and more".as_bytes());
+        let p = temp.path().join("long");
+        std::fs::write(&p, content).unwrap();
+
+        assert_eq!(formats.detect(&p), Some(FileFormat::Text));
+    }
+
+    #[test]
+    fn chunking_is_configured_per_format() {
+        let mut formats = FileFormatRegistry::default();
+        assert_eq!(formats.chunking(FileFormat::Text), Chunking::Lines);
+        assert_eq!(formats.chunking(FileFormat::Binary), Chunking::FixedSize);
+
+        formats.set_chunking(FileFormat::Binary, Chunking::CONTENT_DEFINED_DEFAULT);
+        assert_eq!(
+            formats.chunking(FileFormat::Binary),
+            Chunking::CONTENT_DEFINED_DEFAULT
+        );
+        assert_eq!(formats.chunking(FileFormat::Text), Chunking::Lines);
+    }
+
+    #[test]
+    fn may_manage_without_content() {
+        let mut formats = FileFormatRegistry::default();
+        assert!(formats.may_manage(Path::new("gone.cook")));
+        assert!(!formats.may_manage(Path::new("gone.txt")));
+
+        formats.set_content_sniffing(true);
+        assert!(formats.may_manage(Path::new("gone.txt")));
+    }
+}
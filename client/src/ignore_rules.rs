@@ -0,0 +1,157 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Name of the files with gitignore-style rules of what not to sync.
+pub const IGNORE_FILE_NAME: &str = ".cooksyncignore";
+
+/// Rules from all `.cooksyncignore` files found in the storage dir. Like
+/// with git, a file applies to its directory and everything below it, and
+/// rules in deeper files take precedence.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    base_path: PathBuf,
+    // In the order directories were visited, so parents come before
+    // their children.
+    matchers: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    pub fn new(base_path: &Path) -> IgnoreRules {
+        IgnoreRules {
+            base_path: base_path.to_path_buf(),
+            matchers: Vec::new(),
+        }
+    }
+
+    /// Reads the ignore file in `dir` if there's one and it isn't read yet.
+    /// Directories have to be added top-down. Invalid lines are skipped with
+    /// a warning.
+    pub fn add_dir(&mut self, dir: &Path) {
+        let file = dir.join(IGNORE_FILE_NAME);
+        if !file.is_file() || self.matchers.iter().any(|m| m.path() == dir) {
+            return;
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&file) {
+            warn!("Some rules in {:?} are invalid: {}", file, e);
+        }
+
+        match builder.build() {
+            Ok(matcher) => self.matchers.push(matcher),
+            Err(e) => warn!("Can't use rules in {:?}: {}", file, e),
+        }
+    }
+
+    /// Reads the ignore files from the storage dir down to the directory of
+    /// `path`, absolute or relative to the storage dir.
+    pub fn add_dirs_above(&mut self, path: &Path) {
+        let path = self.base_path.join(path);
+        let Some(relative) = path
+            .parent()
+            .and_then(|p| p.strip_prefix(&self.base_path).ok())
+        else {
+            return;
+        };
+
+        let mut dir = self.base_path.clone();
+        self.add_dir(&dir);
+        for part in relative.components() {
+            dir.push(part);
+            self.add_dir(&dir);
+        }
+    }
+
+    /// Whether `path`, absolute or relative to the storage dir, is excluded
+    /// by the rules or sits in an excluded directory.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = self.base_path.join(path);
+
+        for matcher in self.matchers.iter().rev() {
+            if !path.starts_with(matcher.path()) {
+                continue;
+            }
+
+            match matcher.matched_path_or_any_parents(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rules_for(temp: &TempDir, files: &[(&str, &str)]) -> IgnoreRules {
+        let mut dirs = vec![temp.path().to_path_buf()];
+        for (dir, content) in files {
+            let dir = temp.path().join(dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(IGNORE_FILE_NAME), content).unwrap();
+            dirs.push(dir);
+        }
+        dirs.sort();
+        dirs.dedup();
+
+        let mut rules = IgnoreRules::new(temp.path());
+        for dir in dirs {
+            rules.add_dir(&dir);
+        }
+        rules
+    }
+
+    #[test]
+    fn nothing_is_ignored_without_ignore_files() {
+        let temp = TempDir::new().unwrap();
+        let rules = rules_for(&temp, &[]);
+
+        assert!(!rules.is_ignored(Path::new("soup.cook"), false));
+    }
+
+    #[test]
+    fn root_rules_match_files_and_directories() {
+        let temp = TempDir::new().unwrap();
+        let rules = rules_for(&temp, &[("", "drafts/\n*.menu\n/archive\n")]);
+
+        assert!(rules.is_ignored(Path::new("drafts"), true));
+        assert!(rules.is_ignored(Path::new("drafts/soup.cook"), false));
+        assert!(rules.is_ignored(Path::new("a/b/drafts/soup.cook"), false));
+        assert!(rules.is_ignored(Path::new("week.menu"), false));
+        assert!(rules.is_ignored(Path::new("archive/old.cook"), false));
+        assert!(!rules.is_ignored(Path::new("a/archive/old.cook"), false));
+        assert!(!rules.is_ignored(Path::new("soup.cook"), false));
+        assert!(rules.is_ignored(&temp.path().join("week.menu"), false));
+    }
+
+    #[test]
+    fn rules_above_a_path_are_read_on_demand() {
+        let temp = TempDir::new().unwrap();
+        rules_for(&temp, &[("", "*.jpg\n"), ("photos", "!keep.jpg\n")]);
+
+        let mut rules = IgnoreRules::new(temp.path());
+        rules.add_dirs_above(Path::new("photos/keep.jpg"));
+
+        assert!(!rules.is_ignored(Path::new("photos/keep.jpg"), false));
+        assert!(rules.is_ignored(Path::new("cover.jpg"), false));
+    }
+
+    #[test]
+    fn nested_rules_take_precedence() {
+        let temp = TempDir::new().unwrap();
+        let rules = rules_for(&temp, &[("", "*.jpg\n"), ("photos", "!keep.jpg\n*.cook\n")]);
+
+        assert!(rules.is_ignored(Path::new("cover.jpg"), false));
+        assert!(rules.is_ignored(Path::new("photos/other.jpg"), false));
+        assert!(!rules.is_ignored(Path::new("photos/keep.jpg"), false));
+        assert!(rules.is_ignored(Path::new("photos/soup.cook"), false));
+        assert!(!rules.is_ignored(Path::new("soup.cook"), false));
+    }
+}
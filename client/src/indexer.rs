@@ -3,29 +3,36 @@ use futures::{
     SinkExt, StreamExt,
 };
 use std::collections::HashMap;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use path_slash::PathExt as _;
 use walkdir::WalkDir;
 
-use notify_debouncer_mini::DebounceEventResult;
+use notify_debouncer_mini::{DebounceEventResult, DebouncedEvent};
 use time::OffsetDateTime;
-use tokio::time::Duration;
+use tokio::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
-use log::debug;
+use log::{debug, warn};
 
 use crate::chunker;
 use crate::connection::{get_connection, ConnectionPool};
+use crate::control::SyncControl;
 use crate::errors::SyncError;
+use crate::file_format::FileFormatRegistry;
+use crate::ignore_rules::{IgnoreRules, IGNORE_FILE_NAME};
 use crate::models::*;
 use crate::registry;
+use crate::sync_path;
 use crate::{SyncStatus, SyncStatusListener};
 
 type DBFiles = HashMap<String, FileRecord>;
 type DiskFiles = HashMap<String, CreateForm>;
 
 const CHECK_INTERVAL_WAIT_SEC: Duration = Duration::from_secs(61);
+// Longer than the watcher's debounce, so the scan triggered by an edit
+// still sees the file as recently written.
+const RACY_WINDOW: time::Duration = time::Duration::seconds(5);
 
 /// Indexer main loop. It doesn't manipulate files, but only
 /// compares what we have in filesystem with what we have in DB.
@@ -33,16 +40,26 @@ const CHECK_INTERVAL_WAIT_SEC: Duration = Duration::from_secs(61);
 /// When any change made it will send a message in channel
 /// that Syncer is listening.
 ///
-/// It runs both on interval and on any event coming from FS watcher.
+/// It runs a full scan on interval, and a scan of just the reported paths
+/// on events coming from FS watcher. Watcher errors get a full scan too.
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     token: CancellationToken,
+    control: &SyncControl,
     listener: Option<Arc<dyn SyncStatusListener>>,
     pool: &ConnectionPool,
     storage_path: &Path,
     namespace_id: i32,
+    formats: &FileFormatRegistry,
     mut local_file_update_rx: Receiver<DebounceEventResult>,
     mut updated_tx: Sender<IndexerUpdateEvent>,
 ) -> Result<(), SyncError> {
+    let mut rules = IgnoreRules::new(storage_path);
+    // What the watcher reported, `None` asks for a full scan.
+    let mut changed: Option<Vec<PathBuf>> = None;
+    let mut next_full_scan = Instant::now();
+    let mut wake_ups = control.wake_ups();
+
     loop {
         // Check for cancellation at loop start
         if token.is_cancelled() {
@@ -50,12 +67,54 @@ pub async fn run(
             break;
         }
 
+        if control.is_paused() {
+            if let Some(ref cb) = listener {
+                cb.on_status_changed(SyncStatus::Paused);
+            }
+
+            // The watcher goes on meanwhile, its events are taken so it
+            // doesn't block, and a full scan picks up what they were about.
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => {
+                        debug!("Indexer shutting down");
+                        return Ok(());
+                    }
+                    _ = control.resumed() => break,
+                    Some(_) = local_file_update_rx.next() => changed = None,
+                }
+            }
+        }
+
         // Notify that we're starting to index
         if let Some(ref cb) = listener {
             cb.on_status_changed(SyncStatus::Indexing);
         }
 
-        if check_index_once(pool, storage_path, namespace_id)? {
+        let targeted = match changed.take() {
+            Some(paths) => {
+                match check_index_paths_once(pool, storage_path, namespace_id, formats, &paths) {
+                    Ok(updated) => Some(updated),
+                    Err(e) => {
+                        debug!("falling back to a full scan: {}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let updated = match targeted {
+            Some(updated) => updated,
+            None => {
+                let (updated, new_rules) = index_once(pool, storage_path, namespace_id, formats)?;
+                rules = new_rules;
+                next_full_scan = Instant::now() + CHECK_INTERVAL_WAIT_SEC;
+                updated
+            }
+        };
+
+        if updated {
             updated_tx.send(IndexerUpdateEvent::Updated).await?;
         }
 
@@ -64,14 +123,31 @@ pub async fn run(
             cb.on_status_changed(SyncStatus::Idle);
         }
 
-        tokio::select! {
-            _ = token.cancelled() => {
-                debug!("Indexer shutting down");
-                break;
-            }
-            _ = tokio::time::sleep(CHECK_INTERVAL_WAIT_SEC) => {},
-            Some(_) = local_file_update_rx.next() => {},
-        };
+        // Changes to ignored files don't need a rescan.
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    debug!("Indexer shutting down");
+                    return Ok(());
+                }
+                _ = tokio::time::sleep_until(next_full_scan) => break,
+                _ = wake_ups.next() => {
+                    changed = None;
+                    break;
+                }
+                Some(events) = local_file_update_rx.next() => {
+                    match changed_paths(&events, &rules) {
+                        Some(paths) if paths.is_empty() => {
+                            debug!("skipping events on ignored paths");
+                        }
+                        paths => {
+                            changed = paths;
+                            break;
+                        }
+                    }
+                }
+            };
+        }
     }
 
     Ok(())
@@ -81,13 +157,51 @@ pub fn check_index_once(
     pool: &ConnectionPool,
     storage_path: &Path,
     namespace_id: i32,
+    formats: &FileFormatRegistry,
 ) -> Result<bool, SyncError> {
+    index_once(pool, storage_path, namespace_id, formats).map(|(updated, _)| updated)
+}
+
+/// Like `check_index_once`, also returns the ignore rules found on the way.
+fn index_once(
+    pool: &ConnectionPool,
+    storage_path: &Path,
+    namespace_id: i32,
+    formats: &FileFormatRegistry,
+) -> Result<(bool, IgnoreRules), SyncError> {
     debug!("interval scan");
 
-    let from_db = get_file_records_from_registry(pool, namespace_id)?;
-    let from_fs = get_file_records_from_disk(storage_path, namespace_id)?;
+    let mut from_db = get_file_records_from_registry(pool, namespace_id)?;
+    let (mut from_fs, rules) = get_file_records_from_disk(storage_path, namespace_id, formats)?;
+    skip_downloading(pool, namespace_id, &mut from_db, &mut from_fs)?;
+
+    let (to_remove, to_add, to_refresh) = compare_records(
+        from_db,
+        from_fs,
+        storage_path,
+        namespace_id,
+        formats,
+        &rules,
+    );
+
+    Ok((apply_changes(pool, to_remove, to_add, to_refresh)?, rules))
+}
+
+/// Writes what `compare_records` found, returns whether there's anything
+/// new to upload.
+fn apply_changes(
+    pool: &ConnectionPool,
+    to_remove: Vec<DeleteForm>,
+    to_add: Vec<CreateForm>,
+    to_refresh: Vec<(FileRecord, FileRecordUpdateForm)>,
+) -> Result<bool, SyncError> {
+    if !to_refresh.is_empty() {
+        let conn = &mut get_connection(pool)?;
 
-    let (to_remove, to_add) = compare_records(from_db, from_fs, namespace_id);
+        for (record, form) in &to_refresh {
+            registry::update_metadata(conn, record, form)?;
+        }
+    }
 
     if !to_remove.is_empty() || !to_add.is_empty() {
         let conn = &mut get_connection(pool)?;
@@ -106,12 +220,148 @@ pub fn check_index_once(
     }
 }
 
-fn filter_eligible(p: &Path) -> bool {
+/// Like `check_index_once`, only looks at `paths` (absolute or relative to
+/// the storage dir) and whatever is under them, both on disk and in the
+/// registry. What the watcher reports is usually a handful of files, much
+/// cheaper to check than the whole tree.
+pub fn check_index_paths_once(
+    pool: &ConnectionPool,
+    storage_path: &Path,
+    namespace_id: i32,
+    formats: &FileFormatRegistry,
+    paths: &[PathBuf],
+) -> Result<bool, SyncError> {
+    debug!("scan of {} changed paths", paths.len());
+
+    let mut from_db = HashMap::new();
+    let mut from_fs = HashMap::new();
+    let mut rules = IgnoreRules::new(storage_path);
+
+    for path in paths {
+        let full_path = storage_path.join(path);
+        // Events can come from outside, e.g. when the storage dir is moved.
+        let Ok(relative) = full_path.strip_prefix(storage_path) else {
+            continue;
+        };
+        if relative.as_os_str().is_empty() {
+            return check_index_once(pool, storage_path, namespace_id, formats);
+        }
+
+        let is_dir = full_path.is_dir();
+        if in_dot_dir(relative, is_dir) {
+            continue;
+        }
+
+        for dir in relative.ancestors().skip(1) {
+            rules.add_dir(&storage_path.join(dir));
+        }
+        if rules.is_ignored(&full_path, is_dir) {
+            continue;
+        }
+
+        let Ok(key) = sync_path::from_local(relative) else {
+            continue;
+        };
+        let conn = &mut get_connection(pool)?;
+        for record in registry::non_deleted_under(conn, namespace_id, &key)? {
+            from_db.insert(record.path.clone(), record);
+        }
+
+        if is_dir {
+            collect_files_from_disk(
+                storage_path,
+                &full_path,
+                namespace_id,
+                formats,
+                &mut rules,
+                &mut from_fs,
+            )?;
+        } else if let Some(format) = eligible_format(&full_path, formats) {
+            match build_file_record(&full_path, storage_path, namespace_id, format) {
+                Ok(record) => {
+                    from_fs.insert(record.path.clone(), record);
+                }
+                Err(e @ SyncError::UnrepresentablePath(_)) => warn!("{}", e),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    skip_downloading(pool, namespace_id, &mut from_db, &mut from_fs)?;
+
+    let (to_remove, to_add, to_refresh) = compare_records(
+        from_db,
+        from_fs,
+        storage_path,
+        namespace_id,
+        formats,
+        &rules,
+    );
+
+    apply_changes(pool, to_remove, to_add, to_refresh)
+}
+
+/// Leaves out files a download is writing right now. The syncer records
+/// them once the whole batch is on disk, until then they'd look changed.
+fn skip_downloading(
+    pool: &ConnectionPool,
+    namespace_id: i32,
+    from_db: &mut DBFiles,
+    from_fs: &mut DiskFiles,
+) -> Result<(), SyncError> {
+    let conn = &mut get_connection(pool)?;
+
+    for intent in registry::download_intents(conn, namespace_id)? {
+        from_db.remove(&intent.path);
+        from_fs.remove(&intent.path);
+    }
+
+    Ok(())
+}
+
+/// Paths to re-index for a batch of watcher events, minus ignored ones.
+/// `None` asks for a full scan: the watcher lost track of changes, or
+/// ignore rules changed and any file could be affected.
+fn changed_paths(events: &DebounceEventResult, rules: &IgnoreRules) -> Option<Vec<PathBuf>> {
+    match events {
+        Ok(events) if !events.is_empty() => {
+            if events
+                .iter()
+                .any(|e| e.path.file_name() == Some(OsStr::new(IGNORE_FILE_NAME)))
+            {
+                return None;
+            }
+
+            Some(
+                events
+                    .iter()
+                    .filter(|DebouncedEvent { path, .. }| !rules.is_ignored(path, path.is_dir()))
+                    .map(|DebouncedEvent { path, .. }| path.clone())
+                    .collect(),
+            )
+        }
+        // Can't tell what changed, better rescan.
+        _ => None,
+    }
+}
+
+/// Whether `relative` is in a directory the scan prunes, or is one.
+fn in_dot_dir(relative: &Path, is_dir: bool) -> bool {
+    let mut dirs: Vec<_> = relative.components().collect();
+    if !is_dir {
+        dirs.pop();
+    }
+
+    dirs.iter()
+        .any(|c| c.as_os_str().to_str().is_some_and(|s| s.starts_with('.')))
+}
+
+fn eligible_format(p: &Path, formats: &FileFormatRegistry) -> Option<FileFormat> {
     // TODO properly follow symlinks, they can be broken as well
-    if p.is_symlink() {
-        return false;
+    if p.is_symlink() || chunker::is_temp_file(p) || !p.is_file() {
+        return None;
     }
-    chunker::is_text(p) || chunker::is_binary(p)
+    formats.detect(p)
 }
 
 fn is_dot_dir(e: &walkdir::DirEntry) -> bool {
@@ -123,23 +373,68 @@ fn is_dot_dir(e: &walkdir::DirEntry) -> bool {
         && e.file_name().to_str().is_some_and(|s| s.starts_with('.'))
 }
 
-fn get_file_records_from_disk(base_path: &Path, namespace_id: i32) -> Result<DiskFiles, SyncError> {
+fn get_file_records_from_disk(
+    base_path: &Path,
+    namespace_id: i32,
+    formats: &FileFormatRegistry,
+) -> Result<(DiskFiles, IgnoreRules), SyncError> {
     let mut cache = HashMap::new();
+    let mut rules = IgnoreRules::new(base_path);
 
-    let iter = WalkDir::new(base_path)
+    collect_files_from_disk(
+        base_path,
+        base_path,
+        namespace_id,
+        formats,
+        &mut rules,
+        &mut cache,
+    )?;
+
+    Ok((cache, rules))
+}
+
+/// Adds records of files under `root` to `cache`. Rules of directories
+/// above `root` have to be in `rules` already.
+fn collect_files_from_disk(
+    base_path: &Path,
+    root: &Path,
+    namespace_id: i32,
+    formats: &FileFormatRegistry,
+    rules: &mut IgnoreRules,
+    cache: &mut DiskFiles,
+) -> Result<(), SyncError> {
+    // Ignore files are loaded as directories are entered, WalkDir visits
+    // a directory before anything in it.
+    let iter = WalkDir::new(root)
         .into_iter()
-        .filter_entry(|e| !(e.file_type().is_dir() && is_dot_dir(e)))
+        .filter_entry(|e| {
+            if !e.file_type().is_dir() {
+                return !rules.is_ignored(e.path(), false);
+            }
+            if is_dot_dir(e) || rules.is_ignored(e.path(), true) {
+                return false;
+            }
+            rules.add_dir(e.path());
+            true
+        })
         .filter_map(|e| e.ok())
         .map(|p| p.into_path())
-        .filter(|p| filter_eligible(p));
-
-    for p in iter {
-        let record = build_file_record(&p, base_path, namespace_id)?;
+        .filter_map(|p| eligible_format(&p, formats).map(|f| (p, f)));
+
+    for (p, format) in iter {
+        let record = match build_file_record(&p, base_path, namespace_id, format) {
+            Ok(record) => record,
+            Err(e @ SyncError::UnrepresentablePath(_)) => {
+                warn!("{}", e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
 
         cache.insert(record.path.clone(), record);
     }
 
-    Ok(cache)
+    Ok(())
 }
 
 fn get_file_records_from_registry(
@@ -157,28 +452,54 @@ fn get_file_records_from_registry(
     Ok(cache)
 }
 
+/// Returns records of deleted files, records of new or changed files and
+/// records of files whose content stayed the same while size or mtime didn't.
 fn compare_records(
     from_db: DBFiles,
     from_fs: DiskFiles,
+    base_path: &Path,
     namespace_id: i32,
-) -> (Vec<DeleteForm>, Vec<CreateForm>) {
+    formats: &FileFormatRegistry,
+    rules: &IgnoreRules,
+) -> (
+    Vec<DeleteForm>,
+    Vec<CreateForm>,
+    Vec<(FileRecord, FileRecordUpdateForm)>,
+) {
     let mut to_remove: Vec<DeleteForm> = Vec::new();
     let mut to_add: Vec<CreateForm> = Vec::new();
+    let mut to_refresh: Vec<(FileRecord, FileRecordUpdateForm)> = Vec::new();
 
     for (p, db_file) in &from_db {
         match from_fs.get(p) {
             // When file from DB is also present on a disk
             // we need to check if it was changed and if it was
             // remove and add file again.
-            Some(disk_file) => {
-                if db_file != disk_file {
-                    to_add.push(disk_file.clone());
+            Some(disk_file) => match compare_content(db_file, disk_file, base_path) {
+                Comparison::Same => {}
+                Comparison::Touched => to_refresh.push((
+                    db_file.clone(),
+                    FileRecordUpdateForm {
+                        size: disk_file.size,
+                        modified_at: disk_file.modified_at,
+                    },
+                )),
+                Comparison::Changed(checksum) => {
+                    let mut disk_file = disk_file.clone();
+                    disk_file.checksum = checksum.or(disk_file.checksum);
+                    to_add.push(disk_file);
                 }
-            }
+            },
             // When file from DB is not present on a disk
-            // we should mark it as deleted in DB
+            // we should mark it as deleted in DB. Unless the current
+            // format config doesn't cover it at all, e.g. a one-off run
+            // without the caller's extra extensions: absent from the scan
+            // doesn't mean deleted then. Same for paths that are ignored
+            // now, they are just not synced anymore.
             None => {
-                to_remove.push(build_delete_form(db_file, namespace_id));
+                if formats.may_manage(Path::new(p)) && !rules.is_ignored(Path::new(p), false) {
+                    to_remove.push(build_delete_form(db_file, namespace_id));
+                }
             }
         }
     }
@@ -189,20 +510,155 @@ fn compare_records(
         }
     }
 
-    (to_remove, to_add)
+    pair_moves(&from_db, &to_remove, &mut to_add, base_path);
+
+    (to_remove, to_add, to_refresh)
 }
 
-fn build_file_record(path: &Path, base: &Path, namespace_id: i32) -> Result<CreateForm, SyncError> {
-    let metadata = path
+/// A new file with the same size and checksum as one that is gone is that
+/// file moved, which gets recorded in `moved_from`. Only gone files with a
+/// known checksum can be matched, and new files are only hashed when their
+/// size matches one of them.
+fn pair_moves(
+    from_db: &DBFiles,
+    to_remove: &[DeleteForm],
+    to_add: &mut [CreateForm],
+    base_path: &Path,
+) {
+    let mut gone: Vec<&FileRecord> = to_remove
+        .iter()
+        .filter_map(|f| from_db.get(&f.path))
+        .filter(|f| f.checksum.is_some())
+        .collect();
+
+    for disk_file in to_add.iter_mut() {
+        if gone.is_empty() {
+            break;
+        }
+        // Changed in place, not new.
+        if from_db.contains_key(&disk_file.path) || !gone.iter().any(|f| f.size == disk_file.size) {
+            continue;
+        }
+
+        let full_path = sync_path::to_local(base_path, &disk_file.path);
+        let checksum = match &disk_file.checksum {
+            Some(checksum) => checksum.clone(),
+            None => match chunker::file_checksum(&full_path) {
+                Ok(checksum) => checksum,
+                Err(e) => {
+                    debug!("can't compute checksum of {:?}: {}", disk_file.path, e);
+                    continue;
+                }
+            },
+        };
+
+        if let Some(i) = gone
+            .iter()
+            .position(|f| f.size == disk_file.size && f.checksum.as_ref() == Some(&checksum))
+        {
+            disk_file.moved_from = Some(gone.swap_remove(i).path.clone());
+        }
+        disk_file.checksum = Some(checksum);
+    }
+}
+
+enum Comparison {
+    Same,
+    /// Only the mtime moved.
+    Touched,
+    /// With the new checksum if it had to be computed.
+    Changed(Option<String>),
+}
+
+/// Size and mtime settle most cases. The checksum is only looked at when
+/// they can't: the mtime moved but the size didn't (`touch`, a backup tool
+/// restoring files), or the file was written so recently that a same-size
+/// edit within the same second would keep both equal.
+fn compare_content(db_file: &FileRecord, disk_file: &CreateForm, base_path: &Path) -> Comparison {
+    if db_file.size != disk_file.size {
+        return Comparison::Changed(None);
+    }
+
+    // Same path and size, so the mtime is all that can differ.
+    let same_mtime = db_file == disk_file;
+    if same_mtime && !is_racy(disk_file.modified_at) {
+        return Comparison::Same;
+    }
+
+    let Some(stored) = &db_file.checksum else {
+        // Recorded before checksums, size and mtime is all there is.
+        return if same_mtime {
+            Comparison::Same
+        } else {
+            Comparison::Changed(None)
+        };
+    };
+
+    let checksum = match &disk_file.checksum {
+        Some(checksum) => checksum.clone(),
+        None => match chunker::file_checksum(&sync_path::to_local(base_path, &disk_file.path)) {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                debug!("can't compute checksum of {:?}: {}", disk_file.path, e);
+                return Comparison::Changed(None);
+            }
+        },
+    };
+
+    if *stored != checksum {
+        Comparison::Changed(Some(checksum))
+    } else if same_mtime {
+        Comparison::Same
+    } else {
+        Comparison::Touched
+    }
+}
+
+/// Whether the file `record` describes is still on disk with that content,
+/// by the same rules the indexer goes by. False if it's gone or unreadable.
+pub fn unchanged_on_disk(record: &FileRecord, base_path: &Path) -> bool {
+    let full_path = sync_path::to_local(base_path, &record.path);
+    // The format doesn't take part in the comparison.
+    let format = record.format.unwrap_or(FileFormat::Binary);
+
+    match build_file_record(&full_path, base_path, record.namespace_id, format) {
+        Ok(disk_file) => matches!(
+            compare_content(record, &disk_file, base_path),
+            Comparison::Same | Comparison::Touched
+        ),
+        Err(_) => false,
+    }
+}
+
+/// Whether a file modified at `modified_at` could still be written again
+/// within the same second, i.e. without its truncated mtime changing.
+fn is_racy(modified_at: OffsetDateTime) -> bool {
+    OffsetDateTime::now_utc() - modified_at < RACY_WINDOW
+}
+
+fn build_file_record(
+    path: &Path,
+    base: &Path,
+    namespace_id: i32,
+    format: FileFormat,
+) -> Result<CreateForm, SyncError> {
+    let full_path = path;
+    let metadata = full_path
         .metadata()
-        .map_err(|e| SyncError::from_io_error(path, e))?;
+        .map_err(|e| SyncError::from_io_error(full_path, e))?;
     // we assume that it was already checked and only one of these can be now
-    let path = path.strip_prefix(base)?.to_slash_lossy().into_owned();
+    let path = sync_path::from_local(full_path.strip_prefix(base)?)?;
     let size: i64 = metadata.len().try_into()?;
     let time = metadata
         .modified()
         .map_err(|e| SyncError::from_io_error(path.clone(), e))?;
     let modified_at = truncate_to_seconds(OffsetDateTime::from(time));
+    // Later same-second edits can only be told apart by content.
+    let checksum = if is_racy(modified_at) {
+        chunker::file_checksum(full_path).ok()
+    } else {
+        None
+    };
 
     let f = CreateForm {
         jid: None,
@@ -211,6 +667,10 @@ fn build_file_record(path: &Path, base: &Path, namespace_id: i32) -> Result<Crea
         size,
         modified_at,
         namespace_id,
+        format: Some(format),
+        checksum,
+        moved_from: None,
+        chunk_ids: None,
     };
 
     Ok(f)
@@ -300,7 +760,8 @@ mod tests {
         let file_path = nested_dir.join("pates-carbo.cook");
         File::create(&file_path).expect("create file");
 
-        let record = build_file_record(&file_path, base, 1).expect("build_file_record");
+        let record =
+            build_file_record(&file_path, base, 1, FileFormat::Text).expect("build_file_record");
 
         assert!(
             !record.path.contains('\\'),
@@ -330,7 +791,8 @@ mod tests {
         fs::create_dir_all(base.join(".git")).expect("mkdir .git");
         File::create(base.join(".git/config.yaml")).expect("create config");
 
-        let records = get_file_records_from_disk(base, 1).expect("walk");
+        let (records, _) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
 
         assert_eq!(records.len(), 1, "expected exactly one record; got {:?}", records.keys().collect::<Vec<_>>());
         assert!(records.contains_key("recipes/dinner.cook"), "normal recipe must be indexed; got {:?}", records.keys().collect::<Vec<_>>());
@@ -348,7 +810,8 @@ mod tests {
         fs::create_dir_all(base.join("recipes/.cache")).expect("mkdir nested");
         File::create(base.join("recipes/.cache/x.cook")).expect("create nested file");
 
-        let records = get_file_records_from_disk(base, 1).expect("walk");
+        let (records, _) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
         assert!(
             records.is_empty(),
             "nested dot-dir contents must be skipped; got {:?}",
@@ -367,7 +830,8 @@ mod tests {
 
         File::create(base.join(".shopping-list")).expect("create dotfile");
 
-        let records = get_file_records_from_disk(base, 1).expect("walk");
+        let (records, _) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
 
         assert_eq!(records.len(), 1, "expected exactly one record; got {:?}", records.keys().collect::<Vec<_>>());
         assert!(records.contains_key(".shopping-list"), "whitelisted root dotfile must be indexed; got {:?}", records.keys().collect::<Vec<_>>());
@@ -384,9 +848,133 @@ mod tests {
         fs::create_dir_all(&hidden_root).expect("mkdir hidden root");
         File::create(hidden_root.join("r.cook")).expect("create cook in hidden root");
 
-        let records = get_file_records_from_disk(&hidden_root, 1).expect("walk");
+        let (records, _) =
+            get_file_records_from_disk(&hidden_root, 1, &FileFormatRegistry::default())
+                .expect("walk");
 
         assert_eq!(records.len(), 1, "expected exactly one record; got {:?}", records.keys().collect::<Vec<_>>());
         assert!(records.contains_key("r.cook"), "file inside hidden storage root must be indexed; got {:?}", records.keys().collect::<Vec<_>>());
     }
+
+    #[test]
+    fn get_file_records_from_disk_honours_nested_ignore_files() {
+        let tmp = TempDir::new().expect("create tempdir");
+        let base = tmp.path();
+
+        fs::create_dir_all(base.join("drafts")).expect("mkdir drafts");
+        fs::create_dir_all(base.join("photos")).expect("mkdir photos");
+        fs::write(base.join(".cooksyncignore"), "drafts/\n*.menu\n").expect("write root rules");
+        fs::write(base.join("photos/.cooksyncignore"), "*.jpg\n!cover.jpg\n")
+            .expect("write nested rules");
+        for f in [
+            "drafts/a.cook",
+            "week.menu",
+            "photos/a.jpg",
+            "photos/cover.jpg",
+            "soup.cook",
+        ] {
+            File::create(base.join(f)).expect("create file");
+        }
+
+        let (records, rules) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
+
+        let mut paths: Vec<_> = records.keys().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                ".cooksyncignore",
+                "photos/.cooksyncignore",
+                "photos/cover.jpg",
+                "soup.cook"
+            ]
+        );
+        assert!(rules.is_ignored(Path::new("drafts/a.cook"), false));
+    }
+
+    #[test]
+    fn get_file_records_from_disk_keys_files_by_nfc_path() {
+        // A macOS NFD name and a Linux NFC name of the same recipe must end
+        // up as the same path on the server.
+        let tmp = TempDir::new().expect("create tempdir");
+        let base = tmp.path();
+        fs::create_dir_all(base.join("De\u{301}jeuner")).expect("mkdir nfd");
+        File::create(base.join("De\u{301}jeuner/Cre\u{301}pes.cook")).expect("create nfd file");
+
+        let (records, _) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
+
+        assert!(
+            records.contains_key("D\u{e9}jeuner/Cr\u{e9}pes.cook"),
+            "path must be NFC; got {:?}",
+            records.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_file_records_from_disk_skips_non_utf8_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new().expect("create tempdir");
+        let base = tmp.path();
+        File::create(base.join(OsStr::from_bytes(b"bad\xff.cook"))).expect("create non-utf8 file");
+        File::create(base.join("soup.cook")).expect("create cook");
+
+        let (records, _) =
+            get_file_records_from_disk(base, 1, &FileFormatRegistry::default()).expect("walk");
+
+        assert_eq!(records.keys().collect::<Vec<_>>(), vec!["soup.cook"]);
+    }
+
+    #[test]
+    fn in_dot_dir_checks_directories_only() {
+        assert!(in_dot_dir(Path::new(".git/config"), false));
+        assert!(in_dot_dir(Path::new("a/.cache"), true));
+        assert!(!in_dot_dir(Path::new(".shopping-list"), false));
+        assert!(!in_dot_dir(Path::new("recipes/soup.cook"), false));
+    }
+
+    #[test]
+    fn changed_paths_skips_ignored_paths() {
+        use notify_debouncer_mini::DebouncedEventKind;
+
+        let tmp = TempDir::new().expect("create tempdir");
+        let base = tmp.path();
+        fs::write(base.join(".cooksyncignore"), "drafts/\n").expect("write rules");
+        let mut rules = IgnoreRules::new(base);
+        rules.add_dir(base);
+
+        let event = |p: &str| DebouncedEvent {
+            path: base.join(p),
+            kind: DebouncedEventKind::Any,
+        };
+
+        assert_eq!(
+            changed_paths(&Ok(vec![event("drafts/a.cook")]), &rules),
+            Some(vec![])
+        );
+        assert_eq!(
+            changed_paths(
+                &Ok(vec![event("drafts/a.cook"), event("soup.cook")]),
+                &rules
+            ),
+            Some(vec![base.join("soup.cook")])
+        );
+        // Rules changed or the watcher lost track, only a full scan will do.
+        assert_eq!(
+            changed_paths(&Ok(vec![event(".cooksyncignore")]), &rules),
+            None
+        );
+        assert_eq!(changed_paths(&Ok(vec![]), &rules), None);
+        assert_eq!(
+            changed_paths(
+                &Err(notify_debouncer_mini::notify::Error::generic("overflow")),
+                &rules
+            ),
+            None
+        );
+    }
 }
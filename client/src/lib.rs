@@ -2,32 +2,45 @@ use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use futures::{channel::mpsc::channel, try_join};
 use notify::RecursiveMode;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 use tokio::sync::Mutex;
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::chunker::{Chunker, InMemoryCache};
+use crate::chunk_store::DiskChunkStore;
+use crate::chunker::{remove_temp_files, Chunker};
 use crate::file_watcher::async_watcher;
 use crate::indexer::check_index_once;
-use crate::syncer::{check_download_once, check_upload_once};
+use crate::syncer::{
+    check_download_once, check_encryption_allowed, check_upload_once, recover_interrupted_download,
+};
 
 const CHANNEL_SIZE: usize = 100;
-const INMEMORY_CACHE_MAX_REC: usize = 100000;
-const INMEMORY_CACHE_MAX_MEM: u64 = 100_000_000_000;
+const CHUNK_STORE_MAX_BYTES: u64 = 512 * 1024 * 1024; // 512 MB
 
+pub mod backoff;
+pub mod bandwidth;
+pub mod chunk_id;
+pub mod chunk_store;
 pub mod chunker;
+pub mod compactor;
 pub mod connection;
 pub mod context;
+pub mod control;
+pub mod crypto;
 pub mod errors;
+pub mod file_format;
 pub mod file_watcher;
+pub mod ignore_rules;
 pub mod indexer;
+pub mod merge;
 pub mod models;
 pub mod registry;
 pub mod remote;
 pub mod schema;
+pub mod sync_path;
 pub mod syncer;
 
 // Export SyncStatus and context types for external use
@@ -51,6 +64,49 @@ pub fn extract_uid_from_jwt(token: &str) -> i32 {
     claims.uid
 }
 
+/// Opens the persistent chunk store that lives next to the DB file,
+/// e.g. `sync.db` keeps its chunks in `sync.chunks/`.
+fn open_chunk_store(db_file_path: &str) -> Result<DiskChunkStore, errors::SyncError> {
+    DiskChunkStore::open(
+        Path::new(db_file_path).with_extension("chunks"),
+        CHUNK_STORE_MAX_BYTES,
+    )
+}
+
+/// Drops temp files of downloads interrupted by a crash or a kill. Failing
+/// to do so isn't fatal as the indexer ignores them anyway.
+fn remove_interrupted_downloads(storage_dir: &Path) {
+    match remove_temp_files(storage_dir) {
+        Ok(0) => {}
+        Ok(n) => debug!("Removed {} interrupted downloads in {:?}", n, storage_dir),
+        Err(e) => warn!("Failed to remove interrupted downloads: {}", e),
+    }
+}
+
+/// Chunker set up with the formats, passphrase and device name configured
+/// on `context`, so every entry point chunks files the same way.
+fn new_chunker(
+    context: &SyncContext,
+    storage_dir: &Path,
+    db_file_path: &str,
+) -> Result<Chunker, errors::SyncError> {
+    let mut chunker = Chunker::new(open_chunk_store(db_file_path)?, storage_dir.to_path_buf());
+    chunker.set_formats(context.formats());
+    chunker.set_cipher(context.cipher());
+    chunker.set_device_name(context.device_name());
+
+    Ok(chunker)
+}
+
+/// Remote client following the network settings on `context`.
+fn new_remote(context: &SyncContext, api_endpoint: &str, remote_token: &str) -> remote::Remote {
+    let mut remote = remote::Remote::new(api_endpoint, remote_token);
+    remote.set_upload_concurrency(context.upload_concurrency());
+    remote.set_network(context.network());
+
+    remote
+}
+
 #[cfg(feature = "ffi")]
 uniffi::setup_scaffolding!();
 
@@ -90,11 +146,24 @@ pub fn wait_remote_update(api_endpoint: &str, remote_token: &str) -> Result<(),
     Ok(())
 }
 
-/// Runs one-off download of updates from remote server.
+/// Drops superseded rows from the local registry. `run` already does this
+/// periodically, this is for apps that only use the one-off functions.
+#[cfg_attr(feature = "ffi", uniffi::export)]
+pub fn compact_registry(db_file_path: &str, namespace_id: i32) -> Result<(), errors::SyncError> {
+    let pool = connection::get_connection_pool(db_file_path)?;
+
+    compactor::compact_once(&pool, namespace_id)?;
+
+    Ok(())
+}
+
+/// Runs one-off download of updates from remote server, with the formats,
+/// passphrase and network settings configured on `context`.
 /// Note, it's not very efficient as requires to re-initialize DB connection,
 /// chunker, remote client, etc every time it runs.
 #[cfg_attr(feature = "ffi", uniffi::export)]
 pub fn run_download_once(
+    context: Arc<SyncContext>,
     storage_dir: &str,
     db_file_path: &str,
     api_endpoint: &str,
@@ -106,29 +175,36 @@ pub fn run_download_once(
     env::set_var("CARGO_LOG", "trace");
 
     let storage_dir = &PathBuf::from(storage_dir);
-    let chunk_cache = InMemoryCache::new(INMEMORY_CACHE_MAX_REC, INMEMORY_CACHE_MAX_MEM);
-    let chunker = Arc::new(Mutex::new(Chunker::new(chunk_cache, storage_dir.clone())));
-    let remote = &remote::Remote::new(api_endpoint, remote_token);
+    let formats = context.formats();
+    let chunker = new_chunker(&context, storage_dir, db_file_path)?;
+    let chunker = Arc::new(Mutex::new(chunker));
+    let remote = &new_remote(&context, api_endpoint, remote_token);
 
     let pool = connection::get_connection_pool(db_file_path)?;
     debug!("Started connection pool for {:?}", db_file_path);
 
+    remove_interrupted_downloads(storage_dir);
+    recover_interrupted_download(&pool, storage_dir, namespace_id, &formats)?;
+
     Runtime::new()?.block_on(check_download_once(
         &pool,
         Arc::clone(&chunker),
         remote,
         storage_dir,
         namespace_id,
+        None,
     ))?;
 
     Ok(())
 }
 
-/// Runs one-off upload of updates to remote server.
+/// Runs one-off upload of updates to remote server, with the formats,
+/// passphrase and network settings configured on `context`.
 /// Note, it's not very efficient as requires to re-initialize DB connection,
 /// chunker, remote client, etc every time it runs.
 #[cfg_attr(feature = "ffi", uniffi::export)]
 pub fn run_upload_once(
+    context: Arc<SyncContext>,
     storage_dir: &str,
     db_file_path: &str,
     api_endpoint: &str,
@@ -136,30 +212,40 @@ pub fn run_upload_once(
     namespace_id: i32,
 ) -> Result<(), errors::SyncError> {
     let storage_dir = &PathBuf::from(storage_dir);
-    let chunk_cache = InMemoryCache::new(INMEMORY_CACHE_MAX_REC, INMEMORY_CACHE_MAX_MEM);
-    let chunker = Arc::new(Mutex::new(Chunker::new(chunk_cache, storage_dir.clone())));
-    let remote = &remote::Remote::new(api_endpoint, remote_token);
+    let formats = context.formats();
+    let chunker = new_chunker(&context, storage_dir, db_file_path)?;
+    let chunker = Arc::new(Mutex::new(chunker));
+    let remote = &new_remote(&context, api_endpoint, remote_token);
 
     let pool = connection::get_connection_pool(db_file_path)?;
     debug!("Started connection pool for {:?}", db_file_path);
 
-    check_index_once(&pool, storage_dir, namespace_id)?;
+    remove_interrupted_downloads(storage_dir);
+    recover_interrupted_download(&pool, storage_dir, namespace_id, &formats)?;
+
+    check_index_once(&pool, storage_dir, namespace_id, &formats)?;
 
     let runtime = Runtime::new()?;
+    runtime.block_on(check_encryption_allowed(&chunker, remote))?;
 
-    // It requires first pass to upload missing chunks and second to
-    // commit and update `jid` to local records.
+    // Small files go up with their commit. Bigger ones need a first pass
+    // to upload missing chunks and second to commit and update `jid` to
+    // local records.
     if !runtime.block_on(check_upload_once(
         &pool,
         Arc::clone(&chunker),
         remote,
+        storage_dir,
         namespace_id,
+        None,
     ))? {
         runtime.block_on(check_upload_once(
             &pool,
             Arc::clone(&chunker),
             remote,
+            storage_dir,
             namespace_id,
+            None,
         ))?;
     }
 
@@ -178,6 +264,7 @@ pub async fn run_async(
     download_only: bool,
 ) -> Result<(), errors::SyncError> {
     let token = context.token();
+    let control = context.control();
     let listener = context.listener();
 
     // Initialize all components first
@@ -185,13 +272,16 @@ pub async fn run_async(
     let (local_registry_updated_tx, local_registry_updated_rx) = channel(CHANNEL_SIZE);
 
     let storage_dir = &PathBuf::from(storage_dir);
-    let chunk_cache = InMemoryCache::new(INMEMORY_CACHE_MAX_REC, INMEMORY_CACHE_MAX_MEM);
-    let chunker = Chunker::new(chunk_cache, storage_dir.clone());
-    let remote = &remote::Remote::new(api_endpoint, remote_token);
+    let formats = context.formats();
+    let chunker = new_chunker(&context, storage_dir, db_file_path)?;
+    let remote = &new_remote(&context, api_endpoint, remote_token);
 
     let pool = connection::get_connection_pool(db_file_path)?;
     debug!("Started connection pool for {:?}", db_file_path);
 
+    remove_interrupted_downloads(storage_dir);
+    recover_interrupted_download(&pool, storage_dir, namespace_id, &formats)?;
+
     if !download_only {
         debouncer
             .watcher()
@@ -206,10 +296,12 @@ pub async fn run_async(
 
     let indexer = indexer::run(
         token.clone(),
+        &control,
         listener.clone(),
         &pool,
         storage_dir,
         namespace_id,
+        &formats,
         local_file_update_rx,
         local_registry_updated_tx,
     );
@@ -217,6 +309,7 @@ pub async fn run_async(
 
     let syncer = syncer::run(
         token.clone(),
+        &control,
         listener.clone(),
         &pool,
         storage_dir,
@@ -228,7 +321,9 @@ pub async fn run_async(
     );
     debug!("Started syncer");
 
-    let result = try_join!(indexer, syncer);
+    let compactor = compactor::run(token.clone(), &control, &pool, namespace_id);
+
+    let result = try_join!(indexer, syncer, compactor);
 
     // Notify completion (on_complete includes success status and optional error message)
     if let Some(ref cb) = listener {
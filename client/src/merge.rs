@@ -0,0 +1,159 @@
+//! Three-way merge of chunk sequences.
+//!
+//! Text files are chunked by line, so merging their chunk IDs merges their
+//! lines. Both sides are diffed against the common base and their changes
+//! applied together, as long as they don't touch the same or adjacent
+//! lines. Like `git merge`, changes right next to each other are treated as
+//! a conflict rather than guessed at.
+
+use std::hash::Hash;
+use std::ops::Range;
+
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+/// Base lines in `base` replaced with `with` on one side.
+struct Hunk<'a, T> {
+    base: Range<usize>,
+    with: &'a [T],
+}
+
+/// `base` with the changes of both `ours` and `theirs`, or `None` if they
+/// overlap. Changes made on both sides in exactly the same way are fine.
+pub fn three_way<T: Hash + Ord + Clone>(base: &[T], ours: &[T], theirs: &[T]) -> Option<Vec<T>> {
+    let ours = hunks(base, ours);
+    let theirs = hunks(base, theirs);
+
+    let mut merged = Vec::with_capacity(base.len());
+    let mut done = 0;
+    let (mut o, mut t) = (0, 0);
+
+    loop {
+        let hunk = match (ours.get(o), theirs.get(t)) {
+            (None, None) => break,
+            (Some(a), Some(b)) if a.base == b.base && a.with == b.with => {
+                o += 1;
+                t += 1;
+                a
+            }
+            (Some(a), Some(b)) if touch(&a.base, &b.base) => return None,
+            (Some(a), Some(b)) if a.base.start < b.base.start => {
+                o += 1;
+                a
+            }
+            (Some(a), None) => {
+                o += 1;
+                a
+            }
+            (_, Some(b)) => {
+                t += 1;
+                b
+            }
+        };
+
+        merged.extend_from_slice(&base[done..hunk.base.start]);
+        merged.extend_from_slice(hunk.with);
+        done = hunk.base.end;
+    }
+
+    merged.extend_from_slice(&base[done..]);
+
+    Some(merged)
+}
+
+/// Changes from `base` to `changed`, in order. Changes that are right next
+/// to each other are joined into one hunk.
+fn hunks<'a, T: Hash + Ord>(base: &[T], changed: &'a [T]) -> Vec<Hunk<'a, T>> {
+    let mut ranges: Vec<(Range<usize>, Range<usize>)> = vec![];
+
+    for op in capture_diff_slices(Algorithm::Myers, base, changed) {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some((b, c)) if b.end == op.old_range().start => {
+                b.end = op.old_range().end;
+                c.end = op.new_range().end;
+            }
+            _ => ranges.push((op.old_range(), op.new_range())),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(base, c)| Hunk {
+            base,
+            with: &changed[c],
+        })
+        .collect()
+}
+
+/// Whether two base ranges overlap or are right next to each other. Two
+/// insertions at the same place touch too, their order would be a guess.
+fn touch(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<&str> {
+        s.split_inclusive('\n').collect()
+    }
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Option<String> {
+        three_way(&lines(base), &lines(ours), &lines(theirs)).map(|m| m.concat())
+    }
+
+    const BASE: &str = "title: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+
+    #[test]
+    fn changes_to_different_lines_are_merged() {
+        let ours = "title: Pancakes\n\nMix @flour{250%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+        let theirs =
+            "title: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{2}.\nFry.\n\nServe with @syrup.\n";
+
+        assert_eq!(
+            merge(BASE, ours, theirs).unwrap(),
+            "title: Pancakes\n\nMix @flour{250%g}.\nAdd @eggs{2}.\nFry.\n\nServe with @syrup.\n"
+        );
+    }
+
+    #[test]
+    fn insertions_and_deletions_are_merged() {
+        let ours = ">> servings: 4\ntitle: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+        let theirs = "title: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{2}.\nFry.\n";
+
+        assert_eq!(
+            merge(BASE, ours, theirs).unwrap(),
+            ">> servings: 4\ntitle: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{2}.\nFry.\n"
+        );
+    }
+
+    #[test]
+    fn same_change_on_both_sides_is_taken_once() {
+        let changed = "title: Pancakes\n\nMix @flour{250%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+
+        assert_eq!(merge(BASE, changed, changed).unwrap(), changed);
+    }
+
+    #[test]
+    fn changes_to_the_same_or_adjacent_lines_conflict() {
+        let ours = "title: Pancakes\n\nMix @flour{250%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+        let same_line = "title: Pancakes\n\nMix @flour{300%g}.\nAdd @eggs{2}.\nFry.\n\nServe.\n";
+        let next_line = "title: Pancakes\n\nMix @flour{200%g}.\nAdd @eggs{3}.\nFry.\n\nServe.\n";
+
+        assert_eq!(merge(BASE, ours, same_line), None);
+        assert_eq!(merge(BASE, ours, next_line), None);
+    }
+
+    #[test]
+    fn unchanged_side_gives_the_other() {
+        let theirs = "title: Crêpes\n\nMix @flour{200%g}.\n";
+
+        assert_eq!(merge(BASE, BASE, theirs).unwrap(), theirs);
+        assert_eq!(merge(BASE, theirs, BASE).unwrap(), theirs);
+        assert_eq!(merge("", "a\n", "").unwrap(), "a\n");
+    }
+}
@@ -1,11 +1,13 @@
-use crate::schema::file_records;
+use crate::schema::{deferred_downloads, download_intents, file_records};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use diesel::deserialize::FromSqlRow;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
 use diesel::expression::AsExpression;
+use diesel::serialize::{self, IsNull, Output, ToSql};
 use diesel::sql_types::Integer;
+use diesel::sqlite::{Sqlite, SqliteValue};
 
 #[repr(i32)]
 #[derive(Debug, Clone, Copy, AsExpression, FromSqlRow, PartialEq, Deserialize, Serialize)]
@@ -15,6 +17,23 @@ pub enum FileFormat {
     Text = 2,
 }
 
+impl ToSql<Integer, Sqlite> for FileFormat {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(*self as i32);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Integer, Sqlite> for FileFormat {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match i32::from_sql(bytes)? {
+            1 => Ok(FileFormat::Binary),
+            2 => Ok(FileFormat::Text),
+            x => Err(format!("Unrecognized file format {}", x).into()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum IndexerUpdateEvent {
     Updated,
@@ -31,6 +50,16 @@ pub struct FileRecord {
     pub size: i64,
     pub modified_at: OffsetDateTime,
     pub namespace_id: i32,
+    pub format: Option<FileFormat>,
+    /// BLAKE3 of the content, only known when it had to be computed or
+    /// came for free, see `chunker::file_checksum`.
+    pub checksum: Option<String>,
+    /// Path of a deleted file with the same content, found in the same
+    /// scan. Uploaded as a move of that file rather than a new one.
+    pub moved_from: Option<String>,
+    /// Chunk IDs of this version on the server, known once it's synced.
+    /// The base for merging edits made here and elsewhere.
+    pub chunk_ids: Option<String>,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -43,6 +72,16 @@ pub struct CreateForm {
     pub size: i64,
     pub modified_at: OffsetDateTime,
     pub namespace_id: i32,
+    pub format: Option<FileFormat>,
+    /// BLAKE3 of the content, only known when it had to be computed or
+    /// came for free, see `chunker::file_checksum`.
+    pub checksum: Option<String>,
+    /// Path of a deleted file with the same content, found in the same
+    /// scan. Uploaded as a move of that file rather than a new one.
+    pub moved_from: Option<String>,
+    /// Chunk IDs of this version on the server, known once it's synced.
+    /// The base for merging edits made here and elsewhere.
+    pub chunk_ids: Option<String>,
 }
 
 #[derive(Insertable, Debug, Clone)]
@@ -81,6 +120,34 @@ pub struct FileRecordDeleteForm {
     pub namespace_id: i32,
 }
 
+/// A remote record a download is about to write to disk, see
+/// `registry::begin_download`.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = download_intents)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DownloadIntent {
+    pub namespace_id: i32,
+    pub jid: i32,
+    pub path: String,
+    pub deleted: bool,
+    pub chunk_ids: String,
+    /// BLAKE3 of the content, `None` for deletes.
+    pub checksum: Option<String>,
+}
+
+/// A remote file left for later on a metered network, paths as they are
+/// stored locally.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = deferred_downloads)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct DeferredDownload {
+    pub namespace_id: i32,
+    pub jid: i32,
+    pub path: String,
+    pub chunk_ids: String,
+    pub moved_from: Option<String>,
+}
+
 impl PartialEq<CreateForm> for FileRecord {
     fn eq(&self, other: &CreateForm) -> bool {
         self.path == other.path && self.size == other.size && self.modified_at == other.modified_at
@@ -103,6 +170,26 @@ pub enum SyncStatus {
     Uploading,
     /// An error occurred during sync
     Error { message: String },
+    /// The server can't be reached, e.g. there's no network. Sync carries
+    /// on by itself once it's back.
+    Offline,
+    /// A request failed for a reason that's likely to pass, e.g. a server
+    /// error. It's tried again in `delay_secs`, `attempt` times so far.
+    Retrying { attempt: u32, delay_secs: u64 },
+    /// Paused with `SyncContext::pause`, until `resume` is called.
+    Paused,
+    /// A downloaded file only differs in letter case from one that's already
+    /// there, and the filesystem can't tell them apart. It was saved as
+    /// `local_path` instead of `path`. Updates of `path` keep landing there,
+    /// edits made to it get uploaded as a new file.
+    CaseConflict { path: String, local_path: String },
+    /// A file was changed here and on another device since it was last
+    /// synced. The other version is now at `path`, the local one was kept
+    /// as `conflicted_copy` and gets uploaded as a new file.
+    Conflict {
+        path: String,
+        conflicted_copy: String,
+    },
 }
 
 #[cfg(test)]
@@ -123,6 +210,35 @@ mod tests {
         assert_ne!(FileFormat::Binary, FileFormat::Text);
     }
 
+    #[test]
+    fn file_format_round_trips_through_sqlite() {
+        use diesel::connection::SimpleConnection;
+        use diesel::sql_types::Nullable;
+        use diesel::sqlite::SqliteConnection;
+
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = Nullable<Integer>)]
+            format: Option<FileFormat>,
+        }
+
+        let conn = &mut SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute("CREATE TABLE t (format INTEGER)").unwrap();
+
+        for format in [Some(FileFormat::Text), Some(FileFormat::Binary), None] {
+            diesel::sql_query("DELETE FROM t").execute(conn).unwrap();
+            diesel::sql_query("INSERT INTO t (format) VALUES (?)")
+                .bind::<Nullable<Integer>, _>(format)
+                .execute(conn)
+                .unwrap();
+
+            let row: Row = diesel::sql_query("SELECT format FROM t")
+                .get_result(conn)
+                .unwrap();
+            assert_eq!(row.format, format);
+        }
+    }
+
     #[test]
     fn test_file_record_create_form_equality() {
         let now = OffsetDateTime::now_utc();
@@ -135,6 +251,10 @@ mod tests {
             size: 1024,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         let form = CreateForm {
@@ -144,6 +264,10 @@ mod tests {
             size: 1024,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         // Should be equal based on path, size, and modified_at
@@ -162,6 +286,10 @@ mod tests {
             size: 1024,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         let form = CreateForm {
@@ -171,6 +299,10 @@ mod tests {
             size: 1024,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         // Should not be equal due to different paths
@@ -189,6 +321,10 @@ mod tests {
             size: 1024,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         let form = CreateForm {
@@ -198,6 +334,10 @@ mod tests {
             size: 2048,
             modified_at: now,
             namespace_id: 1,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         // Should not be equal due to different sizes
@@ -215,6 +355,10 @@ mod tests {
             size: 512,
             modified_at: now,
             namespace_id: 5,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
 
         assert_eq!(form.jid, Some(42));
@@ -265,6 +409,19 @@ mod tests {
         let error = SyncStatus::Error {
             message: "Test error".to_string(),
         };
+        let case_conflict = SyncStatus::CaseConflict {
+            path: "pasta.cook".to_string(),
+            local_path: "pasta (Case Conflict 1a2b3c4d).cook".to_string(),
+        };
+        let offline = SyncStatus::Offline;
+        let retrying = SyncStatus::Retrying {
+            attempt: 2,
+            delay_secs: 4,
+        };
+        let conflict = SyncStatus::Conflict {
+            path: "Pancakes.cook".to_string(),
+            conflicted_copy: "Pancakes (conflicted copy from Pixel).cook".to_string(),
+        };
 
         // Just verify they can be constructed
         assert!(matches!(idle, SyncStatus::Idle));
@@ -273,5 +430,9 @@ mod tests {
         assert!(matches!(downloading, SyncStatus::Downloading));
         assert!(matches!(uploading, SyncStatus::Uploading));
         assert!(matches!(error, SyncStatus::Error { .. }));
+        assert!(matches!(case_conflict, SyncStatus::CaseConflict { .. }));
+        assert!(matches!(offline, SyncStatus::Offline));
+        assert!(matches!(retrying, SyncStatus::Retrying { attempt: 2, .. }));
+        assert!(matches!(conflict, SyncStatus::Conflict { .. }));
     }
 }
@@ -1,6 +1,6 @@
 use diesel::dsl::{max, sql};
 use diesel::prelude::*;
-use diesel::{insert_into, update};
+use diesel::{insert_into, update, Connection as _};
 
 use log::trace;
 
@@ -25,12 +25,145 @@ pub fn update_jid(conn: &mut Connection, record: &FileRecord, jid: i32) -> Resul
         .execute(conn)
 }
 
+pub fn update_checksum(
+    conn: &mut Connection,
+    record: &FileRecord,
+    checksum: Option<&str>,
+) -> Result<usize> {
+    trace!("update_checksum {:?}: {:?}", checksum, record);
+
+    update(file_records::table)
+        .filter(file_records::id.eq(record.id))
+        .set(file_records::checksum.eq(checksum))
+        .execute(conn)
+}
+
+pub fn update_chunk_ids(
+    conn: &mut Connection,
+    record: &FileRecord,
+    chunk_ids: &str,
+) -> Result<usize> {
+    trace!("update_chunk_ids {:?}: {:?}", chunk_ids, record);
+
+    update(file_records::table)
+        .filter(file_records::id.eq(record.id))
+        .set(file_records::chunk_ids.eq(chunk_ids))
+        .execute(conn)
+}
+
+/// Refreshes size and mtime of a file whose content didn't change, e.g.
+/// after a `touch`.
+pub fn update_metadata(
+    conn: &mut Connection,
+    record: &FileRecord,
+    form: &FileRecordUpdateForm,
+) -> Result<usize> {
+    trace!("update_metadata {:?}: {:?}", form, record);
+
+    update(file_records::table)
+        .filter(file_records::id.eq(record.id))
+        .set(form)
+        .execute(conn)
+}
+
 pub fn delete(conn: &mut Connection, forms: &[DeleteForm]) -> Result<usize> {
     trace!("marking as deleted {:?}", forms);
 
     insert_into(file_records::table).values(forms).execute(conn)
 }
 
+/// Records what downloaded files are about to write to disk, before they
+/// touch anything.
+pub fn begin_download(conn: &mut Connection, intents: &[DownloadIntent]) -> Result<usize> {
+    trace!("begin_download {:?}", intents);
+
+    insert_into(download_intents::table)
+        .values(intents)
+        .execute(conn)
+}
+
+/// Registry rows of a download batch, written in one transaction with
+/// dropping the intents of `jids`, so that either both happen or neither.
+/// Files of `jids` that were left for later are done too.
+pub fn finish_download(
+    conn: &mut Connection,
+    namespace_id: i32,
+    jids: &[i32],
+    created: &[CreateForm],
+    deleted: &[DeleteForm],
+) -> Result<()> {
+    trace!("finish_download {:?}", jids);
+
+    conn.transaction(|conn| {
+        create(conn, created)?;
+        delete(conn, deleted)?;
+
+        diesel::delete(download_intents::table)
+            .filter(download_intents::namespace_id.eq(namespace_id))
+            .filter(download_intents::jid.eq_any(jids))
+            .execute(conn)?;
+        diesel::delete(deferred_downloads::table)
+            .filter(deferred_downloads::namespace_id.eq(namespace_id))
+            .filter(deferred_downloads::jid.eq_any(jids))
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Remembers remote files to download later, in place of anything left
+/// for later of the same paths before.
+pub fn defer_download(conn: &mut Connection, deferred: &[DeferredDownload]) -> Result<usize> {
+    trace!("defer_download {:?}", deferred);
+
+    conn.transaction(|conn| {
+        for d in deferred {
+            drop_deferred(conn, d.namespace_id, &d.path)?;
+        }
+
+        insert_into(deferred_downloads::table)
+            .values(deferred)
+            .execute(conn)
+    })
+}
+
+/// Files left for later, in commit order.
+pub fn deferred_downloads(
+    conn: &mut Connection,
+    namespace_id: i32,
+) -> Result<Vec<DeferredDownload>> {
+    trace!("deferred_downloads");
+
+    deferred_downloads::table
+        .filter(deferred_downloads::namespace_id.eq(namespace_id))
+        .select(DeferredDownload::as_select())
+        .order(deferred_downloads::jid.asc())
+        .load(conn)
+}
+
+/// Forgets the file left for later at `path`, e.g. because a newer version
+/// of it came in meanwhile.
+pub fn drop_deferred(conn: &mut Connection, namespace_id: i32, path: &str) -> Result<usize> {
+    trace!("drop_deferred {:?}", path);
+
+    diesel::delete(deferred_downloads::table)
+        .filter(deferred_downloads::namespace_id.eq(namespace_id))
+        .filter(deferred_downloads::path.eq(path))
+        .execute(conn)
+}
+
+/// Intents of downloads that haven't finished, in the order they were
+/// applied.
+pub fn download_intents(conn: &mut Connection, namespace_id: i32) -> Result<Vec<DownloadIntent>> {
+    trace!("download_intents");
+
+    download_intents::table
+        .filter(download_intents::namespace_id.eq(namespace_id))
+        .select(DownloadIntent::as_select())
+        .order((download_intents::jid.asc(), download_intents::id.asc()))
+        .load(conn)
+}
+
 pub fn non_deleted(conn: &mut Connection, namespace_id: i32) -> Result<Vec<FileRecord>> {
     trace!("non_deleted");
 
@@ -50,6 +183,38 @@ pub fn non_deleted(conn: &mut Connection, namespace_id: i32) -> Result<Vec<FileR
         .load::<FileRecord>(conn)
 }
 
+/// Like `non_deleted`, only `path` and what's under it if it's a directory.
+pub fn non_deleted_under(
+    conn: &mut Connection,
+    namespace_id: i32,
+    path: &str,
+) -> Result<Vec<FileRecord>> {
+    trace!("non_deleted_under {:?}", path);
+
+    // Everything between "dir/" and "dir0" ('/' + 1) is under "dir". Unlike
+    // LIKE, this is case-sensitive and can use the path index.
+    let in_path = file_records::path
+        .eq(path.to_string())
+        .or(file_records::path
+            .ge(format!("{}/", path))
+            .and(file_records::path.lt(format!("{}0", path))));
+
+    let subquery = file_records::table
+        .filter(file_records::namespace_id.eq(namespace_id))
+        .filter(in_path.clone())
+        .group_by(file_records::path)
+        .select(max(file_records::id))
+        .into_boxed()
+        .select(sql::<diesel::sql_types::Integer>("max(id)"));
+
+    file_records::table
+        .filter(file_records::deleted.eq(false))
+        .filter(file_records::id.eq_any(subquery))
+        .select(FileRecord::as_select())
+        .order(file_records::id.asc())
+        .load::<FileRecord>(conn)
+}
+
 /// Files that don't have jid
 /// These should be send to remote
 pub fn updated_locally(conn: &mut Connection, namespace_id: i32) -> Result<Vec<FileRecord>> {
@@ -73,6 +238,42 @@ pub fn updated_locally(conn: &mut Connection, namespace_id: i32) -> Result<Vec<F
     query.load::<FileRecord>(conn)
 }
 
+/// Latest record of `path`, synced or not.
+pub fn latest_for_path(
+    conn: &mut Connection,
+    namespace_id: i32,
+    path: &str,
+) -> Result<Option<FileRecord>> {
+    trace!("latest_for_path {:?}", path);
+
+    file_records::table
+        .filter(file_records::namespace_id.eq(namespace_id))
+        .filter(file_records::path.eq(path))
+        .select(FileRecord::as_select())
+        .order(file_records::id.desc())
+        .first::<FileRecord>(conn)
+        .optional()
+}
+
+/// Latest record of `path` that the server knows about, i.e. with a jid.
+/// Its `jid` is the version local edits of `path` are based on.
+pub fn latest_synced(
+    conn: &mut Connection,
+    namespace_id: i32,
+    path: &str,
+) -> Result<Option<FileRecord>> {
+    trace!("latest_synced {:?}", path);
+
+    file_records::table
+        .filter(file_records::namespace_id.eq(namespace_id))
+        .filter(file_records::path.eq(path))
+        .filter(file_records::jid.is_not_null())
+        .select(FileRecord::as_select())
+        .order(file_records::id.desc())
+        .first::<FileRecord>(conn)
+        .optional()
+}
+
 pub fn latest_jid(conn: &mut Connection, namespace_id: i32) -> Result<i32> {
     trace!("latest_jid");
 
@@ -88,3 +289,62 @@ pub fn latest_jid(conn: &mut Connection, namespace_id: i32) -> Result<i32> {
         Err(e) => Err(e),
     }
 }
+
+/// Collapses the history of every path to its latest synced row plus a
+/// newer unsynced one if any, and drops paths whose latest row is a
+/// tombstone the server already acknowledged. Creating one of them again
+/// goes up with parent jid 0, which the server takes on top of a deletion.
+/// The row carrying the highest `jid` is always kept, so `latest_jid`
+/// doesn't go back.
+/// Returns the number of removed rows.
+pub fn compact(conn: &mut Connection, namespace_id: i32) -> Result<usize> {
+    trace!("compact");
+
+    conn.transaction(|conn| {
+        let keep_jid_row = file_records::table
+            .filter(file_records::namespace_id.eq(namespace_id))
+            .filter(file_records::jid.is_not_null())
+            .order(file_records::jid.desc())
+            .select(file_records::id)
+            .first::<i32>(conn)
+            .optional()?
+            .unwrap_or(0);
+
+        let latest = file_records::table
+            .filter(file_records::namespace_id.eq(namespace_id))
+            .group_by(file_records::path)
+            .select(max(file_records::id))
+            .into_boxed()
+            .select(sql::<diesel::sql_types::Integer>("max(id)"));
+
+        let latest_synced = file_records::table
+            .filter(file_records::namespace_id.eq(namespace_id))
+            .filter(file_records::jid.is_not_null())
+            .group_by(file_records::path)
+            .select(max(file_records::id))
+            .into_boxed()
+            .select(sql::<diesel::sql_types::Integer>("max(id)"));
+
+        let superseded = diesel::delete(
+            file_records::table
+                .filter(file_records::namespace_id.eq(namespace_id))
+                .filter(file_records::id.ne(keep_jid_row))
+                .filter(file_records::id.ne_all(latest))
+                .filter(file_records::id.ne_all(latest_synced)),
+        )
+        .execute(conn)?;
+
+        // Once the server has a deletion, the local tombstone only hides
+        // the path's older rows, which the pass above already removed.
+        let acknowledged_tombstones = diesel::delete(
+            file_records::table
+                .filter(file_records::namespace_id.eq(namespace_id))
+                .filter(file_records::id.ne(keep_jid_row))
+                .filter(file_records::deleted.eq(true))
+                .filter(file_records::jid.is_not_null()),
+        )
+        .execute(conn)?;
+
+        Ok(superseded + acknowledged_tombstones)
+    })
+}
@@ -1,7 +1,7 @@
 use futures::{Stream, StreamExt};
-use path_slash::PathExt as _;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
+use std::sync::Arc;
 use uuid::Uuid;
 
 use log::trace;
@@ -9,7 +9,9 @@ use log::trace;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, USER_AGENT};
 use reqwest::{Client, StatusCode};
 
+use crate::bandwidth::NetworkPolicy;
 use crate::errors::SyncError;
+use crate::sync_path;
 
 /// User-Agent sent on every request, e.g. "cooklang-sync-client/0.4.11".
 /// Lets the server identify the client version in logs when diagnosing
@@ -21,6 +23,10 @@ const CLIENT_VERSION_HEADER: &str = "x-client-version";
 type Result<T, E = SyncError> = std::result::Result<T, E>;
 
 pub const REQUEST_TIMEOUT_SECS: u64 = 60;
+/// Request bodies are paced to the upload limit in slices of this size.
+const METERED_SLICE_SIZE: usize = 64 * 1024;
+/// Chunk upload requests that run at the same time, unless set otherwise.
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ResponseFileRecord {
@@ -28,12 +34,47 @@ pub struct ResponseFileRecord {
     pub path: String,
     pub deleted: bool,
     pub chunk_ids: String,
+    /// Where the file was before, if it got here through a move.
+    #[serde(default)]
+    pub moved_from: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum CommitResultStatus {
     Success(i32),
     NeedChunks(String),
+    /// The path was committed again since the parent jid, with the jid,
+    /// chunk IDs and deleted flag of the latest version.
+    Conflict(i32, String, bool),
+}
+
+/// A file committed with `commit_batch`, like with `commit`.
+#[derive(Debug, Clone)]
+pub struct CommitEntry {
+    pub path: String,
+    pub deleted: bool,
+    pub chunk_ids: String,
+    pub parent_jid: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum CommitBatchResultStatus {
+    /// jids of the entries, in order.
+    Success(Vec<i32>),
+    /// Chunks missing for any of the entries, none was committed.
+    NeedChunks(String),
+    /// Entries committed again since their parent jid, by index, with the
+    /// jid, chunk IDs and deleted flag of the latest version. None was
+    /// committed.
+    Conflict(Vec<(usize, i32, String, bool)>),
+}
+
+/// `CommitBatchResultStatus` as the server sends it, conflicts by path.
+#[derive(Debug, Deserialize)]
+enum ServerCommitBatchResultStatus {
+    Success(Vec<i32>),
+    NeedChunks(String),
+    Conflict(Vec<(String, i32, String, bool)>),
 }
 
 pub struct Remote {
@@ -41,6 +82,8 @@ pub struct Remote {
     token: String,
     uuid: String,
     client: Client,
+    upload_concurrency: usize,
+    network: Arc<NetworkPolicy>,
 }
 
 impl Remote {
@@ -64,8 +107,29 @@ impl Remote {
             uuid: Uuid::new_v4().into(),
             token: token.into(),
             client,
+            upload_concurrency: DEFAULT_UPLOAD_CONCURRENCY,
+            network: Arc::new(NetworkPolicy::default()),
         }
     }
+
+    /// Limits how many chunk upload requests run at the same time, at least
+    /// one.
+    pub fn set_upload_concurrency(&mut self, limit: usize) {
+        self.upload_concurrency = limit.max(1);
+    }
+
+    pub fn upload_concurrency(&self) -> usize {
+        self.upload_concurrency
+    }
+
+    /// Follows the bandwidth limits and metering of `network` from now on.
+    pub fn set_network(&mut self, network: Arc<NetworkPolicy>) {
+        self.network = network;
+    }
+
+    pub fn network(&self) -> &NetworkPolicy {
+        &self.network
+    }
 }
 impl Remote {
     fn auth_headers(&self) -> HeaderMap {
@@ -91,19 +155,16 @@ impl Remote {
         match response.status() {
             StatusCode::OK => Ok(()),
             StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-            status => Err(SyncError::Unknown(format!(
-                "Upload chunk failed with status: {}",
-                status
-            ))),
+            status => Err(status_error("Upload chunk", status)),
         }
     }
 
-    pub async fn upload_batch(&self, chunks: Vec<(String, Vec<u8>)>) -> Result<()> {
-        trace!(
-            "uploading chunks {:?}",
-            chunks.iter().map(|(c, _)| c).collect::<Vec<_>>()
-        );
-
+    /// Uploads `chunks` in one request. They're pulled from the stream as
+    /// the request body is sent, so only the one on its way is in memory.
+    pub async fn upload_batch<S>(&self, chunks: S) -> Result<()>
+    where
+        S: Stream<Item = Result<(String, Vec<u8>)>> + Send + 'static,
+    {
         // Generate a random boundary string
         let boundary = format!("------------------------{}", Uuid::new_v4());
         let mut headers = self.auth_headers();
@@ -115,8 +176,14 @@ impl Remote {
         let final_boundary = format!("--{}--\r\n", &boundary).into_bytes();
 
         // Create a stream of chunk data
-        let stream = futures::stream::iter(chunks)
-            .map(move |(chunk_id, content)| {
+        let stream = chunks
+            .map(move |chunk| {
+                let (chunk_id, content) = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => return futures::stream::iter(vec![Err(e)]),
+                };
+                trace!("uploading chunk {:?}", chunk_id);
+
                 let part = format!(
                     "--{boundary}\r\n\
                  Content-Disposition: form-data; name=\"{chunk_id}\"\r\n\
@@ -139,6 +206,7 @@ impl Remote {
         // Add final boundary
 
         let stream = stream.chain(futures::stream::once(async move { Ok(final_boundary) }));
+        let stream = metered(Arc::clone(&self.network), stream);
 
         let response = self
             .client
@@ -151,10 +219,7 @@ impl Remote {
         match response.status() {
             StatusCode::OK => Ok(()),
             StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-            status => Err(SyncError::Unknown(format!(
-                "Upload batch failed with status: {}",
-                status
-            ))),
+            status => Err(status_error("Upload batch", status)),
         }
     }
 
@@ -174,10 +239,7 @@ impl Remote {
                 Err(_) => Err(SyncError::BodyExtractError),
             },
             StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-            status => Err(SyncError::Unknown(format!(
-                "Download chunk failed with status: {}",
-                status
-            ))),
+            status => Err(status_error("Download chunk", status)),
         }
     }
 
@@ -200,10 +262,7 @@ impl Remote {
                 Ok(records)
             }
             StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-            status => Err(SyncError::Unknown(format!(
-                "List metadata failed with status: {}",
-                status
-            ))),
+            status => Err(status_error("List metadata", status)),
         }
     }
 
@@ -233,31 +292,36 @@ impl Remote {
             Ok(response) => match response.status() {
                 StatusCode::OK => Ok(()),
                 StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-                status => Err(SyncError::Unknown(format!(
-                    "Poll metadata failed with status: {}",
-                    status
-                ))),
+                status => Err(status_error("Poll metadata", status)),
             },
             Err(e) if e.is_timeout() => Ok(()), // Ignore timeout errors
             Err(e) => Err(e.into()),
         }
     }
 
+    /// Commits a new version of `path`. With a `parent_jid`, the version it
+    /// was changed from, the server answers with a conflict instead if `path`
+    /// has a newer version by now. Without one it's committed regardless.
     pub async fn commit(
         &self,
         path: &str,
         deleted: bool,
         chunk_ids: &str,
+        parent_jid: Option<i32>,
     ) -> Result<CommitResultStatus> {
         trace!("commit {:?}", path);
 
-        let path = Path::new(path);
+        let path = sync_path::from_local(Path::new(path))?;
+        let parent_jid = parent_jid.map(|jid| jid.to_string());
 
-        let params = [
+        let mut params = vec![
             ("deleted", if deleted { "true" } else { "false" }),
             ("chunk_ids", chunk_ids),
-            ("path", &path.to_slash().unwrap()),
+            ("path", &path),
         ];
+        if let Some(parent_jid) = &parent_jid {
+            params.push(("parent_jid", parent_jid));
+        }
 
         let response = self
             .client
@@ -274,10 +338,173 @@ impl Remote {
                 Ok(records)
             }
             StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
-            status => Err(SyncError::Unknown(format!(
-                "Commit metadata failed with status: {}",
-                status
-            ))),
+            status => Err(status_error("Commit metadata", status)),
+        }
+    }
+
+    /// Commits `entries` together: either all of them get their jids under
+    /// one transaction, or none does. Servers that don't know about batches
+    /// get them one by one, which isn't atomic, but whatever went in is
+    /// deduplicated when it's sent again.
+    pub async fn commit_batch(&self, entries: &[CommitEntry]) -> Result<CommitBatchResultStatus> {
+        trace!("commit batch of {}", entries.len());
+
+        let paths = batch_paths(entries)?;
+        let params = batch_fields(entries, &paths);
+
+        let response = self
+            .client
+            .post(self.api_endpoint.clone() + "/metadata/commit_batch" + "?uuid=" + &self.uuid)
+            .headers(self.auth_headers())
+            .form(&params)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(batch_result(response.json().await?, &paths)),
+            StatusCode::NOT_FOUND => self.commit_one_by_one(entries).await,
+            StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
+            status => Err(status_error("Commit metadata batch", status)),
+        }
+    }
+
+    /// Commits `entries` like `commit_batch`, with `chunks` they may need in
+    /// the same request, so small files don't wait for a `NeedChunks` round
+    /// trip. Chunks the server has already are sent for nothing, so keep it
+    /// to small ones. Servers that don't know about it get `commit_batch`.
+    pub async fn commit_with_chunks(
+        &self,
+        entries: &[CommitEntry],
+        chunks: Vec<(String, Vec<u8>)>,
+    ) -> Result<CommitBatchResultStatus> {
+        trace!(
+            "commit batch of {} with {} chunks",
+            entries.len(),
+            chunks.len()
+        );
+
+        let paths = batch_paths(entries)?;
+        let params = batch_fields(entries, &paths);
+
+        let boundary = format!("------------------------{}", Uuid::new_v4());
+        let mut headers = self.auth_headers();
+        headers.insert(
+            "content-type",
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={}", &boundary)).unwrap(),
+        );
+
+        let mut body = vec![];
+        for (name, value) in params {
+            body.extend(
+                format!(
+                    "--{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"{name}\"\r\n\r\n\
+                 {value}\r\n"
+                )
+                .into_bytes(),
+            );
+        }
+        for (i, (chunk_id, content)) in chunks.into_iter().enumerate() {
+            body.extend(
+                format!(
+                    "--{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"chunks[{i}].id\"\r\n\r\n\
+                 {chunk_id}\r\n\
+                 --{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"chunks[{i}].data\"; filename=\"{chunk_id}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n"
+                )
+                .into_bytes(),
+            );
+            body.extend(content);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", &boundary).into_bytes());
+        let body = metered(Arc::clone(&self.network), futures::stream::iter([Ok(body)]));
+
+        let response = self
+            .client
+            .post(
+                self.api_endpoint.clone() + "/metadata/commit_with_chunks" + "?uuid=" + &self.uuid,
+            )
+            .headers(headers)
+            .body(reqwest::Body::wrap_stream(body))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(batch_result(response.json().await?, &paths)),
+            StatusCode::NOT_FOUND => self.commit_batch(entries).await,
+            StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
+            status => Err(status_error("Commit metadata with chunks", status)),
+        }
+    }
+
+    async fn commit_one_by_one(&self, entries: &[CommitEntry]) -> Result<CommitBatchResultStatus> {
+        let mut ids = vec![];
+        let mut missing = vec![];
+        let mut conflicts = vec![];
+
+        for (i, e) in entries.iter().enumerate() {
+            match self
+                .commit(&e.path, e.deleted, &e.chunk_ids, e.parent_jid)
+                .await?
+            {
+                CommitResultStatus::Success(jid) => ids.push(jid),
+                CommitResultStatus::NeedChunks(chunks) => missing.push(chunks),
+                CommitResultStatus::Conflict(jid, chunk_ids, deleted) => {
+                    conflicts.push((i, jid, chunk_ids, deleted))
+                }
+            }
+        }
+
+        if !conflicts.is_empty() {
+            Ok(CommitBatchResultStatus::Conflict(conflicts))
+        } else if !missing.is_empty() {
+            Ok(CommitBatchResultStatus::NeedChunks(missing.join(",")))
+        } else {
+            Ok(CommitBatchResultStatus::Success(ids))
+        }
+    }
+
+    /// Moves the file at `from` to `path` without uploading it again, on top
+    /// of `parent_jid` at `path` like `commit`. Returns `Conflict` if `path`
+    /// has a newer record, or `None` if the server refused because `from`
+    /// doesn't have these chunks anymore or it doesn't know about moves.
+    /// Both paths need to be committed as usual then.
+    pub async fn move_file(
+        &self,
+        from: &str,
+        path: &str,
+        chunk_ids: &str,
+        parent_jid: i32,
+    ) -> Result<Option<CommitResultStatus>> {
+        trace!("move {:?} to {:?}", from, path);
+
+        let from = sync_path::from_local(Path::new(from))?;
+        let path = sync_path::from_local(Path::new(path))?;
+        let parent_jid = parent_jid.to_string();
+
+        let params = [
+            ("from", from.as_str()),
+            ("path", path.as_str()),
+            ("chunk_ids", chunk_ids),
+            ("parent_jid", parent_jid.as_str()),
+        ];
+
+        let response = self
+            .client
+            .post(self.api_endpoint.clone() + "/metadata/move" + "?uuid=" + &self.uuid)
+            .headers(self.auth_headers())
+            .form(&params)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json().await?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized),
+            status => Err(status_error("Move metadata", status)),
         }
     }
 
@@ -324,6 +551,7 @@ impl Remote {
 
                     while let Some(chunk) = stream.next().await {
                         let chunk = chunk?;
+                        self.network.download().acquire(chunk.len()).await;
                         buffer.extend_from_slice(&chunk);
 
                         // Process complete parts from buffer
@@ -336,7 +564,7 @@ impl Remote {
                     }
                 }
                 StatusCode::UNAUTHORIZED => Err(SyncError::Unauthorized)?,
-                status => Err(SyncError::Unknown(format!("Download batch failed with status: {}", status)))?,
+                status => Err(status_error("Download batch", status))?,
             }
         })
     }
@@ -382,6 +610,95 @@ fn process_part(part: &[u8]) -> Result<Option<(String, Vec<u8>)>> {
     }
 }
 
+/// Paths of a batch as the server gets them.
+fn batch_paths(entries: &[CommitEntry]) -> Result<Vec<String>> {
+    entries
+        .iter()
+        .map(|e| sync_path::from_local(Path::new(&e.path)))
+        .collect()
+}
+
+/// Form fields of a batch, `entries[i].path` and so on.
+fn batch_fields(entries: &[CommitEntry], paths: &[String]) -> Vec<(String, String)> {
+    let mut params: Vec<(String, String)> = vec![];
+    for (i, (entry, path)) in entries.iter().zip(paths).enumerate() {
+        params.push((format!("entries[{}].path", i), path.clone()));
+        params.push((format!("entries[{}].deleted", i), entry.deleted.to_string()));
+        params.push((format!("entries[{}].chunk_ids", i), entry.chunk_ids.clone()));
+        if let Some(parent_jid) = entry.parent_jid {
+            params.push((format!("entries[{}].parent_jid", i), parent_jid.to_string()));
+        }
+    }
+
+    params
+}
+
+/// The server's answer to a batch, with conflicts by entry index of `paths`.
+fn batch_result(
+    status: ServerCommitBatchResultStatus,
+    paths: &[String],
+) -> CommitBatchResultStatus {
+    match status {
+        ServerCommitBatchResultStatus::Success(ids) => CommitBatchResultStatus::Success(ids),
+        ServerCommitBatchResultStatus::NeedChunks(chunks) => {
+            CommitBatchResultStatus::NeedChunks(chunks)
+        }
+        ServerCommitBatchResultStatus::Conflict(conflicts) => {
+            let conflicts = conflicts
+                .into_iter()
+                .filter_map(|(path, jid, chunk_ids, deleted)| {
+                    let i = paths.iter().position(|p| *p == path)?;
+                    Some((i, jid, chunk_ids, deleted))
+                })
+                .collect();
+
+            CommitBatchResultStatus::Conflict(conflicts)
+        }
+    }
+}
+
+/// Error for a response with an unexpected `status` to `what`. Server
+/// errors and rate limiting pass, so they are worth retrying.
+fn status_error(what: &str, status: StatusCode) -> SyncError {
+    let message = format!("{} failed with status: {}", what, status);
+
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        SyncError::ServerUnavailable(message)
+    } else {
+        SyncError::Unknown(message)
+    }
+}
+
+/// Paces `body` to the upload limit as it's sent, a slice at a time, so a
+/// change of limit applies to what's left of it.
+fn metered<S>(
+    network: Arc<NetworkPolicy>,
+    body: S,
+) -> impl Stream<Item = Result<Vec<u8>>> + Send + 'static
+where
+    S: Stream<Item = Result<Vec<u8>>> + Send + 'static,
+{
+    body.flat_map(|piece| {
+        let slices: Vec<Result<Vec<u8>>> = match piece {
+            Ok(bytes) => bytes
+                .chunks(METERED_SLICE_SIZE)
+                .map(|slice| Ok(slice.to_vec()))
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        futures::stream::iter(slices)
+    })
+    .then(move |slice| {
+        let network = Arc::clone(&network);
+        async move {
+            if let Ok(bytes) = &slice {
+                network.upload().acquire(bytes.len()).await;
+            }
+            slice
+        }
+    })
+}
+
 // Helper function to find boundary in buffer
 fn find_boundary(data: &[u8], boundary: &[u8]) -> Option<usize> {
     data.windows(boundary.len())
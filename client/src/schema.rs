@@ -1,5 +1,28 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    deferred_downloads (id) {
+        id -> Integer,
+        namespace_id -> Integer,
+        jid -> Integer,
+        path -> Text,
+        chunk_ids -> Text,
+        moved_from -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    download_intents (id) {
+        id -> Integer,
+        namespace_id -> Integer,
+        jid -> Integer,
+        path -> Text,
+        deleted -> Bool,
+        chunk_ids -> Text,
+        checksum -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     file_records (id) {
         id -> Integer,
@@ -9,5 +32,11 @@ diesel::table! {
         modified_at -> TimestamptzSqlite,
         size -> BigInt,
         namespace_id -> Integer,
+        format -> Nullable<Integer>,
+        checksum -> Nullable<Text>,
+        moved_from -> Nullable<Text>,
+        chunk_ids -> Nullable<Text>,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(deferred_downloads, download_intents, file_records,);
@@ -0,0 +1,315 @@
+//! Canonical form of file paths in the registry and on the server.
+//!
+//! Paths are relative to the storage dir, `/`-separated and NFC normalized.
+//! macOS tends to hand out NFD names while Linux and Android keep whatever
+//! was typed, usually NFC, so without normalization the same recipe would
+//! show up as two different files on the server.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+
+use path_slash::PathExt as _;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::errors::SyncError;
+
+type Result<T, E = SyncError> = std::result::Result<T, E>;
+
+/// Directories whose listing is kept at most, it starts over beyond that.
+const MAX_LISTINGS: usize = 1_024;
+/// A listing is only kept when the directory hasn't changed for this long,
+/// an entry added within its mtime granularity would go unnoticed otherwise.
+const LISTING_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Names that aren't NFC in the directories looked into, by their NFC form,
+/// as of the directory's mtime. Most directories have none, and a lookup of
+/// a name that isn't there doesn't read the directory again until it
+/// changes.
+static NON_NFC_NAMES: LazyLock<Mutex<HashMap<PathBuf, Listing>>> = LazyLock::new(Default::default);
+
+struct Listing {
+    modified: SystemTime,
+    names: HashMap<String, OsString>,
+}
+
+/// Canonical path of a file on disk, `relative` to the storage dir. Names
+/// that aren't valid UTF-8 can't be represented on the server and are
+/// refused.
+pub fn from_local(relative: &Path) -> Result<String> {
+    let path = relative
+        .to_slash()
+        .ok_or_else(|| SyncError::UnrepresentablePath(relative.to_string_lossy().into_owned()))?;
+
+    Ok(path.nfc().collect())
+}
+
+/// Canonical form of a path received from the server. Anything that could
+/// escape the storage dir is refused.
+pub fn from_remote(path: &str) -> Result<String> {
+    let invalid = || SyncError::InvalidRemotePath(path.to_string());
+
+    if path.is_empty() || path.contains(['\\', '\0']) {
+        return Err(invalid());
+    }
+
+    for part in path.split('/') {
+        let mut components = Path::new(part).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(path.nfc().collect())
+}
+
+/// Where the file with canonical `path` lives under `base`. When a file or
+/// directory on disk has the same name in a different normalization, it's
+/// used instead of creating a twin next to it. New names are created as
+/// they are, i.e. NFC.
+pub fn to_local(base: &Path, path: &str) -> PathBuf {
+    let mut full_path = base.to_path_buf();
+    let mut parts = path.split('/');
+
+    for part in parts.by_ref() {
+        let exact = full_path.join(part);
+
+        if exact.symlink_metadata().is_ok() {
+            full_path = exact;
+        } else if let Some(equivalent) = find_equivalent(&full_path, part) {
+            full_path = equivalent;
+        } else {
+            // Nothing below it is there either.
+            full_path = exact;
+            break;
+        }
+    }
+
+    full_path.extend(parts);
+    full_path
+}
+
+/// Key under which paths collide on a case-insensitive filesystem.
+pub fn fold_case(path: &str) -> String {
+    path.to_lowercase()
+}
+
+/// Name a file is saved under when its path only differs in case from one
+/// that is already there, e.g. `Pasta (Case Conflict 1a2b3c4d).cook`. It
+/// depends only on `path`, so later updates and deletes land on the same
+/// file.
+pub fn case_conflict_path(path: &str) -> String {
+    let tag = &blake3::hash(path.as_bytes()).to_hex()[..8];
+
+    with_suffix(path, &format!(" (Case Conflict {})", tag))
+}
+
+/// Name a local edit is kept under when the file was changed on another
+/// device too, e.g. `Pancakes (conflicted copy from Pixel).cook`. Pass `n`
+/// above 1 when that name is taken already.
+pub fn conflicted_copy_path(path: &str, device_name: Option<&str>, n: u32) -> String {
+    let mut label = String::from("conflicted copy");
+
+    if let Some(device_name) = device_name {
+        label.push_str(" from ");
+        label.push_str(&device_name.replace(['/', '\\'], "-"));
+    }
+    if n > 1 {
+        label.push_str(&format!(" {}", n));
+    }
+
+    with_suffix(path, &format!(" ({})", label))
+}
+
+/// `path` with `suffix` added to the file name, before the extension.
+fn with_suffix(path: &str, suffix: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    };
+
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}.{}", stem, suffix, ext),
+        _ => format!("{}{}", name, suffix),
+    };
+
+    match dir {
+        Some(dir) => format!("{}/{}", dir, name),
+        None => name,
+    }
+}
+
+/// Entry of `dir` named `name` in another normalization. `name` is NFC
+/// already, so only names that aren't can be it.
+fn find_equivalent(dir: &Path, name: &str) -> Option<PathBuf> {
+    let modified = fs::metadata(dir).and_then(|m| m.modified()).ok()?;
+    let mut listings = NON_NFC_NAMES.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(listing) = listings.get(dir).filter(|l| l.modified == modified) {
+        return listing.names.get(name).map(|n| dir.join(n));
+    }
+
+    let names: HashMap<String, OsString> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file_name = e.file_name();
+            let nfc: String = file_name.to_str()?.nfc().collect();
+            (nfc.as_str() != file_name).then_some((nfc, file_name))
+        })
+        .collect();
+    let found = names.get(name).map(|n| dir.join(n));
+
+    let settled = modified
+        .elapsed()
+        .is_ok_and(|age| age >= LISTING_SETTLE_TIME);
+    if settled {
+        if listings.len() >= MAX_LISTINGS {
+            listings.clear();
+        }
+        listings.insert(dir.to_path_buf(), Listing { modified, names });
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NFC: &str = "Cr\u{e8}me br\u{fb}l\u{e9}e.cook";
+    const NFD: &str = "Cre\u{300}me bru\u{302}le\u{301}e.cook";
+
+    #[test]
+    fn local_paths_are_normalized_to_nfc() {
+        assert_ne!(NFC, NFD);
+        assert_eq!(from_local(Path::new(NFD)).unwrap(), NFC);
+        assert_eq!(from_local(Path::new(NFC)).unwrap(), NFC);
+        assert_eq!(
+            from_local(&Path::new("Desserts").join(NFD)).unwrap(),
+            format!("Desserts/{}", NFC)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_refused() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"Soup\xff.cook"));
+
+        assert!(matches!(
+            from_local(path),
+            Err(SyncError::UnrepresentablePath(_))
+        ));
+    }
+
+    #[test]
+    fn remote_paths_are_normalized_to_nfc() {
+        assert_eq!(from_remote(NFD).unwrap(), NFC);
+        assert_eq!(
+            from_remote("Breakfast/Pancakes.cook").unwrap(),
+            "Breakfast/Pancakes.cook"
+        );
+        assert_eq!(from_remote(".shopping-list").unwrap(), ".shopping-list");
+    }
+
+    #[test]
+    fn remote_paths_escaping_storage_dir_are_refused() {
+        for path in [
+            "",
+            "/etc/passwd",
+            "../Soup.cook",
+            "Breakfast/../../Soup.cook",
+            "./Soup.cook",
+            "Breakfast//Soup.cook",
+            "Breakfast/",
+            "Breakfast\\Soup.cook",
+            "Soup\0.cook",
+        ] {
+            assert!(
+                matches!(from_remote(path), Err(SyncError::InvalidRemotePath(_))),
+                "{:?} should be refused",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn case_conflict_paths_keep_directory_and_extension() {
+        let path = case_conflict_path("Dinner/pasta.cook");
+
+        assert!(path.starts_with("Dinner/pasta (Case Conflict "));
+        assert!(path.ends_with(").cook"));
+        assert_eq!(path, case_conflict_path("Dinner/pasta.cook"));
+        assert_ne!(
+            fold_case(&case_conflict_path("Pasta.cook")),
+            fold_case(&case_conflict_path("PASTA.cook"))
+        );
+        assert!(case_conflict_path(".shopping-list").starts_with(".shopping-list (Case Conflict "));
+        assert_eq!(
+            fold_case("Dinner/Pasta.cook"),
+            fold_case("dinner/pasta.COOK")
+        );
+    }
+
+    #[test]
+    fn conflicted_copy_paths_name_the_device() {
+        assert_eq!(
+            conflicted_copy_path("Breakfast/Pancakes.cook", Some("Pixel"), 1),
+            "Breakfast/Pancakes (conflicted copy from Pixel).cook"
+        );
+        assert_eq!(
+            conflicted_copy_path("Pancakes.cook", None, 2),
+            "Pancakes (conflicted copy 2).cook"
+        );
+        assert_eq!(
+            conflicted_copy_path(".shopping-list", Some("Anna's iPad/Mini"), 1),
+            ".shopping-list (conflicted copy from Anna's iPad-Mini)"
+        );
+    }
+
+    #[test]
+    fn to_local_reuses_names_in_other_normalization() {
+        let temp = TempDir::new().unwrap();
+        let dir = "D\u{e9}jeuner";
+        let dir_nfd = "De\u{301}jeuner";
+        fs::create_dir(temp.path().join(dir_nfd)).unwrap();
+        fs::write(temp.path().join(dir_nfd).join(NFD), "").unwrap();
+
+        let existing = to_local(temp.path(), &format!("{}/{}", dir, NFC));
+        assert!(existing.exists());
+
+        let new = to_local(temp.path(), &format!("{}/Soup.cook", dir));
+        assert!(new.parent().unwrap().is_dir());
+        assert!(new.ends_with("Soup.cook"));
+
+        assert_eq!(
+            to_local(temp.path(), "Soup.cook"),
+            temp.path().join("Soup.cook")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn to_local_notices_names_added_since_the_directory_was_listed() {
+        let temp = TempDir::new().unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3_600);
+        fs::File::open(temp.path())
+            .unwrap()
+            .set_modified(hour_ago)
+            .unwrap();
+
+        assert_eq!(to_local(temp.path(), NFC), temp.path().join(NFC));
+
+        fs::write(temp.path().join(NFD), "").unwrap();
+        assert_eq!(to_local(temp.path(), NFC), temp.path().join(NFD));
+    }
+}
@@ -1,21 +1,32 @@
-use futures::{channel::mpsc::Receiver, try_join, StreamExt};
+use futures::{channel::mpsc::Receiver, try_join, Stream, StreamExt, TryStreamExt};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 use std::sync::Arc;
 use time::OffsetDateTime;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
-use crate::chunker::Chunker;
-use crate::connection::{get_connection, ConnectionPool};
-use crate::errors::SyncError;
-use crate::indexer::truncate_to_seconds;
+use crate::backoff::Backoff;
+use crate::chunk_id;
+use crate::chunker::{self, Chunker, Chunking};
+use crate::connection::{get_connection, Connection, ConnectionPool};
+use crate::control::{SyncControl, WakeUps};
+use crate::crypto;
+use crate::errors::{ErrorKind, SyncError};
+use crate::file_format::FileFormatRegistry;
+use crate::ignore_rules::IgnoreRules;
+use crate::indexer::{self, truncate_to_seconds};
+use crate::merge;
 use crate::models;
 use crate::registry;
-use crate::remote::{CommitResultStatus, Remote};
+use crate::remote::{
+    CommitBatchResultStatus, CommitEntry, CommitResultStatus, Remote, ResponseFileRecord,
+};
+use crate::sync_path;
 use crate::{SyncStatus, SyncStatusListener};
 
 type Result<T, E = SyncError> = std::result::Result<T, E>;
@@ -23,10 +34,30 @@ type Result<T, E = SyncError> = std::result::Result<T, E>;
 const INTERVAL_CHECK_UPLOAD_SEC: Duration = Duration::from_secs(47);
 // TODO should be in sync in multiple places
 const MAX_UPLOAD_SIZE: usize = 3_000_000;
+// Files committed in one request, and how many bytes of chunk IDs they
+// may have together, the server takes forms of up to 1 MiB.
+const MAX_COMMIT_FILES: usize = 100;
+const MAX_COMMIT_SIZE: usize = 500_000;
+// Batches whose chunks are within these go up together with their commit,
+// instead of waiting to be asked for the ones missing.
+const MAX_INLINE_CHUNKS: usize = 200;
+const MAX_INLINE_SIZE: usize = 64_000;
+// Chunks asked for in one download request, how many of their bytes are
+// held in memory before they go to the chunk store, and how many requests
+// run at the same time.
+const MAX_DOWNLOAD_CHUNKS: usize = 500;
+const MAX_DOWNLOAD_SIZE: usize = 3_000_000;
+/// What a line is counted as when sizing download batches.
+const LINE_SIZE_ESTIMATE: usize = 1_024;
+const CONCURRENT_DOWNLOADS: usize = 3;
+// Waits between retries after transient errors, e.g. while offline.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
 
 #[allow(clippy::too_many_arguments)]
 pub async fn run(
     token: CancellationToken,
+    control: &SyncControl,
     listener: Option<Arc<dyn SyncStatusListener>>,
     pool: &ConnectionPool,
     storage_path: &Path,
@@ -38,20 +69,25 @@ pub async fn run(
 ) -> Result<()> {
     let chunker = Arc::new(Mutex::new(chunker));
 
+    // The loops retry transient errors themselves, whatever they return
+    // ends the whole sync.
     if read_only {
-        let _ = try_join!(download_loop(
+        download_loop(
             token.clone(),
+            control,
             listener.clone(),
             pool,
             Arc::clone(&chunker),
             remote,
             storage_path,
-            namespace_id
-        ))?;
+            namespace_id,
+        )
+        .await?;
     } else {
-        let _ = try_join!(
+        try_join!(
             download_loop(
                 token.clone(),
+                control,
                 listener.clone(),
                 pool,
                 Arc::clone(&chunker),
@@ -61,10 +97,12 @@ pub async fn run(
             ),
             upload_loop(
                 token.clone(),
+                control,
                 listener.clone(),
                 pool,
                 Arc::clone(&chunker),
                 remote,
+                storage_path,
                 namespace_id,
                 local_registry_updated_rx
             ),
@@ -74,8 +112,10 @@ pub async fn run(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn download_loop(
     token: CancellationToken,
+    control: &SyncControl,
     listener: Option<Arc<dyn SyncStatusListener>>,
     pool: &ConnectionPool,
     chunker: Arc<Mutex<Chunker>>,
@@ -83,6 +123,9 @@ async fn download_loop(
     storage_path: &Path,
     namespace_id: i32,
 ) -> Result<()> {
+    let mut backoff = Backoff::new(RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+    let mut wake_ups = control.wake_ups();
+
     loop {
         // Check for cancellation at loop start
         if token.is_cancelled() {
@@ -90,24 +133,43 @@ async fn download_loop(
             break;
         }
 
+        if !control.wait_while_paused(&token, listener.as_deref()).await {
+            break;
+        }
+
         // Notify that we're downloading
         if let Some(ref cb) = listener {
             cb.on_status_changed(SyncStatus::Downloading);
         }
 
-        match check_download_once(
+        let metered = remote.network().is_metered();
+        let result = check_download_once(
             pool,
             Arc::clone(&chunker),
             remote,
             storage_path,
             namespace_id,
+            listener.as_deref(),
         )
-        .await
-        {
-            Ok(v) => v,
-            Err(SyncError::Unauthorized) => return Err(SyncError::Unauthorized),
-            Err(e) => return Err(SyncError::Unknown(format!("Check download failed: {}", e))),
-        };
+        .await;
+
+        if let Err(e) = result {
+            match wait_to_retry(
+                &token,
+                control,
+                &mut wake_ups,
+                listener.as_deref(),
+                &mut backoff,
+                e,
+            )
+            .await
+            {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(SyncError::Unauthorized) => return Err(SyncError::Unauthorized),
+                Err(e) => return Err(SyncError::Unknown(format!("Check download failed: {}", e))),
+            }
+        }
 
         // Return to idle after downloading
         if let Some(ref cb) = listener {
@@ -121,8 +183,23 @@ async fn download_loop(
                 debug!("Download loop shutting down");
                 break;
             }
+            // Binary files left for later can come now.
+            _ = remote.network().unmetered(), if metered => {}
+            _ = wake_ups.next() => {}
             result = remote.poll() => {
-                result?;
+                // Only once the server answers again, a download can work
+                // while polling keeps failing.
+                match result {
+                    Ok(()) => backoff.reset(),
+                    Err(e) => {
+                        let listener = listener.as_deref();
+                        if !wait_to_retry(&token, control, &mut wake_ups, listener, &mut backoff, e)
+                            .await?
+                        {
+                            break;
+                        }
+                    }
+                }
             }
         }
     }
@@ -130,18 +207,72 @@ async fn download_loop(
     Ok(())
 }
 
+/// Waits out `error` if it's transient, telling the listener, and returns
+/// whether to go on, i.e. false when cancelled meanwhile. A sync-now request
+/// or a pause cuts the wait short. Other errors are returned as they are.
+async fn wait_to_retry(
+    token: &CancellationToken,
+    control: &SyncControl,
+    wake_ups: &mut WakeUps,
+    listener: Option<&dyn SyncStatusListener>,
+    backoff: &mut Backoff,
+    error: SyncError,
+) -> Result<bool> {
+    if error.kind() != ErrorKind::Transient {
+        return Err(error);
+    }
+
+    let delay = backoff.next_delay();
+    warn!("{}, retrying in {:?}", error, delay);
+
+    if let Some(cb) = listener {
+        cb.on_status_changed(match error.is_offline() {
+            true => SyncStatus::Offline,
+            false => SyncStatus::Retrying {
+                attempt: backoff.attempt(),
+                delay_secs: delay.as_secs(),
+            },
+        });
+    }
+
+    tokio::select! {
+        _ = token.cancelled() => Ok(false),
+        _ = tokio::time::sleep(delay) => Ok(true),
+        _ = wake_ups.next() => Ok(true),
+        // The loop waits for the resume instead.
+        _ = control.paused() => Ok(true),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn upload_loop(
     token: CancellationToken,
+    control: &SyncControl,
     listener: Option<Arc<dyn SyncStatusListener>>,
     pool: &ConnectionPool,
     chunker: Arc<Mutex<Chunker>>,
     remote: &Remote,
+    storage_path: &Path,
     namespace_id: i32,
     mut local_registry_updated_rx: Receiver<models::IndexerUpdateEvent>,
 ) -> Result<()> {
+    let mut backoff = Backoff::new(RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+    let mut wake_ups = control.wake_ups();
+
     // wait for indexer to work first
     tokio::time::sleep(Duration::from_secs(5)).await;
 
+    loop {
+        let result = check_encryption_allowed(&chunker, remote).await;
+        let Err(e) = result else {
+            break;
+        };
+        let listener = listener.as_deref();
+        if !wait_to_retry(&token, control, &mut wake_ups, listener, &mut backoff, e).await? {
+            return Ok(());
+        }
+    }
+
     loop {
         // Check for cancellation at loop start
         if token.is_cancelled() {
@@ -149,6 +280,10 @@ pub async fn upload_loop(
             break;
         }
 
+        if !control.wait_while_paused(&token, listener.as_deref()).await {
+            break;
+        }
+
         // Notify that we're uploading
         if let Some(ref cb) = listener {
             cb.on_status_changed(SyncStatus::Uploading);
@@ -156,7 +291,38 @@ pub async fn upload_loop(
 
         // need to wait only if we didn't upload anything
         // otherwise it should re-run immideately
-        if check_upload_once(pool, Arc::clone(&chunker), remote, namespace_id).await? {
+        let metered = remote.network().is_metered();
+        let result = check_upload_once(
+            pool,
+            Arc::clone(&chunker),
+            remote,
+            storage_path,
+            namespace_id,
+            listener.as_deref(),
+        )
+        .await;
+
+        let all_commited = match result {
+            Ok(all_commited) => {
+                backoff.reset();
+                all_commited
+            }
+            Err(e) => match wait_to_retry(
+                &token,
+                control,
+                &mut wake_ups,
+                listener.as_deref(),
+                &mut backoff,
+                e,
+            )
+            .await?
+            {
+                true => continue,
+                false => break,
+            },
+        };
+
+        if all_commited {
             // Return to idle after uploading
             if let Some(ref cb) = listener {
                 cb.on_status_changed(SyncStatus::Idle);
@@ -170,6 +336,9 @@ pub async fn upload_loop(
                 }
                 _ = tokio::time::sleep(INTERVAL_CHECK_UPLOAD_SEC) => {},
                 Some(_) = local_registry_updated_rx.next() => {},
+                // Binary files that waited can go up now.
+                _ = remote.network().unmetered(), if metered => {},
+                _ = wake_ups.next() => {},
             };
         } else {
             // If we still have work to do, don't set to idle - keep uploading status
@@ -180,50 +349,218 @@ pub async fn upload_loop(
     Ok(())
 }
 
+/// With a passphrase set, makes sure the namespace has no live files that
+/// were synced without one. Encrypted copies would go up next to them and
+/// their plaintext paths and chunks would stay on the server, so encryption
+/// can only be turned on for a namespace that's empty, or whose files were
+/// all deleted.
+pub async fn check_encryption_allowed(chunker: &Mutex<Chunker>, remote: &Remote) -> Result<()> {
+    if chunker.lock().await.cipher().is_none() {
+        return Ok(());
+    }
+
+    // The latest record of every path.
+    let unencrypted = remote
+        .list(0)
+        .await?
+        .iter()
+        .filter(|r| !r.deleted && !crypto::is_encrypted_path(&r.path))
+        .count();
+
+    match unencrypted {
+        0 => Ok(()),
+        n => Err(SyncError::EncryptionError(format!(
+            "{} files are synced unencrypted, encryption needs an empty namespace",
+            n
+        ))),
+    }
+}
+
 pub async fn check_upload_once(
     pool: &ConnectionPool,
     chunker: Arc<Mutex<Chunker>>,
     remote: &Remote,
+    storage_path: &Path,
     namespace_id: i32,
+    listener: Option<&dyn SyncStatusListener>,
 ) -> Result<bool> {
     debug!("upload scan");
 
     let conn = &mut get_connection(pool)?;
     let to_upload = registry::updated_locally(conn, namespace_id)?;
 
-    let mut upload_queue: Vec<Vec<(String, Vec<u8>)>> = vec![vec![]];
+    let mut upload_queue: Vec<Vec<PendingChunk>> = vec![vec![]];
     let mut size = 0;
     let mut last = upload_queue.last_mut().unwrap();
     let mut all_commited = true;
+    // Versions committed elsewhere since the local edit was based on them.
+    let mut newer_remotely = vec![];
+    let mut to_commit = vec![];
+    let metered = remote.network().is_metered();
+
+    // Deletes that are the other half of a move go up with it.
+    let moved_away: HashSet<&str> = to_upload
+        .iter()
+        .filter_map(|f| f.moved_from.as_deref())
+        .collect();
 
     for f in &to_upload {
         trace!("to upload {:?}", f);
+
+        if f.deleted && moved_away.contains(f.path.as_str()) {
+            continue;
+        }
+
         let mut chunker = chunker.lock().await;
+
+        if metered && !f.deleted && is_binary(&chunker, f.format, &f.path) {
+            trace!("{:?} waits for an unmetered network", f.path);
+            continue;
+        }
+
         let mut chunk_ids = vec![String::from("")];
+        let mut checksum = None;
 
         if !f.deleted {
-            // Also warms up the cache
-            chunk_ids = chunker.hashify(&f.path).await?;
+            let unchanged = unchanged_on_disk(f, storage_path).await?;
+            // Hashed on an earlier pass, and the chunks are still around.
+            let stored = f
+                .chunk_ids
+                .as_deref()
+                .filter(|ids| unchanged && !ids.is_empty())
+                .filter(|ids| ids.split(',').all(|c| chunker.check_chunk(c)));
+
+            if let Some(ids) = stored {
+                chunk_ids = ids.split(',').map(String::from).collect();
+                checksum = f.checksum.clone();
+            } else {
+                // Also warms up the cache
+                chunk_ids = match f.format {
+                    Some(format) => chunker.hashify_as(&f.path, format).await?,
+                    None => chunker.hashify(&f.path).await?,
+                };
+                // What the server gets, which may be newer than what the indexer saw.
+                let ids: Vec<&str> = chunk_ids.iter().map(String::as_str).collect();
+                checksum = chunker.checksum(&ids).ok();
+
+                // So that another pass over this row doesn't hash it again.
+                if unchanged && checksum.is_some() {
+                    registry::update_chunk_ids(conn, f, &chunk_ids.join(","))?;
+                    registry::update_checksum(conn, f, checksum.as_deref())?;
+                }
+            }
         }
 
-        let r = remote
-            .commit(&f.path, f.deleted, &chunk_ids.join(","))
-            .await?;
+        let tombstone = f
+            .moved_from
+            .as_ref()
+            .and_then(|from| to_upload.iter().find(|t| t.deleted && t.path == *from));
+
+        if let Some(tombstone) = tombstone {
+            let moved = remote
+                .move_file(
+                    &remote_path(&chunker, &tombstone.path)?,
+                    &remote_path(&chunker, &f.path)?,
+                    &chunk_ids.join(","),
+                    parent_jid(conn, namespace_id, &f.path)?,
+                )
+                .await?;
+
+            match moved {
+                Some(CommitResultStatus::Success(jid)) => {
+                    trace!("move success");
+                    registry::update_jid(conn, tombstone, jid)?;
+                    registry::update_jid(conn, f, jid)?;
+                    registry::update_chunk_ids(conn, f, &chunk_ids.join(","))?;
+                    if checksum.is_some() {
+                        registry::update_checksum(conn, f, checksum.as_deref())?;
+                    }
+                    continue;
+                }
+                Some(CommitResultStatus::Conflict(jid, chunk_ids, deleted)) => {
+                    // Like a conflicting commit, the old path's delete goes
+                    // up once the newer version at the new one is in.
+                    debug!("{:?} was changed remotely in jid {}", f.path, jid);
+                    all_commited = false;
+                    newer_remotely.push(remote_record(&chunker, f, jid, chunk_ids, deleted)?);
+                    continue;
+                }
+                Some(CommitResultStatus::NeedChunks(_)) | None => {}
+            }
+
+            // The server can't move it, the old path goes away as a plain
+            // delete and the new one is committed like any other file.
+            to_commit.push(PendingCommit {
+                record: tombstone,
+                checksum: None,
+                entry: CommitEntry {
+                    path: remote_path(&chunker, &tombstone.path)?,
+                    deleted: true,
+                    chunk_ids: String::new(),
+                    parent_jid: Some(parent_jid(conn, namespace_id, &tombstone.path)?),
+                },
+            });
+        }
+
+        to_commit.push(PendingCommit {
+            record: f,
+            checksum,
+            entry: CommitEntry {
+                path: remote_path(&chunker, &f.path)?,
+                deleted: f.deleted,
+                chunk_ids: chunk_ids.join(","),
+                parent_jid: Some(parent_jid(conn, namespace_id, &f.path)?),
+            },
+        });
+    }
+
+    // Files committed together get their jids together, so other devices
+    // never see a recipe without the photo that went up with it.
+    for batch in commit_batches(to_commit) {
+        let entries: Vec<CommitEntry> = batch.iter().map(|p| p.entry.clone()).collect();
+        let inline = inline_chunks(&mut *chunker.lock().await, &batch).await?;
 
-        match r {
-            CommitResultStatus::Success(jid) => {
+        let result = match inline {
+            Some(chunks) => remote.commit_with_chunks(&entries, chunks).await?,
+            None => remote.commit_batch(&entries).await?,
+        };
+
+        match result {
+            CommitBatchResultStatus::Success(jids) => {
                 trace!("commit success");
-                registry::update_jid(conn, f, jid)?;
+
+                for (p, jid) in batch.iter().zip(jids) {
+                    registry::update_jid(conn, p.record, jid)?;
+                    if !p.record.deleted {
+                        registry::update_chunk_ids(conn, p.record, &p.entry.chunk_ids)?;
+                    }
+                    if p.checksum.is_some() {
+                        registry::update_checksum(conn, p.record, p.checksum.as_deref())?;
+                    }
+                }
             }
-            CommitResultStatus::NeedChunks(chunks) => {
+            CommitBatchResultStatus::NeedChunks(chunks) => {
                 trace!("need chunks");
 
                 all_commited = false;
 
+                let mut chunker = chunker.lock().await;
+
                 for c in chunks.split(',') {
-                    let data = chunker.read_chunk(c)?;
-                    size += data.len();
-                    last.push((c.into(), data));
+                    // Any file of the batch that has it can be read for it.
+                    let Some(p) = batch
+                        .iter()
+                        .find(|p| p.entry.chunk_ids.split(',').any(|id| id == c))
+                    else {
+                        continue;
+                    };
+                    let c = PendingChunk {
+                        id: c.into(),
+                        path: p.record.path.clone(),
+                        format: p.record.format,
+                    };
+                    size += c.size(&mut chunker).await? as usize;
+                    last.push(c);
 
                     if size > MAX_UPLOAD_SIZE {
                         upload_queue.push(vec![]);
@@ -232,102 +569,1157 @@ pub async fn check_upload_once(
                     }
                 }
             }
+            CommitBatchResultStatus::Conflict(conflicts) => {
+                // The rest of the batch goes up again with the next round,
+                // once the newer versions are in, a merge or anything else
+                // still local on top of them.
+                all_commited = false;
+
+                let chunker = chunker.lock().await;
+
+                for (i, jid, chunk_ids, deleted) in conflicts {
+                    let f = batch[i].record;
+                    debug!("{:?} was changed remotely in jid {}", f.path, jid);
+
+                    newer_remotely.push(remote_record(&chunker, f, jid, chunk_ids, deleted)?);
+                }
+            }
         }
     }
 
-    for batch in upload_queue {
-        if !batch.is_empty() {
-            remote.upload_batch(batch).await?;
-        }
+    futures::stream::iter(upload_queue.into_iter().filter(|b| !b.is_empty()))
+        .map(|batch| remote.upload_batch(upload_stream(Arc::clone(&chunker), batch)))
+        .buffer_unordered(remote.upload_concurrency())
+        .try_for_each(|_| async { Ok(()) })
+        .await?;
+
+    // The download loop can't be relied on for these, it only asks for
+    // records newer than the latest jid here, which may be past them.
+    if !newer_remotely.is_empty() {
+        apply_remote(
+            conn,
+            &chunker,
+            remote,
+            storage_path,
+            namespace_id,
+            listener,
+            newer_remotely,
+        )
+        .await?;
     }
 
     Ok(all_commited)
 }
 
+/// A local change about to be committed, and what the server gets for it.
+struct PendingCommit<'a> {
+    record: &'a models::FileRecord,
+    checksum: Option<String>,
+    entry: CommitEntry,
+}
+
+/// `to_commit` in batches that fit in one request.
+fn commit_batches(to_commit: Vec<PendingCommit>) -> Vec<Vec<PendingCommit>> {
+    let mut batches: Vec<Vec<PendingCommit>> = vec![];
+    let mut size = 0;
+
+    for p in to_commit {
+        match batches.last_mut() {
+            Some(batch)
+                if batch.len() < MAX_COMMIT_FILES
+                    && size + p.entry.chunk_ids.len() <= MAX_COMMIT_SIZE =>
+            {
+                size += p.entry.chunk_ids.len();
+                batch.push(p);
+            }
+            _ => {
+                size = p.entry.chunk_ids.len();
+                batches.push(vec![p]);
+            }
+        }
+    }
+
+    batches
+}
+
+/// Chunks of `batch` to send along with its commit, if they are few and
+/// small enough that sending ones the server has already doesn't matter.
+async fn inline_chunks(
+    chunker: &mut Chunker,
+    batch: &[PendingCommit<'_>],
+) -> Result<Option<Vec<(String, Vec<u8>)>>> {
+    let mut pending = vec![];
+    let mut seen = HashSet::new();
+    let mut size = 0;
+
+    for p in batch.iter().filter(|p| !p.entry.deleted) {
+        for id in p.entry.chunk_ids.split(',') {
+            if id.is_empty() || !seen.insert(id) {
+                continue;
+            }
+
+            let c = PendingChunk {
+                id: id.into(),
+                path: p.record.path.clone(),
+                format: p.record.format,
+            };
+            size += c.size(chunker).await? as usize;
+
+            if size > MAX_INLINE_SIZE || seen.len() > MAX_INLINE_CHUNKS {
+                return Ok(None);
+            }
+            pending.push(c);
+        }
+    }
+
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    let mut chunks = Vec::with_capacity(pending.len());
+    for c in pending {
+        let data = c.read(chunker).await?;
+        chunks.push((c.id, data));
+    }
+
+    Ok(Some(chunks))
+}
+
+/// A chunk the server asked for, and the file to chunk again if it's gone
+/// from the chunk store by the time it's uploaded.
+struct PendingChunk {
+    id: String,
+    path: String,
+    format: Option<models::FileFormat>,
+}
+
+impl PendingChunk {
+    async fn size(&self, chunker: &mut Chunker) -> Result<u64> {
+        self.restore(chunker).await?;
+        chunker.chunk_size(&self.id)
+    }
+
+    async fn read(&self, chunker: &mut Chunker) -> Result<Vec<u8>> {
+        self.restore(chunker).await?;
+        seal_chunk(chunker, &self.id, chunker.read_chunk(&self.id)?)
+    }
+
+    async fn restore(&self, chunker: &mut Chunker) -> Result<()> {
+        if !chunker.check_chunk(&self.id) {
+            debug!(
+                "chunk {} was evicted, reading {:?} again",
+                self.id, self.path
+            );
+            match self.format {
+                Some(format) => chunker.hashify_as(&self.path, format).await?,
+                None => chunker.hashify(&self.path).await?,
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Content of `batch` as it goes up, read from the chunk store one chunk at
+/// a time while the request is being sent.
+fn upload_stream(
+    chunker: Arc<Mutex<Chunker>>,
+    batch: Vec<PendingChunk>,
+) -> impl Stream<Item = Result<(String, Vec<u8>)>> {
+    async_stream::try_stream! {
+        for c in batch {
+            let data = c.read(&mut *chunker.lock().await).await?;
+
+            yield (c.id, data);
+        }
+    }
+}
+
+/// jid of the version local changes of `path` are based on, 0 if it was
+/// never synced, so a file created here doesn't replace one created on
+/// another device unseen.
+fn parent_jid(conn: &mut Connection, namespace_id: i32, path: &str) -> Result<i32> {
+    let synced = registry::latest_synced(conn, namespace_id, path)?;
+
+    Ok(synced.and_then(|r| r.jid).unwrap_or(0))
+}
+
+/// The latest version of the file of `record` as the server described it in
+/// a conflict, in the shape `list` returns.
+fn remote_record(
+    chunker: &Chunker,
+    record: &models::FileRecord,
+    jid: i32,
+    chunk_ids: String,
+    deleted: bool,
+) -> Result<ResponseFileRecord> {
+    Ok(ResponseFileRecord {
+        id: jid,
+        path: remote_path(chunker, &record.path)?,
+        deleted,
+        chunk_ids,
+        moved_from: None,
+    })
+}
+
 pub async fn check_download_once(
     pool: &ConnectionPool,
     chunker: Arc<Mutex<Chunker>>,
     remote: &Remote,
     storage_path: &Path,
     namespace_id: i32,
+    listener: Option<&dyn SyncStatusListener>,
 ) -> Result<bool> {
     debug!("download scan");
 
     let conn = &mut get_connection(pool)?;
 
     let latest_local = registry::latest_jid(conn, namespace_id).unwrap_or(0);
-    let to_download = remote.list(latest_local).await?;
-    // TODO maybe should limit one download at a time and use batches
-    // it can also overflow in-memory cache
-    let mut download_queue: Vec<&str> = vec![];
+    let mut to_download = remote.list(latest_local).await?;
+
+    if !remote.network().is_metered() {
+        let deferred = resume_deferred(conn, &chunker, namespace_id, &to_download).await?;
+        to_download.extend(deferred);
+    }
+
+    apply_remote(
+        conn,
+        &chunker,
+        remote,
+        storage_path,
+        namespace_id,
+        listener,
+        to_download,
+    )
+    .await
+}
+
+/// Files left for later on a metered network, as `list` would return them.
+/// Ones that have a newer version by now, here or in `listed`, are
+/// forgotten instead.
+async fn resume_deferred(
+    conn: &mut Connection,
+    chunker: &Mutex<Chunker>,
+    namespace_id: i32,
+    listed: &[ResponseFileRecord],
+) -> Result<Vec<ResponseFileRecord>> {
+    let deferred = registry::deferred_downloads(conn, namespace_id)?;
+
+    if deferred.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let chunker = chunker.lock().await;
+    let listed: HashSet<String> = listed
+        .iter()
+        .filter_map(|r| local_path(&chunker, &r.path).ok())
+        .filter_map(|path| sync_path::from_remote(&path).ok())
+        .collect();
+
+    let mut resumed = vec![];
+
+    for d in deferred {
+        let synced = registry::latest_synced(conn, namespace_id, &d.path)?.and_then(|r| r.jid);
+
+        if synced.is_some_and(|jid| jid >= d.jid) || listed.contains(&d.path) {
+            registry::drop_deferred(conn, namespace_id, &d.path)?;
+            continue;
+        }
+
+        resumed.push(ResponseFileRecord {
+            id: d.jid,
+            path: remote_path(&chunker, &d.path)?,
+            deleted: false,
+            chunk_ids: d.chunk_ids,
+            moved_from: match d.moved_from {
+                Some(from) => Some(remote_path(&chunker, &from)?),
+                None => None,
+            },
+        });
+    }
+
+    debug!("resuming {} deferred downloads", resumed.len());
+
+    Ok(resumed)
+}
+
+/// Records what a download that was cut short, e.g. by a crash, got on
+/// disk. Files with the content they were downloaded with, or gone for
+/// deletes, count as downloaded. Others weren't reached and are newer than
+/// anything recorded, so they get listed again, or are local edits that
+/// were kept and go up as such. Has to run before the indexer, which would
+/// take the downloaded files for local changes. Returns how many files were
+/// recorded.
+pub fn recover_interrupted_download(
+    pool: &ConnectionPool,
+    storage_path: &Path,
+    namespace_id: i32,
+    formats: &FileFormatRegistry,
+) -> Result<usize> {
+    let conn = &mut get_connection(pool)?;
+    let intents = registry::download_intents(conn, namespace_id)?;
+
+    if intents.is_empty() {
+        return Ok(0);
+    }
+
+    let mut created = vec![];
+    let mut deleted = vec![];
+
+    for intent in &intents {
+        let local = sync_path::to_local(storage_path, &intent.path);
+
+        match &intent.checksum {
+            None if !local.exists() => deleted.push(build_delete_form(
+                &intent.path,
+                storage_path,
+                intent.jid,
+                namespace_id,
+            )),
+            Some(checksum) if chunker::file_checksum(&local).ok().as_ref() == Some(checksum) => {
+                let mut form = build_file_record(
+                    &intent.path,
+                    storage_path,
+                    intent.jid,
+                    namespace_id,
+                    formats.detect(&local),
+                    Some(checksum.clone()),
+                )?;
+                form.chunk_ids = Some(intent.chunk_ids.clone());
+                created.push(form);
+            }
+            _ => trace!("{:?} wasn't downloaded", intent.path),
+        }
+    }
+
+    let recovered = created.len() + deleted.len();
+    debug!(
+        "recovered {} of {} files of an interrupted download",
+        recovered,
+        intents.len()
+    );
+
+    let jids: Vec<i32> = intents.iter().map(|i| i.jid).collect();
+    registry::finish_download(conn, namespace_id, &jids, &created, &deleted)?;
+
+    Ok(recovered)
+}
+
+/// Brings the remote records `to_download`, as `list` returns them, to disk
+/// and into the registry. Returns whether there were any.
+async fn apply_remote(
+    conn: &mut Connection,
+    chunker: &Mutex<Chunker>,
+    remote: &Remote,
+    storage_path: &Path,
+    namespace_id: i32,
+    listener: Option<&dyn SyncStatusListener>,
+    mut to_download: Vec<ResponseFileRecord>,
+) -> Result<bool> {
+    {
+        let chunker = chunker.lock().await;
+        for d in &mut to_download {
+            d.path = local_path(&chunker, &d.path)?;
+            d.moved_from = match &d.moved_from {
+                Some(from) => Some(local_path(&chunker, from)?),
+                None => None,
+            };
+        }
+    }
+
+    // A bad path only costs its own file, the rest still gets applied.
+    let mut skipped = vec![];
+    to_download.retain_mut(|d| match sync_path::from_remote(&d.path) {
+        Ok(path) => {
+            d.path = path;
+            // Without a usable old path it's downloaded like a new file.
+            d.moved_from = d
+                .moved_from
+                .as_deref()
+                .and_then(|from| sync_path::from_remote(from).ok());
+            true
+        }
+        Err(e) => {
+            error!("skipping jid {}: {}", d.id, e);
+            // Recorded like a delete, so it isn't listed again on every poll.
+            skipped.push(build_delete_form(&d.path, storage_path, d.id, namespace_id));
+            false
+        }
+    });
+
+    // Ignored here, whatever other devices sync. They're recorded like
+    // skipped paths, so they aren't listed again either.
+    let mut rules = IgnoreRules::new(storage_path);
+    to_download.retain(|d| {
+        let local = sync_path::to_local(storage_path, &d.path);
+        rules.add_dirs_above(&local);
+        if !rules.is_ignored(&local, false) {
+            return true;
+        }
+        debug!("not syncing jid {}, {:?} is ignored", d.id, d.path);
+        skipped.push(build_delete_form(&d.path, storage_path, d.id, namespace_id));
+        false
+    });
+
+    // Binary files that need downloading wait for an unmetered network. The
+    // rest goes on as usual, so they're remembered apart from the registry.
+    // Anything not known to be text may be binary and waits too.
+    if remote.network().is_metered() {
+        let chunker = chunker.lock().await;
+        let mut deferred = vec![];
+        let mut kept = Vec::with_capacity(to_download.len());
+
+        for d in to_download {
+            let defer = !d.deleted
+                && synced_format(conn, &chunker, namespace_id, &d.path)?
+                    != Some(models::FileFormat::Text)
+                && d.chunk_ids.split(',').any(|c| !chunker.check_chunk(c));
+
+            if defer {
+                deferred.push(models::DeferredDownload {
+                    namespace_id,
+                    jid: d.id,
+                    path: d.path.clone(),
+                    chunk_ids: d.chunk_ids.clone(),
+                    moved_from: d.moved_from.clone(),
+                });
+            } else {
+                kept.push(d);
+            }
+        }
+        to_download = kept;
+
+        if !deferred.is_empty() {
+            debug!(
+                "deferring {} binary files on a metered network",
+                deferred.len()
+            );
+            registry::defer_download(conn, &deferred)?;
+        }
+    }
+
+    if to_download.is_empty() {
+        registry::delete(conn, &skipped)?;
+        return Ok(false);
+    }
+
+    // In commit order, so when it's interrupted whatever made it to disk is
+    // older than whatever didn't.
+    to_download.sort_by_key(|d| d.id);
+
+    let case_conflicts = if chunker.lock().await.is_case_insensitive() {
+        resolve_case_conflicts(conn, namespace_id, &mut to_download)?
+    } else {
+        vec![]
+    };
+
+    let renames = local_renames(
+        conn,
+        &*chunker.lock().await,
+        storage_path,
+        namespace_id,
+        &to_download,
+    )?;
+
+    // Chunks are queued in commit order, so files can be written in that
+    // order as the batches come in. Each file waits for the batches that
+    // have its chunks, and those of the files before it.
+    let mut batches: Vec<Vec<&str>> = vec![];
+    // Estimated bytes of each batch, chunk sizes aren't known beforehand.
+    let mut batch_sizes: Vec<usize> = vec![];
+    let mut queued: HashMap<&str, usize> = HashMap::new();
+    let mut ready_after: Vec<usize> = Vec::with_capacity(to_download.len());
 
     for d in &to_download {
         trace!("collecting needed chunks for {:?}", d);
 
-        if d.deleted {
+        let mut after = ready_after.last().copied().unwrap_or(0);
+
+        if d.deleted || renames.contains_key(&d.path) {
+            ready_after.push(after);
             continue;
         }
 
         let mut chunker = chunker.lock().await;
 
-        // Warm-up cache to include chunks from an old file
-        if chunker.exists(&d.path) {
-            chunker.hashify(&d.path).await?;
+        let mut missing: Vec<&str> = d
+            .chunk_ids
+            .split(',')
+            .filter(|c| !chunker.check_chunk(c))
+            .collect();
+
+        // The chunk store persists between runs, so the local copy only needs
+        // rehashing when it may hold chunks the store doesn't have anymore.
+        // When it's unchanged since it was hashed, it's known whether it does.
+        let known = match missing.is_empty() {
+            true => None,
+            false => match registry::latest_for_path(conn, namespace_id, &d.path)? {
+                Some(r) if !r.deleted && unchanged_on_disk(&r, storage_path).await? => r.chunk_ids,
+                _ => None,
+            },
+        };
+        let may_hold = known.is_none_or(|ids| ids.split(',').any(|c| missing.contains(&c)));
+
+        if !missing.is_empty() && may_hold && chunker.exists(&d.path) {
+            // Best effort, whatever can't be recovered gets downloaded.
+            if let Err(e) = chunker.hashify(&d.path).await {
+                debug!("can't reuse local copy of {:?}: {}", d.path, e);
+            }
+            missing.retain(|c| !chunker.check_chunk(c));
+        }
+
+        let format = synced_format(conn, &chunker, namespace_id, &d.path)?;
+        let chunk_size = chunk_size_estimate(&chunker, format);
+
+        for c in missing {
+            let batch = match queued.get(c) {
+                Some(&batch) => batch,
+                None => {
+                    let full = batches
+                        .last()
+                        .zip(batch_sizes.last())
+                        .is_none_or(|(b, size)| {
+                            b.len() >= MAX_DOWNLOAD_CHUNKS || size + chunk_size > MAX_DOWNLOAD_SIZE
+                        });
+                    if full {
+                        batches.push(vec![]);
+                        batch_sizes.push(0);
+                    }
+                    batches.last_mut().unwrap().push(c);
+                    *batch_sizes.last_mut().unwrap() += chunk_size;
+                    queued.insert(c, batches.len());
+                    batches.len()
+                }
+            };
+            after = after.max(batch);
+        }
+
+        ready_after.push(after);
+    }
+
+    // Counts the batches that made it to the chunk store. Once the sender is
+    // gone, no more are coming.
+    let (landed_tx, mut landed_rx) = watch::channel(0);
+    // Chunks of landed batches that didn't match their ID.
+    let corrupted = &std::sync::Mutex::new(HashSet::new());
+
+    let download = async move {
+        let mut downloads = futures::stream::iter(batches)
+            .map(|batch| download_batch(chunker, remote, batch))
+            .buffered(CONCURRENT_DOWNLOADS);
+
+        while let Some(downloaded) = downloads.next().await {
+            corrupted.lock().unwrap().extend(downloaded?);
+            landed_tx.send_modify(|landed| *landed += 1);
         }
 
-        for c in d.chunk_ids.split(',') {
-            if chunker.check_chunk(c) {
+        Ok::<_, SyncError>(())
+    };
+
+    let mut applied = Applied::default();
+    let write = async {
+        for (d, after) in to_download.iter().zip(ready_after) {
+            if landed_rx.wait_for(|landed| *landed >= after).await.is_err() {
+                // The download failed, it tells why.
+                break;
+            }
+
+            // Tried again with the next download, the rest carries on.
+            let unverified: Vec<String> = {
+                let corrupted = corrupted.lock().unwrap();
+                d.chunk_ids
+                    .split(',')
+                    .filter(|c| corrupted.contains(*c))
+                    .map(String::from)
+                    .collect()
+            };
+            if !unverified.is_empty() {
+                applied.unverified.push((d, unverified));
                 continue;
             }
 
-            download_queue.push(c);
+            apply_file(
+                conn,
+                chunker,
+                storage_path,
+                namespace_id,
+                &renames,
+                d,
+                &mut applied,
+            )
+            .await?;
         }
+
+        Ok::<_, SyncError>(())
+    };
+
+    let (downloaded, written) = tokio::join!(download, write);
+
+    // Only once everything else got through, or the next download would
+    // start after what didn't.
+    if downloaded.is_ok() && written.is_ok() {
+        applied.deleted.extend(skipped);
     }
 
-    if !download_queue.is_empty() {
-        let mut chunker = chunker.lock().await;
+    // Whatever made it to disk goes into the registry even if the rest
+    // didn't. It's all older than what's missing, so the next download
+    // picks up right after it.
+    registry::finish_download(
+        conn,
+        namespace_id,
+        &applied.jids,
+        &applied.created,
+        &applied.deleted,
+    )?;
 
-        let mut downloaded = remote.download_batch(download_queue).await;
+    // Left for later like on a metered network, the files after them are
+    // recorded and the next download starts past them.
+    let deferred: Vec<models::DeferredDownload> = applied
+        .unverified
+        .iter()
+        .map(|(d, _)| models::DeferredDownload {
+            namespace_id,
+            jid: d.id,
+            path: d.path.clone(),
+            chunk_ids: d.chunk_ids.clone(),
+            moved_from: d.moved_from.clone(),
+        })
+        .collect();
+    if !deferred.is_empty() {
+        registry::defer_download(conn, &deferred)?;
+    }
 
-        while let Some(result) = downloaded.next().await {
-            match result {
-                Ok((chunk_id, data)) => {
-                    chunker.save_chunk(&chunk_id, data)?;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
+    for (path, local_path) in case_conflicts {
+        warn!(
+            "{:?} only differs in case from another file, saved as {:?}",
+            path, local_path
+        );
+        if let Some(cb) = listener {
+            cb.on_status_changed(SyncStatus::CaseConflict { path, local_path });
+        }
+    }
+
+    for (d, chunk_ids) in applied.unverified {
+        let error = SyncError::IntegrityError(chunk_ids.join(","));
+        warn!("{:?} wasn't downloaded: {}", d.path, error);
+        if let Some(cb) = listener {
+            cb.on_status_changed(SyncStatus::Error {
+                message: format!("{}: {}", d.path, error),
+            });
+        }
+    }
+
+    for (path, conflicted_copy) in applied.conflicts {
+        warn!(
+            "{:?} was changed here and remotely, local version kept as {:?}",
+            path, conflicted_copy
+        );
+        if let Some(cb) = listener {
+            cb.on_status_changed(SyncStatus::Conflict {
+                path,
+                conflicted_copy,
+            });
+        }
+    }
+
+    written?;
+    downloaded?;
+
+    Ok(true)
+}
+
+/// What `apply_file` did so far, for the registry and the listener.
+#[derive(Default)]
+struct Applied<'a> {
+    jids: Vec<i32>,
+    created: Vec<models::CreateForm>,
+    deleted: Vec<models::DeleteForm>,
+    conflicts: Vec<(String, String)>,
+    /// Files not written because of chunks that kept failing verification.
+    unverified: Vec<(&'a ResponseFileRecord, Vec<String>)>,
+}
+
+/// Brings remote record `d` to disk, once its chunks are in the store. The
+/// registry rows it needs go to `applied`, to be written with the rest of
+/// the batch.
+async fn apply_file(
+    conn: &mut Connection,
+    chunker: &Mutex<Chunker>,
+    storage_path: &Path,
+    namespace_id: i32,
+    renames: &HashMap<String, models::FileRecord>,
+    d: &ResponseFileRecord,
+    applied: &mut Applied<'_>,
+) -> Result<()> {
+    trace!("udpating downloaded files {:?}", d);
+
+    let mut chunker = chunker.lock().await;
+
+    let intent = download_intent(&chunker, renames, namespace_id, d)?;
+    registry::begin_download(conn, &[intent])?;
+    applied.jids.push(d.id);
+
+    if d.deleted {
+        let edited = edited_locally(conn, storage_path, namespace_id, &d.path).await?;
+        applied
+            .deleted
+            .push(build_delete_form(&d.path, storage_path, d.id, namespace_id));
+        // Unless it moved, then it's renamed with the record it moved to.
+        // A local edit outlives the delete, the indexer uploads it again.
+        let moved = renames.values().any(|r| r.path == d.path);
+        if !moved && !edited && chunker.exists(&d.path) {
+            chunker.delete(&d.path).await?;
+        }
+    } else if let Some(synced) = renames.get(&d.path) {
+        chunker.rename(&synced.path, &d.path).await?;
+
+        // As last synced, local edits made to the old path come along
+        // and get picked up by the indexer as edits of the new one.
+        let form = models::CreateForm {
+            jid: Some(d.id),
+            path: d.path.clone(),
+            deleted: false,
+            size: synced.size,
+            modified_at: synced.modified_at,
+            namespace_id,
+            format: synced.format,
+            checksum: synced.checksum.clone(),
+            moved_from: None,
+            chunk_ids: Some(d.chunk_ids.clone()),
+        };
+        applied.created.push(form);
+    } else {
+        let chunks: Vec<&str> = d.chunk_ids.split(',').collect();
+        // The file doesn't need reading back to know its checksum.
+        let checksum = chunker.checksum(&chunks)?;
+
+        // Unless both sides made the same change.
+        let edited = edited_locally(conn, storage_path, namespace_id, &d.path).await?
+            && differs_locally(storage_path, &d.path, &checksum).await;
+        let merged = match edited {
+            true => merge_local_edit(conn, &mut chunker, storage_path, namespace_id, d).await?,
+            false => None,
+        };
+        if edited && merged.is_none() {
+            let copy = keep_local_edit(&mut chunker, storage_path, &d.path).await?;
+            applied.conflicts.push((d.path.clone(), copy));
+        }
+
+        let to_save = match &merged {
+            Some(merged) => merged.iter().map(String::as_str).collect(),
+            None => chunks.clone(),
+        };
+        if let Err(e) = chunker.save(&d.path, to_save).await {
+            error!("{:?}", e);
+            return Err(e);
+        }
+
+        let format = chunker
+            .formats()
+            .detect(&sync_path::to_local(storage_path, &d.path));
+        let mut form = build_file_record(
+            &d.path,
+            storage_path,
+            d.id,
+            namespace_id,
+            format,
+            Some(checksum),
+        )?;
+        form.chunk_ids = Some(d.chunk_ids.clone());
+
+        match merged {
+            // The incoming version is synced and the merge is a local
+            // edit on top of it, which goes up with the next upload.
+            Some(_) => {
+                debug!("merged local and remote changes of {:?}", d.path);
+                let merge_form = models::CreateForm {
+                    jid: None,
+                    checksum: None,
+                    chunk_ids: None,
+                    ..form.clone()
+                };
+                form.size = chunks
+                    .iter()
+                    .map(|c| chunker.read_chunk(c).map(|data| data.len()))
+                    .sum::<Result<usize>>()?
+                    .try_into()?;
+                applied.created.extend([form, merge_form]);
             }
+            None => applied.created.push(form),
         }
     }
 
-    for d in &to_download {
-        trace!("udpating downloaded files {:?}", d);
+    Ok(())
+}
 
-        let mut chunker = chunker.lock().await;
+/// What applying `d` leaves on disk, recorded before it's applied.
+fn download_intent(
+    chunker: &Chunker,
+    renames: &HashMap<String, models::FileRecord>,
+    namespace_id: i32,
+    d: &ResponseFileRecord,
+) -> Result<models::DownloadIntent> {
+    let checksum = if d.deleted {
+        None
+    } else if let Some(synced) = renames.get(&d.path) {
+        // Its chunks aren't downloaded, but it has the content of the file
+        // it's renamed from.
+        synced.checksum.clone()
+    } else {
+        let chunks: Vec<&str> = d.chunk_ids.split(',').collect();
+        Some(chunker.checksum(&chunks)?)
+    };
+
+    Ok(models::DownloadIntent {
+        namespace_id,
+        jid: d.id,
+        path: d.path.clone(),
+        deleted: d.deleted,
+        chunk_ids: d.chunk_ids.clone(),
+        checksum,
+    })
+}
+
+/// Paths that only differ in case would overwrite each other on a
+/// case-insensitive filesystem. Whatever is there first keeps its name, the
+/// others are saved under `sync_path::case_conflict_path`, tombstones
+/// included so deletes find them. Only whole paths are compared. Returns
+/// the server and local paths of the renamed files.
+///
+/// The copy is registered with the jid of the server's file, so it isn't
+/// sent back as is. Once edited it goes up under its own name, a new file
+/// like a conflicted copy, and the file it came from is left alone.
+fn resolve_case_conflicts(
+    conn: &mut Connection,
+    namespace_id: i32,
+    to_download: &mut [ResponseFileRecord],
+) -> Result<Vec<(String, String)>> {
+    let mut taken: HashMap<String, String> = registry::non_deleted(conn, namespace_id)?
+        .into_iter()
+        .map(|r| (sync_path::fold_case(&r.path), r.path))
+        .collect();
+    let mut conflicts = vec![];
 
-        if d.deleted {
-            let form = build_delete_form(&d.path, storage_path, d.id, namespace_id);
-            // TODO atomic?
-            registry::delete(conn, &vec![form])?;
-            if chunker.exists(&d.path) {
-                chunker.delete(&d.path).await?;
+    for d in to_download {
+        let key = sync_path::fold_case(&d.path);
+        let conflict_path = sync_path::case_conflict_path(&d.path);
+
+        match taken.get(&key) {
+            Some(existing) if *existing == d.path => {
+                if d.deleted {
+                    taken.remove(&key);
+                }
             }
-        } else {
-            let chunks: Vec<&str> = d.chunk_ids.split(',').collect();
-            // TODO atomic? store in tmp first and then move?
-            // TODO should be after we create record in db
-            if let Err(e) = chunker.save(&d.path, chunks).await {
-                error!("{:?}", e);
-                return Err(e);
+            // Never the file that is there, even if its copy isn't either.
+            Some(_) if d.deleted => d.path = conflict_path,
+            Some(_) => {
+                conflicts.push((d.path.clone(), conflict_path.clone()));
+                taken.insert(sync_path::fold_case(&conflict_path), conflict_path.clone());
+                d.path = conflict_path;
             }
+            None if d.deleted => {}
+            None => {
+                taken.insert(key, d.path.clone());
+            }
+        }
+    }
 
-            let form = build_file_record(&d.path, storage_path, d.id, namespace_id)?;
-            registry::create(conn, &vec![form])?;
+    Ok(conflicts)
+}
+
+/// Whether the file at `path` was changed locally since it was last synced.
+/// Goes by the file itself, so edits the indexer hasn't seen yet count too.
+async fn edited_locally(
+    conn: &mut Connection,
+    storage_path: &Path,
+    namespace_id: i32,
+    path: &str,
+) -> Result<bool> {
+    if !sync_path::to_local(storage_path, path).exists() {
+        return Ok(false);
+    }
+
+    Ok(match registry::latest_synced(conn, namespace_id, path)? {
+        Some(synced) if !synced.deleted => !unchanged_on_disk(&synced, storage_path).await?,
+        // Created here, or again since it was deleted.
+        _ => true,
+    })
+}
+
+/// `indexer::unchanged_on_disk` on a blocking thread, it may read the
+/// whole file.
+async fn unchanged_on_disk(record: &models::FileRecord, storage_path: &Path) -> Result<bool> {
+    let record = record.clone();
+    let storage_path = storage_path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || indexer::unchanged_on_disk(&record, &storage_path))
+        .await
+        .map_err(chunker::join_error)
+}
+
+/// Whether the file at `path` exists and has other content than `checksum`.
+async fn differs_locally(storage_path: &Path, path: &str, checksum: &str) -> bool {
+    let local = sync_path::to_local(storage_path, path);
+
+    local.exists() && chunker::file_checksum_async(local).await.ok().as_deref() != Some(checksum)
+}
+
+/// Three-way merge of the local edit of text file `d.path` with the
+/// incoming version, line by line against the last synced one. `None` when
+/// it isn't chunked by lines, the last synced version isn't in the chunk
+/// store anymore, or both sides changed the same or adjacent lines.
+async fn merge_local_edit(
+    conn: &mut Connection,
+    chunker: &mut Chunker,
+    storage_path: &Path,
+    namespace_id: i32,
+    d: &ResponseFileRecord,
+) -> Result<Option<Vec<String>>> {
+    let format = chunker
+        .formats()
+        .detect(&sync_path::to_local(storage_path, &d.path));
+    let Some(format) = format else {
+        return Ok(None);
+    };
+    if chunker.formats().chunking(format) != Chunking::Lines {
+        return Ok(None);
+    }
+
+    let Some(base) = registry::latest_synced(conn, namespace_id, &d.path)?
+        .filter(|r| !r.deleted)
+        .and_then(|r| r.chunk_ids)
+    else {
+        return Ok(None);
+    };
+    let base: Vec<&str> = base.split(',').filter(|c| !c.is_empty()).collect();
+    if !base.iter().all(|c| chunker.check_chunk(c)) {
+        return Ok(None);
+    }
+
+    let ours = chunker.hashify_as(&d.path, format).await?;
+    let ours: Vec<&str> = ours.iter().map(String::as_str).collect();
+    let theirs: Vec<&str> = d.chunk_ids.split(',').filter(|c| !c.is_empty()).collect();
+
+    let merged = merge::three_way(&base, &ours, &theirs);
+
+    Ok(merged.map(|m| m.into_iter().map(String::from).collect()))
+}
+
+/// Moves the local edit of `path` out of the way of the incoming version,
+/// to a conflicted copy the indexer then picks up as a new file. Returns
+/// the copy's path.
+async fn keep_local_edit(chunker: &mut Chunker, storage_path: &Path, path: &str) -> Result<String> {
+    let mut n = 1;
+    let copy = loop {
+        let copy = sync_path::conflicted_copy_path(path, chunker.device_name(), n);
+        if !sync_path::to_local(storage_path, &copy).exists() {
+            break copy;
         }
+        n += 1;
+    };
+
+    chunker.rename(path, &copy).await?;
+
+    Ok(copy)
+}
+
+/// Moved files that can be renamed instead of downloaded, by new path,
+/// with the last synced record of the old one. That record must have the
+/// content the file was moved with, the old file must still be there and
+/// nothing else may be at the new path.
+fn local_renames(
+    conn: &mut Connection,
+    chunker: &Chunker,
+    storage_path: &Path,
+    namespace_id: i32,
+    to_download: &[ResponseFileRecord],
+) -> Result<HashMap<String, models::FileRecord>> {
+    let mut renames: HashMap<String, models::FileRecord> = HashMap::new();
+
+    for d in to_download {
+        let Some(from) = &d.moved_from else {
+            continue;
+        };
+        if d.deleted || renames.values().any(|r| r.path == *from) {
+            continue;
+        }
+
+        // Looked up by exact path, a case conflict copy is never the file.
+        let Some(synced) = registry::latest_synced(conn, namespace_id, from)? else {
+            continue;
+        };
+        if synced.deleted || synced.checksum.is_none() {
+            continue;
+        }
+
+        // Chunks that aren't in the store anymore mean a download anyway.
+        let chunks: Vec<&str> = d.chunk_ids.split(',').collect();
+        if chunker.checksum(&chunks).ok() != synced.checksum {
+            continue;
+        }
+
+        let case_only = sync_path::fold_case(from) == sync_path::fold_case(&d.path);
+        let in_the_way = sync_path::to_local(storage_path, &d.path).exists() && !case_only;
+        if !sync_path::to_local(storage_path, from).exists() || in_the_way {
+            continue;
+        }
+
+        renames.insert(d.path.clone(), synced);
     }
 
-    Ok(!to_download.is_empty())
+    Ok(renames)
+}
+
+/// Size a chunk of a file in `format` has at most, as far as it's known.
+/// Anything not known to be text could be binary.
+fn chunk_size_estimate(chunker: &Chunker, format: Option<models::FileFormat>) -> usize {
+    let format = format.unwrap_or(models::FileFormat::Binary);
+
+    chunker
+        .formats()
+        .chunking(format)
+        .max_chunk_size()
+        .unwrap_or(LINE_SIZE_ESTIMATE)
+}
+
+/// Format `path` was last synced as, or the one its name suggests if it
+/// wasn't synced before. Remote records don't say.
+fn synced_format(
+    conn: &mut Connection,
+    chunker: &Chunker,
+    namespace_id: i32,
+    path: &str,
+) -> Result<Option<models::FileFormat>> {
+    let stored = registry::latest_for_path(conn, namespace_id, path)?.and_then(|r| r.format);
+
+    Ok(stored.or_else(|| chunker.formats().detect_by_name(Path::new(path))))
+}
+
+/// Whether the file at `path` is synced as binary, going by its `format`
+/// if it's known.
+fn is_binary(chunker: &Chunker, format: Option<models::FileFormat>, path: &str) -> bool {
+    format.or_else(|| chunker.formats().detect_by_name(Path::new(path)))
+        == Some(models::FileFormat::Binary)
+}
+
+/// Path as the server stores it, encrypted when a passphrase is set.
+fn remote_path(chunker: &Chunker, path: &str) -> Result<String> {
+    match chunker.cipher() {
+        Some(cipher) => cipher.encrypt_path(path),
+        None => Ok(path.to_string()),
+    }
+}
+
+fn local_path(chunker: &Chunker, path: &str) -> Result<String> {
+    match chunker.cipher() {
+        Some(cipher) => cipher.decrypt_path(path),
+        None if crypto::is_encrypted_path(path) => Err(missing_passphrase()),
+        None => Ok(path.to_string()),
+    }
+}
+
+/// Chunk content as uploaded, only chunks with keyed IDs are encrypted.
+fn seal_chunk(chunker: &Chunker, id: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+    match chunker.cipher() {
+        Some(cipher) if chunk_id::is_keyed(id) => cipher.encrypt_chunk(&data),
+        _ => Ok(data),
+    }
+}
+
+/// Downloads `chunk_ids` to the chunk store. Chunks that don't match their
+/// ID are asked for once more, could be a glitch on the way. Returns the
+/// IDs of those that still don't.
+async fn download_batch(
+    chunker: &Mutex<Chunker>,
+    remote: &Remote,
+    chunk_ids: Vec<&str>,
+) -> Result<Vec<String>> {
+    let mut corrupted = download_chunks(chunker, remote, chunk_ids).await?;
+
+    if !corrupted.is_empty() {
+        debug!(
+            "retrying {} chunks that failed verification",
+            corrupted.len()
+        );
+        let retry = corrupted.iter().map(String::as_str).collect();
+        corrupted = download_chunks(chunker, remote, retry).await?;
+    }
+
+    for chunk_id in &corrupted {
+        error!("downloaded content doesn't match chunk {}", chunk_id);
+    }
+
+    Ok(corrupted)
+}
+
+/// Saves the chunks that match their ID, returns the IDs of those that
+/// don't. The chunker is only locked to save them, a few at a time.
+async fn download_chunks(
+    chunker: &Mutex<Chunker>,
+    remote: &Remote,
+    chunk_ids: Vec<&str>,
+) -> Result<Vec<String>> {
+    let mut corrupted = vec![];
+    let mut received = vec![];
+    let mut size = 0;
+    let mut downloaded = remote.download_batch(chunk_ids).await;
+
+    while let Some(result) = downloaded.next().await {
+        let (chunk_id, data) = result?;
+        size += data.len();
+        received.push((chunk_id, data));
+
+        if size > MAX_DOWNLOAD_SIZE {
+            let mut chunker = chunker.lock().await;
+            corrupted.extend(save_chunks(&mut chunker, received.drain(..))?);
+            size = 0;
+        }
+    }
+
+    let mut chunker = chunker.lock().await;
+    corrupted.extend(save_chunks(&mut chunker, received)?);
+
+    Ok(corrupted)
+}
+
+fn save_chunks(
+    chunker: &mut Chunker,
+    received: impl IntoIterator<Item = (String, Vec<u8>)>,
+) -> Result<Vec<String>> {
+    let mut corrupted = vec![];
+
+    for (chunk_id, data) in received {
+        match open_chunk(chunker, &chunk_id, data)? {
+            Some(data) => chunker.save_chunk(&chunk_id, data)?,
+            None => corrupted.push(chunk_id),
+        }
+    }
+
+    Ok(corrupted)
+}
+
+/// Decrypted content of a downloaded chunk, `None` if it doesn't match the
+/// chunk's ID. One that doesn't decrypt at all was sealed with another
+/// passphrase, which retrying won't change.
+fn open_chunk(chunker: &Chunker, id: &str, data: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    let data = if chunk_id::is_keyed(id) {
+        let cipher = chunker.cipher().ok_or_else(missing_passphrase)?;
+
+        cipher.decrypt_chunk(&data).map_err(|e| {
+            SyncError::EncryptionError(format!(
+                "can't decrypt chunk {}, is the passphrase right? {}",
+                id, e
+            ))
+        })?
+    } else {
+        data
+    };
+
+    Ok(chunker.verify_chunk(id, &data).then_some(data))
+}
+
+fn missing_passphrase() -> SyncError {
+    SyncError::EncryptionError("remote data is encrypted, passphrase isn't set".to_string())
 }
 
 fn build_file_record(
@@ -335,10 +1727,10 @@ fn build_file_record(
     base: &Path,
     jid: i32,
     namespace_id: i32,
+    format: Option<models::FileFormat>,
+    checksum: Option<String>,
 ) -> Result<models::CreateForm, SyncError> {
-    let mut full_path = base.to_path_buf();
-    full_path.push(path);
-    let metadata = full_path
+    let metadata = sync_path::to_local(base, path)
         .metadata()
         .map_err(|e| SyncError::from_io_error(path, e))?;
     let size: i64 = metadata.len().try_into()?;
@@ -356,6 +1748,10 @@ fn build_file_record(
         size,
         modified_at,
         namespace_id,
+        format,
+        checksum,
+        moved_from: None,
+        chunk_ids: None,
     };
 
     Ok(form)
@@ -108,3 +108,129 @@ fn child_tokens_are_independent_of_each_other() {
     assert!(a.is_cancelled());
     assert!(!b.is_cancelled(), "sibling token should not be affected");
 }
+
+#[test]
+fn binary_chunking_defaults_to_fixed_size_and_is_configurable() {
+    use cooklang_sync_client::chunker::Chunking;
+    use cooklang_sync_client::models::FileFormat;
+
+    let ctx = SyncContext::new();
+    assert_eq!(
+        ctx.formats().chunking(FileFormat::Binary),
+        Chunking::FixedSize
+    );
+
+    ctx.set_content_defined_chunking(1_024, 4_096, 16_384)
+        .expect("valid sizes");
+    assert_eq!(
+        ctx.formats().chunking(FileFormat::Binary),
+        Chunking::ContentDefined {
+            min_size: 1_024,
+            avg_size: 4_096,
+            max_size: 16_384
+        }
+    );
+
+    assert!(ctx.set_content_defined_chunking(1, 2, 3).is_err());
+    // A rejected config leaves the previous one in place.
+    assert!(matches!(
+        ctx.formats().chunking(FileFormat::Binary),
+        Chunking::ContentDefined { .. }
+    ));
+}
+
+#[test]
+fn registered_formats_are_visible_to_run() {
+    use cooklang_sync_client::models::FileFormat;
+    use std::path::Path;
+
+    let ctx = SyncContext::new();
+    assert_eq!(ctx.formats().detect(Path::new("notes.txt")), None);
+
+    ctx.add_text_extension("txt".to_string());
+    ctx.add_binary_filename("cover".to_string());
+
+    let formats = ctx.formats();
+    assert_eq!(
+        formats.detect(Path::new("notes.txt")),
+        Some(FileFormat::Text)
+    );
+    assert_eq!(
+        formats.detect(Path::new("a/cover")),
+        Some(FileFormat::Binary)
+    );
+}
+
+#[test]
+fn encryption_passphrase_can_be_set_and_cleared() {
+    let ctx = SyncContext::new();
+    assert!(!ctx.is_encryption_enabled());
+    assert!(ctx.cipher().is_none());
+
+    assert!(ctx
+        .set_encryption_passphrase(String::new(), "cook@example.com".to_string())
+        .is_err());
+    assert!(!ctx.is_encryption_enabled());
+
+    ctx.set_encryption_passphrase("correct horse".to_string(), "cook@example.com".to_string())
+        .expect("valid passphrase");
+    assert!(ctx.is_encryption_enabled());
+    assert!(ctx.cipher().is_some());
+
+    ctx.clear_encryption_passphrase();
+    assert!(!ctx.is_encryption_enabled());
+}
+
+#[test]
+fn upload_concurrency_defaults_and_is_configurable() {
+    let ctx = SyncContext::new();
+    assert_eq!(
+        ctx.upload_concurrency(),
+        cooklang_sync_client::remote::DEFAULT_UPLOAD_CONCURRENCY
+    );
+
+    ctx.set_upload_concurrency(1);
+    assert_eq!(ctx.upload_concurrency(), 1);
+
+    ctx.set_upload_concurrency(0);
+    assert_eq!(
+        ctx.upload_concurrency(),
+        1,
+        "at least one upload has to run"
+    );
+}
+
+#[test]
+fn network_settings_reach_the_shared_policy() {
+    let ctx = SyncContext::new();
+    let network = ctx.network();
+    assert!(!ctx.is_metered());
+    assert_eq!(network.upload().limit(), 0, "no limit by default");
+
+    ctx.set_upload_limit(50_000);
+    ctx.set_download_limit(200_000);
+    ctx.set_metered(true);
+
+    // Changed on the policy a running sync holds already.
+    assert_eq!(network.upload().limit(), 50_000);
+    assert_eq!(network.download().limit(), 200_000);
+    assert!(network.is_metered());
+}
+
+#[tokio::test]
+async fn controls_reach_the_running_sync() {
+    let ctx = SyncContext::new();
+    let control = ctx.control();
+    let mut wake_ups = control.wake_ups();
+    assert!(!ctx.is_paused());
+
+    ctx.pause();
+    assert!(control.is_paused());
+    ctx.resume();
+    assert!(!control.is_paused());
+
+    ctx.sync_now();
+    tokio::time::timeout(std::time::Duration::from_secs(1), wake_ups.next())
+        .await
+        .expect("woken up");
+}
@@ -3,12 +3,15 @@
 mod common;
 
 use cooklang_sync_client::connection::get_connection;
+use cooklang_sync_client::control::SyncControl;
+use cooklang_sync_client::file_format::FileFormatRegistry;
 use cooklang_sync_client::indexer::run;
 use cooklang_sync_client::models::{FileRecord, IndexerUpdateEvent};
 use cooklang_sync_client::schema::file_records;
 use diesel::prelude::*;
 use futures::channel::mpsc;
 use futures::{SinkExt, StreamExt};
+use notify_debouncer_mini::{DebouncedEvent, DebouncedEventKind};
 use std::fs;
 use std::time::Duration;
 use tokio::time::timeout;
@@ -35,10 +38,12 @@ async fn run_emits_update_event_on_initial_scan_and_on_subsequent_fs_event() {
     let join = tokio::spawn(async move {
         run(
             token_for_loop,
+            &SyncControl::default(),
             None, // no listener
             &pool_cloned,
             &storage_path,
             NS,
+            &FileFormatRegistry::default(),
             fs_rx,
             updated_tx,
         )
@@ -105,10 +110,12 @@ async fn run_exits_cleanly_when_cancelled_immediately() {
         Duration::from_secs(5),
         run(
             token,
+            &SyncControl::default(),
             None,
             &pool,
             storage.path(),
             NS,
+            &FileFormatRegistry::default(),
             fs_rx,
             updated_tx,
         ),
@@ -117,3 +124,73 @@ async fn run_exits_cleanly_when_cancelled_immediately() {
     .expect("run must exit within 5s when token is already cancelled")
     .expect("run returns Ok");
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn run_reindexes_only_paths_from_fs_events() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = tempfile::TempDir::new().unwrap();
+    fs::write(storage.path().join("a.cook"), b"v1").unwrap();
+
+    let (mut fs_tx, fs_rx) = mpsc::channel::<notify_debouncer_mini::DebounceEventResult>(8);
+    let (updated_tx, mut updated_rx) = mpsc::channel::<IndexerUpdateEvent>(8);
+
+    let token = CancellationToken::new();
+    let token_for_loop = token.clone();
+
+    let pool_cloned = pool.clone();
+    let storage_path = storage.path().to_path_buf();
+    let join = tokio::spawn(async move {
+        run(
+            token_for_loop,
+            &SyncControl::default(),
+            None,
+            &pool_cloned,
+            &storage_path,
+            NS,
+            &FileFormatRegistry::default(),
+            fs_rx,
+            updated_tx,
+        )
+        .await
+    });
+
+    timeout(Duration::from_secs(5), updated_rx.next())
+        .await
+        .expect("initial Updated event within 5s")
+        .expect("channel not closed");
+
+    // Only b.cook is reported, c.cook waits for the next full scan.
+    fs::write(storage.path().join("b.cook"), b"new").unwrap();
+    fs::write(storage.path().join("c.cook"), b"unreported").unwrap();
+    fs_tx
+        .send(Ok(vec![DebouncedEvent {
+            path: storage.path().join("b.cook"),
+            kind: DebouncedEventKind::Any,
+        }]))
+        .await
+        .expect("push debounce event");
+
+    timeout(Duration::from_secs(5), updated_rx.next())
+        .await
+        .expect("second Updated event within 5s")
+        .expect("channel not closed");
+
+    token.cancel();
+    timeout(Duration::from_secs(5), join)
+        .await
+        .expect("loop must exit within 5s of cancel")
+        .expect("task joined")
+        .expect("run returns Ok on cancel");
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let mut paths: Vec<String> = file_records::table
+        .filter(file_records::deleted.eq(false))
+        .select(FileRecord::as_select())
+        .load(conn)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    paths.sort();
+    assert_eq!(paths, vec!["a.cook".to_string(), "b.cook".to_string()]);
+}
@@ -3,11 +3,14 @@
 //! Each test creates a fresh `TempDir`, drops files into it, runs
 //! `check_index_once`, and asserts the resulting state of `file_records`.
 
+#![allow(clippy::useless_vec)]
+
 mod common;
 
 use cooklang_sync_client::connection::get_connection;
-use cooklang_sync_client::indexer::check_index_once;
-use cooklang_sync_client::models::{CreateForm, FileRecord};
+use cooklang_sync_client::file_format::FileFormatRegistry;
+use cooklang_sync_client::indexer::{check_index_once, check_index_paths_once};
+use cooklang_sync_client::models::{CreateForm, DownloadIntent, FileFormat, FileRecord};
 use cooklang_sync_client::registry;
 use cooklang_sync_client::schema::file_records;
 use diesel::prelude::*;
@@ -40,7 +43,8 @@ fn check_index_once_records_new_file() {
     let storage = storage_dir();
     write(&storage, "recipes/soup.cook", b"title: Soup\n");
 
-    let changed = check_index_once(&pool, storage.path(), NS).expect("scan");
+    let changed =
+        check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).expect("scan");
     assert!(changed, "new file must cause an update");
 
     let conn = &mut get_connection(&pool).unwrap();
@@ -59,9 +63,11 @@ fn check_index_once_is_noop_when_nothing_changed() {
     let storage = storage_dir();
     write(&storage, "a.cook", b"hello");
 
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
-    assert!(!check_index_once(&pool, storage.path(), NS).unwrap(),
-        "second scan with no FS changes must return false");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+    assert!(
+        !check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap(),
+        "second scan with no FS changes must return false"
+    );
 
     let conn = &mut get_connection(&pool).unwrap();
     let rows: i64 = file_records::table
@@ -76,14 +82,14 @@ fn check_index_once_appends_a_new_row_when_file_is_modified() {
     let (pool, _db_dir) = common::fresh_client_pool();
     let storage = storage_dir();
     let path = write(&storage, "a.cook", b"v1");
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     // Rewrite content with a different size and advance mtime by >=1 second
     // so truncate_to_seconds still produces a distinguishable value.
     std::thread::sleep(std::time::Duration::from_millis(1100));
     fs::write(&path, b"v2-longer").unwrap();
 
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     let conn = &mut get_connection(&pool).unwrap();
     let rows: Vec<FileRecord> = file_records::table
@@ -109,15 +115,79 @@ fn check_index_once_appends_a_new_row_when_file_is_modified() {
     assert_eq!(live[0].id, rows[1].id);
 }
 
+#[test]
+fn check_index_once_ignores_touch_without_content_change() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    let path = write(&storage, "a.cook", b"hello");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    // Like a backup tool restoring the file with an older mtime.
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    assert!(
+        !check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap(),
+        "same content under a new mtime isn't a change"
+    );
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let rows: Vec<FileRecord> = file_records::table
+        .select(FileRecord::as_select())
+        .load(conn)
+        .unwrap();
+    assert_eq!(rows.len(), 1, "no row appended for a touch");
+    assert_eq!(
+        rows[0].modified_at,
+        OffsetDateTime::from(old).replace_nanosecond(0).unwrap(),
+        "stored mtime follows the file"
+    );
+}
+
+#[test]
+fn check_index_once_detects_same_size_edit_within_the_same_second() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    let path = write(&storage, "a.cook", b"v1");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    let recorded = {
+        let conn = &mut get_connection(&pool).unwrap();
+        registry::non_deleted(conn, NS).unwrap()[0].modified_at
+    };
+    fs::write(&path, b"v2").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(recorded.into())
+        .unwrap();
+
+    assert!(
+        check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap(),
+        "size and truncated mtime are equal, only the content tells the edit apart"
+    );
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let live = registry::non_deleted(conn, NS).unwrap();
+    let expected = cooklang_sync_client::chunker::file_checksum(&path).unwrap();
+    assert_eq!(live[0].checksum.as_deref(), Some(expected.as_str()));
+}
+
 #[test]
 fn check_index_once_records_delete_when_file_is_removed() {
     let (pool, _db_dir) = common::fresh_client_pool();
     let storage = storage_dir();
     let path = write(&storage, "gone.cook", b"bye");
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     fs::remove_file(&path).unwrap();
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     let conn = &mut get_connection(&pool).unwrap();
     let live = registry::non_deleted(conn, NS).unwrap();
@@ -147,7 +217,7 @@ fn check_index_once_skips_ineligible_files_and_recurses_into_subdirs() {
     write(&storage, "notes.txt", b"c");
     write(&storage, "script.rs", b"d");
 
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     let conn = &mut get_connection(&pool).unwrap();
     let mut paths: Vec<String> = registry::non_deleted(conn, NS)
@@ -174,7 +244,7 @@ fn check_index_once_indexes_dotfiles_on_the_is_text_allowlist() {
     write(&storage, ".shopping-list", b"milk");
     write(&storage, ".hidden-random", b"not included");
 
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     let conn = &mut get_connection(&pool).unwrap();
     let mut paths: Vec<String> = registry::non_deleted(conn, NS)
@@ -186,6 +256,92 @@ fn check_index_once_indexes_dotfiles_on_the_is_text_allowlist() {
     assert_eq!(paths, vec![".shopping-list".to_string()]);
 }
 
+#[test]
+fn check_index_once_indexes_registered_extensions_and_persists_format() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, "soup.cook", b"title: Soup\n");
+    write(&storage, "notes.txt", b"buy eggs\n");
+
+    let mut formats = FileFormatRegistry::default();
+    formats.add_extension("txt", FileFormat::Text);
+
+    assert!(check_index_once(&pool, storage.path(), NS, &formats).unwrap());
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let mut rows: Vec<(String, Option<FileFormat>)> = registry::non_deleted(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.path, r.format))
+        .collect();
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        rows,
+        vec![
+            ("notes.txt".to_string(), Some(FileFormat::Text)),
+            ("soup.cook".to_string(), Some(FileFormat::Text)),
+        ]
+    );
+}
+
+#[test]
+fn check_index_once_does_not_tombstone_files_outside_current_formats() {
+    // A one-off run with the default formats must not treat files that only
+    // the caller's extra extensions cover as deleted.
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, "notes.txt", b"buy eggs\n");
+
+    let mut formats = FileFormatRegistry::default();
+    formats.add_extension("txt", FileFormat::Text);
+    assert!(check_index_once(&pool, storage.path(), NS, &formats).unwrap());
+
+    assert!(
+        !check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap(),
+        "nothing changed from the point of view of the default formats"
+    );
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let live = registry::non_deleted(conn, NS).unwrap();
+    assert_eq!(live.len(), 1);
+    assert_eq!(live[0].path, "notes.txt");
+}
+
+#[test]
+fn check_index_once_leaves_newly_ignored_files_alone() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, "drafts/soup.cook", b"s");
+    write(&storage, "stew.cook", b"s");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    // Ignoring a synced folder stops syncing it, it doesn't delete it.
+    write(&storage, ".cooksyncignore", b"drafts/\n");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let mut paths: Vec<(String, bool)> = file_records::table
+        .select(FileRecord::as_select())
+        .load(conn)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.path, r.deleted))
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            (".cooksyncignore".to_string(), false),
+            ("drafts/soup.cook".to_string(), false),
+            ("stew.cook".to_string(), false),
+        ]
+    );
+
+    // New files in the ignored folder aren't picked up either.
+    write(&storage, "drafts/salad.cook", b"s");
+    assert!(!check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+}
+
 #[cfg(unix)]
 #[test]
 fn check_index_once_skips_symlinks() {
@@ -196,7 +352,7 @@ fn check_index_once_skips_symlinks() {
     let link = storage.path().join("link.cook");
     symlink(&target, &link).expect("symlink");
 
-    assert!(check_index_once(&pool, storage.path(), NS).unwrap());
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
 
     let conn = &mut get_connection(&pool).unwrap();
     let mut paths: Vec<String> = registry::non_deleted(conn, NS)
@@ -209,6 +365,68 @@ fn check_index_once_skips_symlinks() {
         "symlink entry must be skipped by filter_eligible");
 }
 
+#[test]
+fn check_index_once_skips_interrupted_download_temp_files() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+
+    write(&storage, "soup.cook", b"title: Soup\n");
+    write(&storage, ".soup.cook.cooksync-tmp", b"title: So");
+
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let paths: Vec<String> = registry::non_deleted(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    assert_eq!(paths, vec!["soup.cook".to_string()]);
+}
+
+#[test]
+fn check_index_once_skips_files_being_downloaded() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, "soup.cook", b"title: Soup\n");
+    write(&storage, "stew.cook", b"title: Stew\n");
+
+    let conn = &mut get_connection(&pool).unwrap();
+    registry::begin_download(
+        conn,
+        &[DownloadIntent {
+            namespace_id: NS,
+            jid: 3,
+            path: "soup.cook".to_string(),
+            deleted: false,
+            chunk_ids: String::new(),
+            checksum: None,
+        }],
+    )
+    .unwrap();
+
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+    assert!(!check_index_paths_once(
+        &pool,
+        storage.path(),
+        NS,
+        &FileFormatRegistry::default(),
+        &[PathBuf::from("soup.cook")]
+    )
+    .unwrap());
+
+    let paths: Vec<String> = registry::non_deleted(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    assert_eq!(
+        paths,
+        vec!["stew.cook".to_string()],
+        "the syncer records soup.cook once it's downloaded"
+    );
+}
+
 #[test]
 fn check_index_once_does_not_tombstone_just_downloaded_file() {
     // Regression test for https://github.com/cooklang/cooklang-sync/issues/18.
@@ -245,12 +463,17 @@ fn check_index_once_does_not_tombstone_just_downloaded_file() {
             size: content.len() as i64,
             modified_at,
             namespace_id: NS,
+            format: None,
+            checksum: None,
+            moved_from: None,
+            chunk_ids: None,
         };
         registry::create(conn, &vec![form]).expect("seed registry");
     }
 
     // Run the indexer's filesystem-vs-registry comparison.
-    check_index_once(&pool, storage.path(), NS).expect("check_index_once");
+    check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default())
+        .expect("check_index_once");
 
     // The downloaded file must still be the only active row, and it must
     // not have been soft-deleted by a spurious DeleteForm.
@@ -266,10 +489,148 @@ fn check_index_once_on_empty_dir_is_noop() {
     let (pool, _dir) = common::fresh_client_pool();
     let storage = TempDir::new().expect("tempdir");
 
-    let changed = check_index_once(&pool, storage.path(), 1).expect("check_index_once");
+    let changed = check_index_once(&pool, storage.path(), 1, &FileFormatRegistry::default())
+        .expect("check_index_once");
     assert!(!changed, "empty dir must return Ok(false)");
 
     let conn = &mut get_connection(&pool).expect("checkout");
     let rows = registry::non_deleted(conn, 1).expect("non_deleted");
     assert!(rows.is_empty(), "empty dir must not produce any registry rows");
 }
+
+fn live_paths(pool: &cooklang_sync_client::connection::ConnectionPool) -> Vec<String> {
+    let conn = &mut get_connection(pool).unwrap();
+    let mut paths: Vec<String> = registry::non_deleted(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn check_index_paths_once_only_looks_at_given_paths() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, "a.cook", b"a");
+    write(&storage, "b.cook", b"b");
+    write(&storage, "dinner/soup.cook", b"soup");
+    write(&storage, "dinner/pasta.cook", b"pasta");
+
+    let changed = check_index_paths_once(
+        &pool,
+        storage.path(),
+        NS,
+        &FileFormatRegistry::default(),
+        &[storage.path().join("a.cook"), PathBuf::from("dinner")],
+    )
+    .unwrap();
+
+    assert!(changed);
+    assert_eq!(
+        live_paths(&pool),
+        vec!["a.cook", "dinner/pasta.cook", "dinner/soup.cook"],
+        "b.cook wasn't reported, so it isn't indexed yet"
+    );
+}
+
+#[test]
+fn check_index_paths_once_records_deletes_under_removed_directory_only() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    for p in [
+        "dinner/soup.cook",
+        "dinner/sides/rice.cook",
+        "dinner2/stew.cook",
+        "Dinner.cook",
+        "gone.cook",
+    ] {
+        write(&storage, p, b"x");
+    }
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    fs::remove_dir_all(storage.path().join("dinner")).unwrap();
+    fs::remove_file(storage.path().join("gone.cook")).unwrap();
+    // Not reported, must survive even though it's gone too.
+    fs::remove_file(storage.path().join("Dinner.cook")).unwrap();
+
+    let changed = check_index_paths_once(
+        &pool,
+        storage.path(),
+        NS,
+        &FileFormatRegistry::default(),
+        &[
+            storage.path().join("dinner"),
+            storage.path().join("gone.cook"),
+        ],
+    )
+    .unwrap();
+
+    assert!(changed);
+    assert_eq!(live_paths(&pool), vec!["Dinner.cook", "dinner2/stew.cook"]);
+}
+
+#[test]
+fn check_index_paths_once_skips_ignored_and_dot_dir_paths() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(&storage, ".cooksyncignore", b"drafts/\n");
+    write(&storage, "drafts/a.cook", b"a");
+    write(&storage, ".git/x.cook", b"x");
+
+    let changed = check_index_paths_once(
+        &pool,
+        storage.path(),
+        NS,
+        &FileFormatRegistry::default(),
+        &[
+            storage.path().join("drafts/a.cook"),
+            storage.path().join(".git/x.cook"),
+        ],
+    )
+    .unwrap();
+
+    assert!(!changed);
+    assert!(live_paths(&pool).is_empty());
+}
+
+#[test]
+fn check_index_once_pairs_a_delete_with_a_create_of_the_same_content_as_a_move() {
+    let (pool, _db_dir) = common::fresh_client_pool();
+    let storage = storage_dir();
+    write(
+        &storage,
+        "Breakfast/Pancakes.cook",
+        b"Mix @flour{200%g} and @eggs{2}.\n",
+    );
+    write(&storage, "Soup.cook", b"soup");
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    fs::create_dir(storage.path().join("Brunch")).unwrap();
+    fs::rename(
+        storage.path().join("Breakfast/Pancakes.cook"),
+        storage.path().join("Brunch/Pancakes.cook"),
+    )
+    .unwrap();
+    // Same size, different content, so not a move.
+    fs::remove_file(storage.path().join("Soup.cook")).unwrap();
+    write(&storage, "Stew.cook", b"stew");
+
+    assert!(check_index_once(&pool, storage.path(), NS, &FileFormatRegistry::default()).unwrap());
+
+    let conn = &mut get_connection(&pool).unwrap();
+    let live = registry::non_deleted(conn, NS).unwrap();
+    let moved = live
+        .iter()
+        .find(|r| r.path == "Brunch/Pancakes.cook")
+        .unwrap();
+    let stew = live.iter().find(|r| r.path == "Stew.cook").unwrap();
+    assert_eq!(moved.moved_from.as_deref(), Some("Breakfast/Pancakes.cook"));
+    assert_eq!(stew.moved_from, None);
+    assert_eq!(
+        live_paths(&pool),
+        vec!["Brunch/Pancakes.cook", "Stew.cook"],
+        "old paths are still tombstoned"
+    );
+}
@@ -1,9 +1,13 @@
 //! Integration tests for `cooklang_sync_client::registry`.
 
+#![allow(clippy::useless_vec)]
+
 mod common;
 
 use cooklang_sync_client::connection::get_connection;
-use cooklang_sync_client::models::{CreateForm, DeleteForm, FileRecord};
+use cooklang_sync_client::models::{
+    CreateForm, DeferredDownload, DeleteForm, DownloadIntent, FileRecord,
+};
 use cooklang_sync_client::registry;
 use cooklang_sync_client::schema::file_records;
 use diesel::prelude::*;
@@ -19,6 +23,10 @@ fn sample_create(path: &str, size: i64, ns: i32) -> CreateForm {
         size,
         modified_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
         namespace_id: ns,
+        format: None,
+        checksum: None,
+        moved_from: None,
+        chunk_ids: None,
     }
 }
 
@@ -298,3 +306,226 @@ fn latest_jid_returns_zero_for_explicit_jid_zero() {
     let latest = registry::latest_jid(conn, 1).expect("latest_jid");
     assert_eq!(latest, 0, "Some(0) must unwrap to 0, not NotFound");
 }
+
+fn synced_create(path: &str, jid: i32, ns: i32) -> CreateForm {
+    let mut form = sample_create(path, 10, ns);
+    form.jid = Some(jid);
+    form
+}
+
+fn synced_delete(path: &str, jid: i32, ns: i32) -> DeleteForm {
+    DeleteForm {
+        path: path.to_string(),
+        jid: Some(jid),
+        size: 0,
+        modified_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+        deleted: true,
+        namespace_id: ns,
+    }
+}
+
+fn all_rows(conn: &mut SqliteConnection, ns: i32) -> Vec<FileRecord> {
+    file_records::table
+        .filter(file_records::namespace_id.eq(ns))
+        .select(FileRecord::as_select())
+        .order(file_records::id.asc())
+        .load(conn)
+        .unwrap()
+}
+
+#[test]
+fn compact_keeps_latest_synced_and_newer_unsynced_row_per_path() {
+    let (pool, _dir) = common::fresh_client_pool();
+    let conn = &mut get_connection(&pool).expect("checkout");
+
+    registry::create(conn, &[synced_create("a.cook", 1, 1)]).unwrap();
+    registry::create(conn, &[synced_create("a.cook", 2, 1)]).unwrap();
+    registry::create(conn, &[sample_create("a.cook", 30, 1)]).unwrap();
+    registry::create(conn, &[sample_create("a.cook", 40, 1)]).unwrap();
+    registry::create(conn, &[synced_create("b.cook", 3, 1)]).unwrap();
+
+    let ids = |rows: Vec<FileRecord>| rows.into_iter().map(|r| r.id).collect::<Vec<_>>();
+    let live_before = ids(registry::non_deleted(conn, 1).unwrap());
+    let unsynced_before = ids(registry::updated_locally(conn, 1).unwrap());
+
+    assert_eq!(registry::compact(conn, 1).unwrap(), 2);
+
+    let rows = all_rows(conn, 1);
+    let a: Vec<(Option<i32>, i64)> = rows
+        .iter()
+        .filter(|r| r.path == "a.cook")
+        .map(|r| (r.jid, r.size))
+        .collect();
+    assert_eq!(a, vec![(Some(2), 10), (None, 40)]);
+
+    assert_eq!(ids(registry::non_deleted(conn, 1).unwrap()), live_before);
+    assert_eq!(
+        ids(registry::updated_locally(conn, 1).unwrap()),
+        unsynced_before
+    );
+    assert_eq!(registry::latest_jid(conn, 1).unwrap(), 3);
+}
+
+#[test]
+fn compact_drops_acknowledged_tombstones_but_not_unsynced_ones() {
+    let (pool, _dir) = common::fresh_client_pool();
+    let conn = &mut get_connection(&pool).expect("checkout");
+
+    registry::create(conn, &[synced_create("gone.cook", 1, 1)]).unwrap();
+    registry::delete(conn, &[synced_delete("gone.cook", 2, 1)]).unwrap();
+    registry::create(conn, &[synced_create("pending.cook", 3, 1)]).unwrap();
+    let pending = registry::non_deleted(conn, 1).unwrap();
+    registry::delete(conn, &[sample_delete(&pending[0])]).unwrap();
+    registry::create(conn, &[synced_create("kept.cook", 4, 1)]).unwrap();
+
+    assert_eq!(registry::compact(conn, 1).unwrap(), 2);
+
+    let paths: Vec<(String, bool)> = all_rows(conn, 1)
+        .into_iter()
+        .map(|r| (r.path, r.deleted))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            ("pending.cook".to_string(), false),
+            ("pending.cook".to_string(), true),
+            ("kept.cook".to_string(), false),
+        ]
+    );
+    assert_eq!(registry::updated_locally(conn, 1).unwrap().len(), 1);
+    // Created again, it goes up with parent jid 0.
+    assert!(registry::latest_synced(conn, 1, "gone.cook")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn compact_keeps_row_with_highest_jid_and_other_namespaces() {
+    let (pool, _dir) = common::fresh_client_pool();
+    let conn = &mut get_connection(&pool).expect("checkout");
+
+    registry::create(conn, &[synced_create("a.cook", 1, 1)]).unwrap();
+    registry::delete(conn, &[synced_delete("a.cook", 2, 1)]).unwrap();
+    registry::create(conn, &[synced_create("a.cook", 5, 2)]).unwrap();
+    registry::create(conn, &[synced_create("a.cook", 6, 2)]).unwrap();
+
+    assert_eq!(registry::compact(conn, 1).unwrap(), 1);
+    assert_eq!(registry::latest_jid(conn, 1).unwrap(), 2);
+    assert_eq!(all_rows(conn, 2).len(), 2);
+
+    // Nothing left to do the second time.
+    assert_eq!(registry::compact(conn, 1).unwrap(), 0);
+}
+
+fn sample_intent(path: &str, jid: i32, ns: i32) -> DownloadIntent {
+    DownloadIntent {
+        namespace_id: ns,
+        jid,
+        path: path.to_string(),
+        deleted: false,
+        chunk_ids: "c1".to_string(),
+        checksum: Some("sum".to_string()),
+    }
+}
+
+#[test]
+fn finish_download_writes_rows_and_drops_only_intents_of_the_batch() {
+    let (pool, _dir) = common::fresh_client_pool();
+    let conn = &mut get_connection(&pool).expect("checkout");
+
+    registry::begin_download(
+        conn,
+        &[
+            sample_intent("b.cook", 8, 1),
+            sample_intent("a.cook", 7, 1),
+            sample_intent("a.cook", 7, 2),
+        ],
+    )
+    .unwrap();
+    let pending: Vec<i32> = registry::download_intents(conn, 1)
+        .unwrap()
+        .iter()
+        .map(|i| i.jid)
+        .collect();
+    assert_eq!(
+        pending,
+        vec![7, 8],
+        "in the order they're applied, scoped by namespace"
+    );
+
+    registry::finish_download(conn, 1, &[7], &[synced_create("a.cook", 7, 1)], &[]).unwrap();
+
+    let rows = all_rows(conn, 1);
+    assert_eq!(rows.len(), 1);
+    assert_eq!((rows[0].path.as_str(), rows[0].jid), ("a.cook", Some(7)));
+    let pending: Vec<i32> = registry::download_intents(conn, 1)
+        .unwrap()
+        .iter()
+        .map(|i| i.jid)
+        .collect();
+    assert_eq!(pending, vec![8]);
+    assert_eq!(
+        registry::download_intents(conn, 2).unwrap().len(),
+        1,
+        "other namespaces are left alone"
+    );
+}
+
+fn sample_deferred(path: &str, jid: i32, ns: i32) -> DeferredDownload {
+    DeferredDownload {
+        namespace_id: ns,
+        jid,
+        path: path.to_string(),
+        chunk_ids: "c1".to_string(),
+        moved_from: None,
+    }
+}
+
+#[test]
+fn defer_download_keeps_the_latest_per_path_until_it_is_downloaded() {
+    let (pool, _dir) = common::fresh_client_pool();
+    let conn = &mut get_connection(&pool).expect("checkout");
+
+    registry::defer_download(
+        conn,
+        &[
+            sample_deferred("a.jpg", 7, 1),
+            sample_deferred("b.jpg", 8, 1),
+        ],
+    )
+    .unwrap();
+    registry::defer_download(
+        conn,
+        &[
+            sample_deferred("a.jpg", 9, 1),
+            sample_deferred("a.jpg", 9, 2),
+        ],
+    )
+    .unwrap();
+    let deferred: Vec<(String, i32)> = registry::deferred_downloads(conn, 1)
+        .unwrap()
+        .into_iter()
+        .map(|d| (d.path, d.jid))
+        .collect();
+    assert_eq!(
+        deferred,
+        vec![("b.jpg".to_string(), 8), ("a.jpg".to_string(), 9)],
+        "in commit order, newer replaces older"
+    );
+
+    registry::finish_download(conn, 1, &[9], &[synced_create("a.jpg", 9, 1)], &[]).unwrap();
+    let deferred: Vec<i32> = registry::deferred_downloads(conn, 1)
+        .unwrap()
+        .iter()
+        .map(|d| d.jid)
+        .collect();
+    assert_eq!(deferred, vec![8]);
+
+    registry::drop_deferred(conn, 1, "b.jpg").unwrap();
+    assert!(registry::deferred_downloads(conn, 1).unwrap().is_empty());
+    assert_eq!(
+        registry::deferred_downloads(conn, 2).unwrap().len(),
+        1,
+        "other namespaces are left alone"
+    );
+}
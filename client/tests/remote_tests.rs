@@ -4,12 +4,17 @@
 //! at its URL. Tests assert on URL shape, headers, and body — *not* on the
 //! per-instance `uuid` that `Remote` mints at construction.
 
+use cooklang_sync_client::bandwidth::NetworkPolicy;
 use cooklang_sync_client::errors::SyncError;
-use cooklang_sync_client::remote::{CommitResultStatus, Remote, ResponseFileRecord, REQUEST_TIMEOUT_SECS};
+use cooklang_sync_client::remote::{
+    CommitBatchResultStatus, CommitEntry, CommitResultStatus, Remote, ResponseFileRecord,
+    REQUEST_TIMEOUT_SECS,
+};
 use futures::StreamExt;
 use std::time::Duration;
 use wiremock::matchers::{
-    body_string_contains, header, header_exists, method, path, query_param, query_param_contains,
+    body_string_contains, header, header_exists, header_regex, method, path, query_param,
+    query_param_contains,
 };
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -42,7 +47,7 @@ async fn commit_returns_success_on_2xx_with_success_payload() {
 
     let remote = new_remote(&server);
     let result = remote
-        .commit("recipes/a.cook", false, "abc,def")
+        .commit("recipes/a.cook", false, "abc,def", None)
         .await
         .expect("commit");
     assert!(matches!(result, CommitResultStatus::Success(42)));
@@ -62,13 +67,45 @@ async fn commit_returns_need_chunks_on_2xx_with_need_chunks_payload() {
         .await;
 
     let remote = new_remote(&server);
-    let result = remote.commit("a.cook", false, "abc,def").await.expect("commit");
+    let result = remote
+        .commit("a.cook", false, "abc,def", None)
+        .await
+        .expect("commit");
     match result {
         CommitResultStatus::NeedChunks(s) => assert_eq!(s, "abc,def"),
         other => panic!("expected NeedChunks, got {:?}", other),
     }
 }
 
+#[tokio::test]
+async fn commit_sends_parent_jid_and_parses_conflict() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("parent_jid=7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "Conflict": [9, "abc,ghi", false]
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .commit("a.cook", false, "abc,def", Some(7))
+        .await
+        .expect("commit");
+    match result {
+        CommitResultStatus::Conflict(jid, chunk_ids, deleted) => {
+            assert_eq!(jid, 9);
+            assert_eq!(chunk_ids, "abc,ghi");
+            assert!(!deleted);
+        }
+        other => panic!("expected Conflict, got {:?}", other),
+    }
+}
+
 #[tokio::test]
 async fn commit_maps_401_to_unauthorized() {
     let server = MockServer::start().await;
@@ -79,7 +116,7 @@ async fn commit_maps_401_to_unauthorized() {
         .await;
 
     let remote = new_remote(&server);
-    let err = remote.commit("a.cook", false, "").await.unwrap_err();
+    let err = remote.commit("a.cook", false, "", None).await.unwrap_err();
     assert!(
         matches!(err, SyncError::Unauthorized),
         "expected SyncError::Unauthorized on 401, got {:?}",
@@ -88,7 +125,25 @@ async fn commit_maps_401_to_unauthorized() {
 }
 
 #[tokio::test]
-async fn commit_maps_5xx_to_unknown_with_status_in_message() {
+async fn commit_maps_other_4xx_to_unknown() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let err = remote.commit("a.cook", false, "", None).await.unwrap_err();
+    assert!(
+        matches!(err, SyncError::Unknown(_)),
+        "a bad request isn't worth retrying, got {:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn commit_maps_5xx_to_server_unavailable_with_status_in_message() {
     let server = MockServer::start().await;
     Mock::given(method("POST"))
         .and(path("/metadata/commit"))
@@ -97,20 +152,214 @@ async fn commit_maps_5xx_to_unknown_with_status_in_message() {
         .await;
 
     let remote = new_remote(&server);
-    let err = remote.commit("a.cook", false, "").await.unwrap_err();
+    let err = remote.commit("a.cook", false, "", None).await.unwrap_err();
     match err {
-        SyncError::Unknown(msg) => assert!(msg.contains("503"), "expected status in message, got {msg:?}"),
-        other => panic!("expected SyncError::Unknown on 5xx, got {:?}", other),
+        SyncError::ServerUnavailable(msg) => assert!(
+            msg.contains("503"),
+            "expected status in message, got {msg:?}"
+        ),
+        other => panic!(
+            "expected SyncError::ServerUnavailable on 5xx, got {:?}",
+            other
+        ),
+    }
+}
+
+fn batch_entry(path: &str, chunk_ids: &str, parent_jid: Option<i32>) -> CommitEntry {
+    CommitEntry {
+        path: path.to_string(),
+        deleted: false,
+        chunk_ids: chunk_ids.to_string(),
+        parent_jid,
     }
 }
 
+#[tokio::test]
+async fn commit_batch_sends_indexed_entries_and_parses_success() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_batch"))
+        .and(query_param_contains("uuid", "-"))
+        .and(body_string_contains("entries%5B0%5D.path=a.cook"))
+        .and(body_string_contains("entries%5B0%5D.parent_jid=7"))
+        .and(body_string_contains("entries%5B1%5D.path=photo.jpg"))
+        .and(body_string_contains("entries%5B1%5D.chunk_ids=ghi"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [12, 13] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let entries = [
+        batch_entry("a.cook", "abc,def", Some(7)),
+        batch_entry("photo.jpg", "ghi", None),
+    ];
+    let result = remote.commit_batch(&entries).await.expect("commit_batch");
+    assert!(
+        matches!(result, CommitBatchResultStatus::Success(ref jids) if jids == &[12, 13]),
+        "got {:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn commit_batch_maps_conflicting_paths_to_entries() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_batch"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "Conflict": [["photo.jpg", 9, "xyz", true]] })),
+        )
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let entries = [
+        batch_entry("a.cook", "abc", Some(1)),
+        batch_entry("photo.jpg", "ghi", Some(2)),
+    ];
+    match remote.commit_batch(&entries).await.expect("commit_batch") {
+        CommitBatchResultStatus::Conflict(conflicts) => {
+            assert_eq!(conflicts, vec![(1, 9, "xyz".to_string(), true)])
+        }
+        other => panic!("expected Conflict, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn commit_batch_falls_back_to_single_commits_on_older_servers() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_batch"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("chunk_ids=abc"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 5 })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("chunk_ids=ghi"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "NeedChunks": "ghi" })),
+        )
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .commit_batch(&[batch_entry("a.cook", "abc", None)])
+        .await
+        .expect("commit_batch");
+    assert!(
+        matches!(result, CommitBatchResultStatus::Success(ref jids) if jids == &[5]),
+        "got {:?}",
+        result
+    );
+
+    let entries = [
+        batch_entry("a.cook", "abc", None),
+        batch_entry("photo.jpg", "ghi", None),
+    ];
+    let result = remote.commit_batch(&entries).await.expect("commit_batch");
+    assert!(
+        matches!(result, CommitBatchResultStatus::NeedChunks(ref chunks) if chunks == "ghi"),
+        "got {:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn commit_with_chunks_sends_entries_and_chunks_in_one_multipart_request() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .and(query_param_contains("uuid", "-"))
+        .and(header_regex(
+            "content-type",
+            "^multipart/form-data; boundary=",
+        ))
+        .and(body_string_contains(
+            "name=\"entries[0].path\"\r\n\r\na.cook\r\n",
+        ))
+        .and(body_string_contains(
+            "name=\"entries[0].chunk_ids\"\r\n\r\nabc\r\n",
+        ))
+        .and(body_string_contains("name=\"chunks[0].id\"\r\n\r\nabc\r\n"))
+        .and(body_string_contains("name=\"chunks[0].data\""))
+        .and(body_string_contains("Eggs\n"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [3] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .commit_with_chunks(
+            &[batch_entry("a.cook", "abc", None)],
+            vec![("abc".to_string(), b"Eggs\n".to_vec())],
+        )
+        .await
+        .expect("commit_with_chunks");
+    assert!(
+        matches!(result, CommitBatchResultStatus::Success(ref jids) if jids == &[3]),
+        "got {:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn commit_with_chunks_falls_back_to_commit_batch_on_older_servers() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_batch"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "NeedChunks": "abc" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .commit_with_chunks(
+            &[batch_entry("a.cook", "abc", None)],
+            vec![("abc".to_string(), b"Eggs\n".to_vec())],
+        )
+        .await
+        .expect("commit_with_chunks");
+    assert!(
+        matches!(result, CommitBatchResultStatus::NeedChunks(ref chunks) if chunks == "abc"),
+        "got {:?}",
+        result
+    );
+}
+
 #[tokio::test]
 async fn list_parses_response_records_and_preserves_order() {
     let server = MockServer::start().await;
     Mock::given(method("GET"))
         .and(path("/metadata/list"))
         .and(query_param("jid", "7"))
-        .and(header("authorization", format!("Bearer {}", TOKEN).as_str()))
+        .and(header(
+            "authorization",
+            format!("Bearer {}", TOKEN).as_str(),
+        ))
         .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
             { "id": 8, "path": "a.cook", "deleted": false, "chunk_ids": "abc" },
             { "id": 9, "path": "b.cook", "deleted": true,  "chunk_ids": "" }
@@ -237,7 +486,7 @@ async fn upload_maps_401_to_unauthorized() {
 }
 
 #[tokio::test]
-async fn upload_maps_5xx_to_unknown_with_status_in_message() {
+async fn upload_maps_5xx_to_server_unavailable_with_status_in_message() {
     let server = MockServer::start().await;
     Mock::given(method("POST"))
         .and(path("/chunks/abc123"))
@@ -248,8 +497,14 @@ async fn upload_maps_5xx_to_unknown_with_status_in_message() {
     let remote = new_remote(&server);
     let err = remote.upload("abc123", b"hello".to_vec()).await.unwrap_err();
     match err {
-        SyncError::Unknown(msg) => assert!(msg.contains("503"), "expected status in message, got {msg:?}"),
-        other => panic!("expected SyncError::Unknown on 5xx, got {:?}", other),
+        SyncError::ServerUnavailable(msg) => assert!(
+            msg.contains("503"),
+            "expected status in message, got {msg:?}"
+        ),
+        other => panic!(
+            "expected SyncError::ServerUnavailable on 5xx, got {:?}",
+            other
+        ),
     }
 }
 
@@ -274,7 +529,65 @@ async fn upload_batch_posts_multipart_with_each_chunk_as_named_part() {
         ("c1".to_string(), b"hello".to_vec()),
         ("c2".to_string(), b"world".to_vec()),
     ];
-    remote.upload_batch(chunks).await.expect("upload_batch");
+    remote
+        .upload_batch(chunk_stream(chunks))
+        .await
+        .expect("upload_batch");
+}
+
+fn chunk_stream(
+    chunks: Vec<(String, Vec<u8>)>,
+) -> impl futures::Stream<Item = Result<(String, Vec<u8>), SyncError>> {
+    futures::stream::iter(chunks.into_iter().map(Ok))
+}
+
+#[tokio::test]
+async fn upload_batch_fails_when_a_chunk_cant_be_read() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/upload"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let chunks = futures::stream::iter(vec![
+        Ok(("c1".to_string(), b"hello".to_vec())),
+        Err(SyncError::GetFromCacheError),
+    ]);
+    remote
+        .upload_batch(chunks)
+        .await
+        .expect_err("a partial body must not count as uploaded");
+}
+
+#[tokio::test]
+async fn upload_batch_follows_the_upload_limit() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/upload"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let mut remote = new_remote(&server);
+    let network = std::sync::Arc::new(NetworkPolicy::default());
+    network.upload().set_limit(10_000);
+    remote.set_network(network);
+
+    let started = std::time::Instant::now();
+    let chunks = (0..3).map(|i| (format!("c{}", i), vec![0u8; 1_000]));
+    remote
+        .upload_batch(chunk_stream(chunks.collect()))
+        .await
+        .expect("upload_batch");
+
+    // 3000 bytes at 10kB/s, the first 1000 go right away.
+    assert!(
+        started.elapsed() >= Duration::from_millis(200),
+        "took {:?}",
+        started.elapsed()
+    );
 }
 
 #[tokio::test]
@@ -289,12 +602,15 @@ async fn upload_batch_maps_401_to_unauthorized() {
         .await;
 
     let remote = new_remote(&server);
-    let err = remote.upload_batch(vec![("c1".into(), b"x".to_vec())]).await.unwrap_err();
+    let err = remote
+        .upload_batch(chunk_stream(vec![("c1".into(), b"x".to_vec())]))
+        .await
+        .unwrap_err();
     assert!(matches!(err, SyncError::Unauthorized));
 }
 
 #[tokio::test]
-async fn upload_batch_maps_5xx_to_unknown_with_status_in_message() {
+async fn upload_batch_maps_5xx_to_server_unavailable_with_status_in_message() {
     let server = MockServer::start().await;
     Mock::given(method("POST"))
         .and(path("/chunks/upload"))
@@ -303,10 +619,19 @@ async fn upload_batch_maps_5xx_to_unknown_with_status_in_message() {
         .await;
 
     let remote = new_remote(&server);
-    let err = remote.upload_batch(vec![("c1".into(), b"x".to_vec())]).await.unwrap_err();
+    let err = remote
+        .upload_batch(chunk_stream(vec![("c1".into(), b"x".to_vec())]))
+        .await
+        .unwrap_err();
     match err {
-        SyncError::Unknown(msg) => assert!(msg.contains("500"), "expected status in message, got {msg:?}"),
-        other => panic!("expected SyncError::Unknown on 5xx, got {:?}", other),
+        SyncError::ServerUnavailable(msg) => assert!(
+            msg.contains("500"),
+            "expected status in message, got {msg:?}"
+        ),
+        other => panic!(
+            "expected SyncError::ServerUnavailable on 5xx, got {:?}",
+            other
+        ),
     }
 }
 
@@ -410,3 +735,59 @@ async fn download_batch_errors_when_content_type_is_missing() {
         first
     );
 }
+
+#[tokio::test]
+async fn move_file_returns_new_jid_on_success() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/metadata/move"))
+        .and(header(
+            "authorization",
+            format!("Bearer {}", TOKEN).as_str(),
+        ))
+        .and(query_param_contains("uuid", "-"))
+        .and(body_string_contains("from=Breakfast%2FPancakes.cook"))
+        .and(body_string_contains("path=Brunch%2FPancakes.cook"))
+        .and(body_string_contains("chunk_ids=abc%2Cdef"))
+        .and(body_string_contains("parent_jid=7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "Success": 43
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .move_file(
+            "Breakfast/Pancakes.cook",
+            "Brunch/Pancakes.cook",
+            "abc,def",
+            7,
+        )
+        .await
+        .expect("move");
+
+    assert!(matches!(result, Some(CommitResultStatus::Success(43))));
+}
+
+#[tokio::test]
+async fn move_file_returns_none_when_server_refuses() {
+    let server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(path("/metadata/move"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let remote = new_remote(&server);
+    let result = remote
+        .move_file("a.cook", "b.cook", "abc", 0)
+        .await
+        .expect("move");
+
+    assert!(result.is_none(), "caller falls back to plain commits");
+}
@@ -10,17 +10,28 @@
 
 mod common;
 
-use cooklang_sync_client::chunker::{Chunker, InMemoryCache};
+use cooklang_sync_client::bandwidth::NetworkPolicy;
+use cooklang_sync_client::chunk_store::DiskChunkStore;
+use cooklang_sync_client::chunker::{Chunker, Chunking, InMemoryCache};
 use cooklang_sync_client::connection::get_connection;
+use cooklang_sync_client::control::SyncControl;
+use cooklang_sync_client::crypto::Cipher;
 use cooklang_sync_client::errors::SyncError;
-use cooklang_sync_client::models::{CreateForm, DeleteForm, FileRecord};
+use cooklang_sync_client::file_format::FileFormatRegistry;
+use cooklang_sync_client::indexer::check_index_once;
+use cooklang_sync_client::models::{
+    CreateForm, DeleteForm, DownloadIntent, FileFormat, FileRecord,
+};
 use cooklang_sync_client::registry;
 use cooklang_sync_client::remote::Remote;
-use cooklang_sync_client::syncer::{check_download_once, check_upload_once};
+use cooklang_sync_client::syncer::{
+    check_download_once, check_encryption_allowed, check_upload_once, recover_interrupted_download,
+};
+use cooklang_sync_client::{SyncStatus, SyncStatusListener};
 use std::sync::Arc;
 use time::OffsetDateTime;
 use tokio::sync::Mutex;
-use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::matchers::{body_string_contains, method, path, query_param};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 const NS: i32 = 1;
@@ -34,6 +45,10 @@ fn sample_create(path: &str, size: i64) -> CreateForm {
         size,
         modified_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
         namespace_id: NS,
+        format: None,
+        checksum: None,
+        moved_from: None,
+        chunk_ids: None,
     }
 }
 
@@ -59,15 +74,243 @@ async fn check_upload_once_commits_success_and_marks_jid() {
 
     let remote = Remote::new(&server.uri(), TOKEN);
     let chunker_arc = Arc::new(Mutex::new(base.chunker));
-    let all_committed = check_upload_once(&base.pool, Arc::clone(&chunker_arc), &remote, NS)
-        .await
-        .expect("check_upload_once");
+    let all_committed = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
     assert!(all_committed, "all rows should commit in one pass");
 
     // Row now has the returned jid.
     let conn = &mut get_connection(&base.pool).expect("checkout");
     let after: Vec<FileRecord> = registry::updated_locally(conn, NS).expect("updated_locally");
     assert!(after.is_empty(), "no rows should remain unsynced after Success");
+
+    // And remembers what content was committed.
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    let expected =
+        cooklang_sync_client::chunker::file_checksum(&base.dir.path().join("a.cook")).unwrap();
+    assert_eq!(rows[0].checksum.as_deref(), Some(expected.as_str()));
+    assert_eq!(
+        rows[0].chunk_ids.as_deref(),
+        Some(cooklang_sync_client::chunk_id::blake3(b"Eggs\n").as_str()),
+        "base for later merges"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_reuses_chunk_ids_of_unchanged_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 100 })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    // Chunks that can't come from hashing the file, to tell them apart.
+    let stored = cooklang_sync_client::chunk_id::blake3(b"Bacon\n");
+    base.chunker
+        .save_chunk(&stored, b"Bacon\n".to_vec())
+        .unwrap();
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let mut pending = sample_create("a.cook", 5);
+        pending.checksum = Some(
+            cooklang_sync_client::chunker::file_checksum(&base.dir.path().join("a.cook")).unwrap(),
+        );
+        pending.chunk_ids = Some(stored.clone());
+        registry::create(conn, &[pending]).expect("create");
+    }
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(
+        rows[0].chunk_ids.as_deref(),
+        Some(stored.as_str()),
+        "not hashed again"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_remembers_chunk_ids_for_the_next_pass() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let mut pending = sample_create("a.cook", 5);
+        pending.checksum = Some(
+            cooklang_sync_client::chunker::file_checksum(&base.dir.path().join("a.cook")).unwrap(),
+        );
+        registry::create(conn, &[pending]).expect("create");
+    }
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let _ = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await;
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::updated_locally(conn, NS).expect("updated_locally");
+    assert_eq!(rows[0].jid, None);
+    assert_eq!(
+        rows[0].chunk_ids.as_deref(),
+        Some(cooklang_sync_client::chunk_id::blake3(b"Eggs\n").as_str())
+    );
+}
+
+#[tokio::test]
+async fn check_upload_once_commits_pending_files_in_one_batch() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_batch"))
+        .and(body_string_contains("entries%5B0%5D.path=a.cook"))
+        .and(body_string_contains("entries%5B1%5D.path=b.cook"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [20, 21] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    tokio::fs::write(base.dir.path().join("b.cook"), b"Milk\n")
+        .await
+        .expect("write file");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        registry::create(
+            conn,
+            &[sample_create("a.cook", 5), sample_create("b.cook", 5)],
+        )
+        .expect("create");
+    }
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    let all_committed = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    assert!(all_committed);
+
+    // Each file got the jid at its position in the batch.
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let mut rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    rows.sort_by(|a, b| a.path.cmp(&b.path));
+    let jids: Vec<_> = rows.iter().map(|r| (r.path.as_str(), r.jid)).collect();
+    assert_eq!(jids, vec![("a.cook", Some(20)), ("b.cook", Some(21))]);
+}
+
+#[tokio::test]
+async fn check_upload_once_sends_small_files_with_their_chunks() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .and(body_string_contains(format!(
+            "name=\"chunks[0].id\"\r\n\r\n{}\r\n",
+            chunk_id
+        )))
+        .and(body_string_contains("Eggs\n"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [30] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    for p in [
+        "/metadata/commit",
+        "/metadata/commit_batch",
+        "/chunks/upload",
+    ] {
+        Mock::given(method("POST"))
+            .and(path(p))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+    }
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        registry::create(conn, &[sample_create("a.cook", 5)]).expect("create");
+    }
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    let all_committed = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    assert!(all_committed, "one request should be enough");
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(rows[0].jid, Some(30));
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -115,15 +358,87 @@ async fn check_upload_once_triggers_upload_batch_when_server_asks_for_chunks() {
 
     let remote = Remote::new(&server.uri(), TOKEN);
     let chunker_arc = Arc::new(Mutex::new(base.chunker));
-    let all_committed = check_upload_once(&base.pool, Arc::clone(&chunker_arc), &remote, NS)
-        .await
-        .expect("check_upload_once");
+    let all_committed = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
     // NeedChunks path means we did *not* fully commit this pass — caller will
     // retry on the next loop iteration (that's why `lib::run_upload_once`
     // calls this function twice).
     assert!(!all_committed, "NeedChunks path should return false");
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_splits_large_uploads_into_concurrent_requests() {
+    let server = MockServer::start().await;
+
+    // Seven 1 MB blocks go up in three requests of about 3 MB.
+    // The in-memory cache can't be trusted with 1 MB chunks, it's sharded.
+    let mut base = common::client_base();
+    let store_dir = tempfile::TempDir::new().unwrap();
+    let store = DiskChunkStore::open(store_dir.path(), 100_000_000).unwrap();
+    base.chunker = Chunker::new(store, base.dir.path().to_path_buf());
+    let photo: Vec<u8> = (0..7 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    tokio::fs::write(base.dir.path().join("photo.jpg"), &photo)
+        .await
+        .expect("write file");
+    let chunk_ids = base.chunker.hashify("photo.jpg").await.expect("hashify");
+    assert_eq!(chunk_ids.len(), 7);
+
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "NeedChunks": chunk_ids.join(",")
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/upload"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        registry::create(conn, &[sample_create("photo.jpg", photo.len() as i64)]).expect("create");
+    }
+
+    let mut remote = Remote::new(&server.uri(), TOKEN);
+    remote.set_upload_concurrency(2);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    let uploads = server.received_requests().await.unwrap();
+    let uploaded: usize = uploads
+        .iter()
+        .filter(|r| r.url.path() == "/chunks/upload")
+        .map(|r| {
+            chunk_ids
+                .iter()
+                .filter(|id| String::from_utf8_lossy(&r.body).contains(id.as_str()))
+                .count()
+        })
+        .sum();
+    assert_eq!(uploaded, 7, "every chunk goes up exactly once");
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn check_upload_once_commits_tombstone_without_hashifying() {
     let server = MockServer::start().await;
@@ -158,10 +473,20 @@ async fn check_upload_once_commits_tombstone_without_hashifying() {
 
     let remote = Remote::new(&server.uri(), TOKEN);
     let chunker_arc = Arc::new(Mutex::new(base.chunker));
-    let ok = check_upload_once(&base.pool, Arc::clone(&chunker_arc), &remote, NS)
-        .await
-        .expect("check_upload_once");
-    assert!(ok, "tombstone commit is a Success => all_commited stays true");
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    assert!(
+        ok,
+        "tombstone commit is a Success => all_commited stays true"
+    );
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -218,6 +543,7 @@ async fn check_download_once_writes_file_and_inserts_registry_row() {
         &remote,
         base.dir.path(),
         NS,
+        None,
     )
     .await
     .expect("check_download_once");
@@ -233,6 +559,18 @@ async fn check_download_once_writes_file_and_inserts_registry_row() {
     assert_eq!(rows.len(), 1);
     assert_eq!(rows[0].path, "a.cook");
     assert_eq!(rows[0].jid, Some(11));
+    let expected =
+        cooklang_sync_client::chunker::file_checksum(&base.dir.path().join("a.cook")).unwrap();
+    assert_eq!(
+        rows[0].checksum.as_deref(),
+        Some(expected.as_str()),
+        "checksum comes from the downloaded chunks"
+    );
+    assert_eq!(rows[0].chunk_ids.as_deref(), Some(chunk_id.as_str()));
+    assert!(
+        registry::download_intents(conn, NS).unwrap().is_empty(),
+        "intents go with the registry update"
+    );
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -253,7 +591,14 @@ async fn check_download_once_removes_local_file_and_appends_tombstone() {
     tokio::fs::write(base.dir.path().join("gone.cook"), b"bye\n").await.unwrap();
     {
         let conn = &mut get_connection(&base.pool).expect("checkout");
-        registry::create(conn, &[sample_create("gone.cook", 4)]).expect("create");
+        // Synced as it is on disk, a local edit would be kept.
+        let mut synced = sample_create("gone.cook", 4);
+        synced.jid = Some(5);
+        synced.checksum = Some(
+            cooklang_sync_client::chunker::file_checksum(&base.dir.path().join("gone.cook"))
+                .unwrap(),
+        );
+        registry::create(conn, &[synced]).expect("create");
     }
 
     let remote = Remote::new(&server.uri(), TOKEN);
@@ -264,6 +609,7 @@ async fn check_download_once_removes_local_file_and_appends_tombstone() {
         &remote,
         base.dir.path(),
         NS,
+        None,
     )
     .await
     .expect("check_download_once");
@@ -303,6 +649,7 @@ async fn check_download_once_empty_remote_list_is_noop() {
         &remote,
         base.dir.path(),
         NS,
+        None,
     )
     .await
     .expect("check_download_once");
@@ -332,6 +679,7 @@ async fn check_download_once_propagates_unauthorized_from_list() {
         &remote,
         base.dir.path(),
         NS,
+        None,
     )
     .await
     .unwrap_err();
@@ -360,12 +708,2259 @@ async fn check_upload_once_propagates_unauthorized_from_commit() {
 
     let remote = Remote::new(&server.uri(), TOKEN);
     let chunker_arc = Arc::new(Mutex::new(base.chunker));
-    let err = check_upload_once(&base.pool, Arc::clone(&chunker_arc), &remote, NS)
-        .await
-        .unwrap_err();
+    let err = check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .unwrap_err();
     assert!(
         matches!(err, SyncError::Unauthorized),
         "expected SyncError::Unauthorized, got {:?}",
         err
     );
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_encrypts_paths_and_chunks_with_cipher() {
+    let server = MockServer::start().await;
+    let cipher = Cipher::new([7; 32]);
+    let chunk_id = cipher.chunk_id(b"Eggs\n");
+
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "NeedChunks": chunk_id.clone()
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/upload"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    base.chunker.set_cipher(Some(cipher.clone()));
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        registry::create(conn, &[sample_create("a.cook", 5)]).expect("create");
+    }
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    let requests = server.received_requests().await.expect("recorded requests");
+    let sent = |p: &str| {
+        requests
+            .iter()
+            .find(|r| r.url.path() == p)
+            .expect(p)
+            .body
+            .clone()
+    };
+
+    let commit = String::from_utf8_lossy(&sent("/metadata/commit")).to_string();
+    assert!(
+        !commit.contains("a.cook"),
+        "path leaked in commit: {commit}"
+    );
+    assert!(
+        commit.contains("e2e%3A"),
+        "commit should carry an encrypted path: {commit}"
+    );
+    assert!(
+        commit.contains("k3%3A"),
+        "commit should carry keyed chunk ids: {commit}"
+    );
+
+    // Same for the commit with inline chunks the server didn't know about.
+    let inline = sent("/metadata/commit_with_chunks");
+    assert!(
+        !String::from_utf8_lossy(&inline).contains("a.cook"),
+        "path leaked in inline commit"
+    );
+    assert!(
+        !inline.windows(5).any(|w| w == b"Eggs\n"),
+        "chunk content leaked in inline commit"
+    );
+
+    let upload = sent("/chunks/upload");
+    assert!(
+        !upload.windows(5).any(|w| w == b"Eggs\n"),
+        "chunk content leaked in upload"
+    );
+}
+
+#[tokio::test]
+async fn check_encryption_allowed_refuses_namespace_with_unencrypted_files() {
+    let server = MockServer::start().await;
+    let cipher = Cipher::new([7; 32]);
+    let encrypted_path = cipher.encrypt_path("b.cook").unwrap();
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 1, "path": "gone.cook", "deleted": true, "chunk_ids": "" },
+            { "id": 2, "path": encrypted_path, "deleted": false, "chunk_ids": "" },
+            { "id": 3, "path": "a.cook", "deleted": false, "chunk_ids": "" }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let plain = Mutex::new(Chunker::new(
+        InMemoryCache::new(100, 10_000_000),
+        base.dir.path().to_path_buf(),
+    ));
+    check_encryption_allowed(&plain, &remote)
+        .await
+        .expect("nothing to check without a passphrase");
+
+    base.chunker.set_cipher(Some(cipher));
+    let err = check_encryption_allowed(&Mutex::new(base.chunker), &remote)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, SyncError::EncryptionError(_)),
+        "got {:?}",
+        err
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_decrypts_paths_and_chunks_with_cipher() {
+    let server = MockServer::start().await;
+    let cipher = Cipher::new([7; 32]);
+    let chunk_id = cipher.chunk_id(b"Eggs\n");
+    let encrypted_path = cipher.encrypt_path("a.cook").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": encrypted_path, "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let boundary = "downloadbound";
+    let mut body = format!(
+        "--{b}\r\nX-Chunk-ID: {id}\r\nContent-Type: application/octet-stream\r\n\r\n",
+        b = boundary,
+        id = chunk_id
+    )
+    .into_bytes();
+    body.extend(cipher.encrypt_chunk(b"Eggs\n").unwrap());
+    body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", boundary).as_str(),
+                )
+                .set_body_bytes(body),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    base.chunker.set_cipher(Some(cipher));
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    let bytes = tokio::fs::read(base.dir.path().join("a.cook"))
+        .await
+        .expect("read");
+    assert_eq!(bytes, b"Eggs\n");
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(rows[0].path, "a.cook");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_errors_on_encrypted_data_without_cipher() {
+    let server = MockServer::start().await;
+    let encrypted_path = Cipher::new([7; 32]).encrypt_path("a.cook").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": encrypted_path, "deleted": true, "chunk_ids": "" }
+        ])))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    let err = check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        matches!(err, SyncError::EncryptionError(_)),
+        "expected SyncError::EncryptionError, got {:?}",
+        err
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_stops_on_chunks_sealed_with_another_passphrase() {
+    let server = MockServer::start().await;
+    let cipher = Cipher::new([7; 32]);
+    let chunk_id = cipher.chunk_id(b"Eggs\n");
+    let encrypted_path = cipher.encrypt_path("a.cook").unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": encrypted_path, "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(&server)
+        .await;
+
+    let boundary = "downloadbound";
+    let mut body = format!(
+        "--{b}\r\nX-Chunk-ID: {id}\r\nContent-Type: application/octet-stream\r\n\r\n",
+        b = boundary,
+        id = chunk_id
+    )
+    .into_bytes();
+    body.extend(Cipher::new([8; 32]).encrypt_chunk(b"Eggs\n").unwrap());
+    body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", boundary).as_str(),
+                )
+                .set_body_bytes(body),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    base.chunker.set_cipher(Some(cipher));
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    let err = check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    // Not an integrity error, those are retried.
+    assert!(
+        matches!(err, SyncError::EncryptionError(_)),
+        "expected SyncError::EncryptionError, got {:?}",
+        err
+    );
+    assert!(!base.dir.path().join("a.cook").exists());
+}
+
+fn chunk_download_response(chunk_id: &str, content: &str) -> ResponseTemplate {
+    let boundary = "downloadbound";
+    let body = format!(
+        "--{b}\r\nX-Chunk-ID: {id}\r\nContent-Type: application/octet-stream\r\n\r\n{content}\r\n--{b}--\r\n",
+        b = boundary,
+        id = chunk_id
+    );
+    ResponseTemplate::new(200)
+        .insert_header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", boundary).as_str(),
+        )
+        .set_body_bytes(body.into_bytes())
+}
+
+async fn mount_list_of_one(server: &MockServer, chunk_id: &str) {
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "a.cook", "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_retries_chunk_that_fails_verification() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    mount_list_of_one(&server, &chunk_id).await;
+
+    // First response is corrupted on the way, the retry gets the real thing.
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggz\n"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    let bytes = tokio::fs::read(base.dir.path().join("a.cook"))
+        .await
+        .expect("read");
+    assert_eq!(bytes, b"Eggs\n");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_skips_files_whose_chunks_keep_failing_verification() {
+    let server = MockServer::start().await;
+    let eggs = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    let milk = cooklang_sync_client::chunk_id::blake3(b"Milk\n");
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "a.cook", "deleted": false, "chunk_ids": eggs },
+            { "id": 12, "path": "b.cook", "deleted": false, "chunk_ids": milk }
+        ])))
+        .mount(&server)
+        .await;
+
+    let boundary = "downloadbound";
+    let body = format!(
+        "--{b}\r\nX-Chunk-ID: {eggs}\r\nContent-Type: application/octet-stream\r\n\r\nEggz\n\r\n\
+         --{b}\r\nX-Chunk-ID: {milk}\r\nContent-Type: application/octet-stream\r\n\r\nMilk\n\r\n--{b}--\r\n",
+        b = boundary
+    );
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", boundary).as_str(),
+                )
+                .set_body_bytes(body.into_bytes()),
+        )
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let listener = RecordingListener::default();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    // The other file is synced, the corrupted one is reported and tried again later.
+    assert!(!base.dir.path().join("a.cook").exists());
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("b.cook"))
+            .await
+            .unwrap(),
+        b"Milk\n"
+    );
+    match listener.statuses.lock().unwrap().as_slice() {
+        [SyncStatus::Error { message }] => {
+            assert!(message.contains("a.cook"), "{message}");
+            assert!(message.contains(&eggs), "{message}");
+        }
+        other => panic!("expected one SyncStatus::Error, got {:?}", other),
+    }
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let synced: Vec<String> = registry::non_deleted(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.path)
+        .collect();
+    assert_eq!(synced, vec!["b.cook"]);
+    let deferred = registry::deferred_downloads(conn, NS).unwrap();
+    assert_eq!(
+        deferred
+            .iter()
+            .map(|d| (d.jid, d.path.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(11, "a.cook")]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_skips_paths_outside_storage_dir_and_normalizes_the_rest() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "../escape.cook", "deleted": false, "chunk_ids": chunk_id },
+            { "id": 12, "path": "Cre\u{301}pes.cook", "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(!base
+        .dir
+        .path()
+        .parent()
+        .unwrap()
+        .join("escape.cook")
+        .exists());
+    assert!(base.dir.path().join("Cr\u{e9}pes.cook").exists());
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].path, "Cr\u{e9}pes.cook");
+    assert_eq!(rows[0].jid, Some(12));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_moves_past_skipped_paths() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 13, "path": "../escape.cook", "deleted": false, "chunk_ids": "" }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert_eq!(
+        registry::latest_jid(conn, NS).unwrap(),
+        13,
+        "not listed again"
+    );
+    assert!(registry::non_deleted(conn, NS).unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_leaves_ignored_paths_alone() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "a.cook", "deleted": false, "chunk_ids": chunk_id },
+            { "id": 12, "path": "drafts/b.cook", "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join(".cooksyncignore"), b"drafts/\n")
+        .await
+        .unwrap();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(base.dir.path().join("a.cook").exists());
+    assert!(!base.dir.path().join("drafts").exists());
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].path, "a.cook");
+    assert_eq!(
+        registry::latest_jid(conn, NS).unwrap(),
+        12,
+        "not listed again"
+    );
+}
+
+#[derive(Default)]
+struct RecordingListener {
+    statuses: std::sync::Mutex<Vec<SyncStatus>>,
+}
+
+impl SyncStatusListener for RecordingListener {
+    fn on_status_changed(&self, status: SyncStatus) {
+        self.statuses.lock().unwrap().push(status);
+    }
+
+    fn on_complete(&self, _success: bool, _message: Option<String>) {}
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_renames_case_conflicts_on_case_insensitive_fs() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    let conflict_path = cooklang_sync_client::sync_path::case_conflict_path("pasta.cook");
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "Pasta.cook", "deleted": false, "chunk_ids": chunk_id },
+            { "id": 12, "path": "pasta.cook", "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "12"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 13, "path": "pasta.cook", "deleted": true, "chunk_ids": "" }
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let mut chunker = base.chunker;
+    chunker.set_case_insensitive(true);
+    let chunker_arc = Arc::new(Mutex::new(chunker));
+    let listener = RecordingListener::default();
+
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(base.dir.path().join("Pasta.cook").exists());
+    assert!(base.dir.path().join(&conflict_path).exists());
+    let statuses = listener.statuses.lock().unwrap().clone();
+    assert_eq!(statuses.len(), 1);
+    match &statuses[0] {
+        SyncStatus::CaseConflict { path, local_path } => {
+            assert_eq!(path, "pasta.cook");
+            assert_eq!(local_path, &conflict_path);
+        }
+        other => panic!("expected SyncStatus::CaseConflict, got {:?}", other),
+    }
+
+    // Deleting the renamed file on the server removes the copy only.
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(base.dir.path().join("Pasta.cook").exists());
+    assert!(!base.dir.path().join(&conflict_path).exists());
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(
+        rows.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(),
+        vec!["Pasta.cook"]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_sends_an_edited_case_conflict_copy_up_as_a_new_file() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    let conflict_path = cooklang_sync_client::sync_path::case_conflict_path("pasta.cook");
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "Pasta.cook", "deleted": false, "chunk_ids": chunk_id },
+            { "id": 12, "path": "pasta.cook", "deleted": false, "chunk_ids": chunk_id }
+        ])))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [13] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let mut chunker = base.chunker;
+    chunker.set_case_insensitive(true);
+    let chunker_arc = Arc::new(Mutex::new(chunker));
+    let formats = FileFormatRegistry::default();
+
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    // As downloaded, the copy stands for the server's file and isn't sent back.
+    check_index_once(&base.pool, base.dir.path(), NS, &formats).expect("index");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        assert!(registry::updated_locally(conn, NS).unwrap().is_empty());
+    }
+
+    // Edited, it's a file of its own under the name it has here, so the
+    // edit reaches the other devices without touching either original.
+    std::fs::write(base.dir.path().join(&conflict_path), "Eggs\nSalt\n").unwrap();
+    check_index_once(&base.pool, base.dir.path(), NS, &formats).expect("index");
+    check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    let requests = server.received_requests().await.expect("recorded requests");
+    let commit = requests
+        .iter()
+        .find(|r| r.url.path() == "/metadata/commit_with_chunks")
+        .unwrap();
+    let commit = String::from_utf8_lossy(&commit.body);
+    assert!(
+        commit.contains(&format!("\r\n\r\n{}\r\n", conflict_path)),
+        "{commit}"
+    );
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(registry::updated_locally(conn, NS).unwrap().is_empty());
+    let pasta = registry::latest_synced(conn, NS, "Pasta.cook")
+        .unwrap()
+        .unwrap();
+    assert_eq!(pasta.jid, Some(11), "the original stays as it was");
+}
+
+/// Registry state after the indexer saw `from` moved to `to`: the synced
+/// old row, its tombstone and the new row pointing back at it.
+fn seed_local_move(
+    pool: &cooklang_sync_client::connection::ConnectionPool,
+    from: &str,
+    to: &str,
+    size: i64,
+) {
+    let conn = &mut get_connection(pool).expect("checkout");
+    let mut old = sample_create(from, size);
+    old.jid = Some(5);
+    registry::create(conn, &[old]).expect("create");
+    let tombstone = DeleteForm {
+        path: from.to_string(),
+        jid: None,
+        size: 0,
+        modified_at: OffsetDateTime::now_utc(),
+        deleted: true,
+        namespace_id: NS,
+    };
+    registry::delete(conn, &[tombstone]).expect("delete");
+    let mut moved = sample_create(to, size);
+    moved.moved_from = Some(from.to_string());
+    registry::create(conn, &[moved]).expect("create");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_commits_a_move_through_the_move_endpoint() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/move"))
+        .and(body_string_contains("from=Breakfast%2FPancakes.cook"))
+        .and(body_string_contains("path=Brunch%2FPancakes.cook"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 9 })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 100 })),
+        )
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::create_dir(base.dir.path().join("Brunch"))
+        .await
+        .unwrap();
+    tokio::fs::write(base.dir.path().join("Brunch/Pancakes.cook"), b"Eggs\n")
+        .await
+        .unwrap();
+    seed_local_move(
+        &base.pool,
+        "Breakfast/Pancakes.cook",
+        "Brunch/Pancakes.cook",
+        5,
+    );
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    assert!(ok);
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(
+        registry::updated_locally(conn, NS).unwrap().is_empty(),
+        "both halves of the move are synced"
+    );
+    assert_eq!(registry::latest_jid(conn, NS).unwrap(), 9);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_commits_both_paths_when_the_server_refuses_a_move() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/move"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("deleted=true"))
+        .and(body_string_contains("path=Breakfast%2FPancakes.cook"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 10 })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("deleted=false"))
+        .and(body_string_contains("path=Brunch%2FPancakes.cook"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 11 })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::create_dir(base.dir.path().join("Brunch"))
+        .await
+        .unwrap();
+    tokio::fs::write(base.dir.path().join("Brunch/Pancakes.cook"), b"Eggs\n")
+        .await
+        .unwrap();
+    seed_local_move(
+        &base.pool,
+        "Breakfast/Pancakes.cook",
+        "Brunch/Pancakes.cook",
+        5,
+    );
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    assert!(ok);
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(registry::updated_locally(conn, NS).unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_keeps_a_moved_file_aside_when_its_new_path_was_taken() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Flour\n");
+    Mock::given(method("POST"))
+        .and(path("/metadata/move"))
+        .and(body_string_contains("parent_jid=0"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "Conflict": [8, chunk_id, false] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 100 })),
+        )
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Flour\n"))
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    tokio::fs::create_dir(base.dir.path().join("Brunch"))
+        .await
+        .unwrap();
+    tokio::fs::write(base.dir.path().join("Brunch/Pancakes.cook"), b"Eggs\n")
+        .await
+        .unwrap();
+    seed_local_move(
+        &base.pool,
+        "Breakfast/Pancakes.cook",
+        "Brunch/Pancakes.cook",
+        5,
+    );
+    base.chunker.set_device_name(Some("Pixel".to_string()));
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_upload_once");
+
+    assert!(!ok, "the old path's delete goes up in the next pass");
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("Brunch/Pancakes.cook"))
+            .await
+            .unwrap(),
+        b"Flour\n"
+    );
+    assert_eq!(
+        tokio::fs::read(
+            base.dir
+                .path()
+                .join("Brunch/Pancakes (conflicted copy from Pixel).cook")
+        )
+        .await
+        .unwrap(),
+        b"Eggs\n"
+    );
+    assert!(matches!(
+        listener.statuses.lock().unwrap().as_slice(),
+        [SyncStatus::Conflict { .. }]
+    ));
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let pending: Vec<(String, bool)> = registry::updated_locally(conn, NS)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.path, r.deleted))
+        .collect();
+    assert_eq!(pending, vec![("Breakfast/Pancakes.cook".to_string(), true)]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_renames_moved_file_instead_of_downloading_it() {
+    let server = MockServer::start().await;
+
+    let mut base = common::client_base();
+    tokio::fs::create_dir(base.dir.path().join("Breakfast"))
+        .await
+        .unwrap();
+    let old_path = base.dir.path().join("Breakfast/Pancakes.cook");
+    tokio::fs::write(&old_path, b"Eggs\n").await.unwrap();
+    let chunk_id = base
+        .chunker
+        .hashify("Breakfast/Pancakes.cook")
+        .await
+        .unwrap()
+        .join(",");
+    let checksum = cooklang_sync_client::chunker::file_checksum(&old_path).unwrap();
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let mut old = sample_create("Breakfast/Pancakes.cook", 5);
+        old.jid = Some(5);
+        old.checksum = Some(checksum.clone());
+        registry::create(conn, &[old]).expect("create");
+    }
+
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "Breakfast/Pancakes.cook", "deleted": true, "chunk_ids": "" },
+            { "id": 12, "path": "Brunch/Pancakes.cook", "deleted": false, "chunk_ids": chunk_id, "moved_from": "Breakfast/Pancakes.cook" }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(
+        !base.dir.path().join("Breakfast").exists(),
+        "old file and its empty dir are gone"
+    );
+    let bytes = tokio::fs::read(base.dir.path().join("Brunch/Pancakes.cook"))
+        .await
+        .expect("read");
+    assert_eq!(bytes, b"Eggs\n");
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let rows = registry::non_deleted(conn, NS).expect("non_deleted");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].path, "Brunch/Pancakes.cook");
+    assert_eq!(rows[0].jid, Some(12));
+    assert_eq!(rows[0].checksum.as_deref(), Some(checksum.as_str()));
+}
+
+/// `a.cook` on disk with `content`, last synced at jid 5 and edited since.
+async fn seed_local_edit(base: &common::ClientBase, content: &[u8]) {
+    tokio::fs::write(base.dir.path().join("a.cook"), content)
+        .await
+        .unwrap();
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let mut synced = sample_create("a.cook", 4);
+    synced.jid = Some(5);
+    registry::create(
+        conn,
+        &[synced, sample_create("a.cook", content.len() as i64)],
+    )
+    .expect("create");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_keeps_unsynced_local_edit_as_conflicted_copy() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    mount_list_of_one(&server, &chunk_id).await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    seed_local_edit(&base, b"Bacon\n").await;
+    base.chunker.set_device_name(Some("Pixel".to_string()));
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    let copy = "a (conflicted copy from Pixel).cook";
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a.cook"))
+            .await
+            .unwrap(),
+        b"Eggs\n",
+        "remote version takes the name"
+    );
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join(copy)).await.unwrap(),
+        b"Bacon\n",
+        "local edit is kept"
+    );
+    let statuses = listener.statuses.lock().unwrap().clone();
+    match statuses.as_slice() {
+        [SyncStatus::Conflict {
+            path,
+            conflicted_copy,
+        }] => {
+            assert_eq!(path, "a.cook");
+            assert_eq!(conflicted_copy, copy);
+        }
+        other => panic!("expected one SyncStatus::Conflict, got {:?}", other),
+    }
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(
+        registry::updated_locally(conn, NS).unwrap().is_empty(),
+        "the copy is left for the indexer"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_keeps_local_edit_the_indexer_has_not_seen() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    mount_list_of_one(&server, &chunk_id).await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    // Only the synced row, the edit happened after the last scan.
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Bacon\n")
+        .await
+        .unwrap();
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let mut synced = sample_create("a.cook", 4);
+        synced.jid = Some(5);
+        registry::create(conn, &[synced]).expect("create");
+    }
+    base.chunker.set_device_name(Some("Pixel".to_string()));
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a.cook"))
+            .await
+            .unwrap(),
+        b"Eggs\n"
+    );
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a (conflicted copy from Pixel).cook"))
+            .await
+            .unwrap(),
+        b"Bacon\n"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_applies_newer_remote_version_on_conflict() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("parent_jid=5"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "Conflict": [11, chunk_id, false] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    seed_local_edit(&base, b"Bacon\n").await;
+    base.chunker.set_device_name(Some("Pixel".to_string()));
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_upload_once");
+
+    assert!(
+        !ok,
+        "whatever is left after the conflict goes up in the next pass"
+    );
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a.cook"))
+            .await
+            .unwrap(),
+        b"Eggs\n",
+        "the newer remote version is downloaded"
+    );
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a (conflicted copy from Pixel).cook"))
+            .await
+            .unwrap(),
+        b"Bacon\n",
+        "the stale edit is kept aside"
+    );
+    assert!(matches!(
+        listener.statuses.lock().unwrap().as_slice(),
+        [SyncStatus::Conflict { .. }]
+    ));
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert_eq!(
+        registry::latest_synced(conn, NS, "a.cook")
+            .unwrap()
+            .unwrap()
+            .jid,
+        Some(11)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_keeps_newer_empty_remote_file_on_conflict() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "Conflict": [11, "", false] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut base = common::client_base();
+    seed_local_edit(&base, b"Bacon\n").await;
+    base.chunker.set_device_name(Some("Pixel".to_string()));
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    // Emptied remotely, not deleted.
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a.cook"))
+            .await
+            .unwrap(),
+        b""
+    );
+    assert_eq!(
+        tokio::fs::read(base.dir.path().join("a (conflicted copy from Pixel).cook"))
+            .await
+            .unwrap(),
+        b"Bacon\n"
+    );
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let synced = registry::latest_synced(conn, NS, "a.cook")
+        .unwrap()
+        .unwrap();
+    assert_eq!(synced.jid, Some(11));
+    assert!(!synced.deleted);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_sends_zero_parent_for_never_synced_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit"))
+        .and(body_string_contains("parent_jid=0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": 3 })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .unwrap();
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    registry::create(conn, &[sample_create("a.cook", 5)]).expect("create");
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let ok = check_upload_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+
+    assert!(ok);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_does_not_report_conflict_for_identical_edits() {
+    let server = MockServer::start().await;
+    let chunk_id = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    mount_list_of_one(&server, &chunk_id).await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(&chunk_id, "Eggs\n"))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    seed_local_edit(&base, b"Eggs\n").await;
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    assert_eq!(
+        std::fs::read_dir(base.dir.path())
+            .unwrap()
+            .filter(|e| e
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .contains("conflicted copy"))
+            .count(),
+        0
+    );
+    assert!(listener.statuses.lock().unwrap().is_empty());
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert_eq!(registry::non_deleted(conn, NS).unwrap()[0].jid, Some(11));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_keeps_file_edited_locally_when_deleted_remotely() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 22, "path": "a.cook", "deleted": true, "chunk_ids": "" }
+        ])))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    seed_local_edit(&base, b"Bacon\n").await;
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    assert!(
+        base.dir.path().join("a.cook").exists(),
+        "the edit outlives the delete"
+    );
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(
+        registry::non_deleted(conn, NS).unwrap().is_empty(),
+        "so the indexer sees it as new and uploads it again"
+    );
+}
+
+/// Mounts a list with `a.cook` at jid 11 made of `lines`, and serves the
+/// chunk of `missing`, the only line the client can't have yet.
+async fn mount_remote_lines(server: &MockServer, lines: &[&str], missing: &str) {
+    let ids: Vec<String> = lines
+        .iter()
+        .map(|l| cooklang_sync_client::chunk_id::blake3(l.as_bytes()))
+        .collect();
+    mount_list_of_one(server, &ids.join(",")).await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .respond_with(chunk_download_response(
+            &cooklang_sync_client::chunk_id::blake3(missing.as_bytes()),
+            missing,
+        ))
+        .mount(server)
+        .await;
+}
+
+/// `a.cook` last synced at jid 5 as `base` and edited into `edited` since.
+async fn seed_local_edit_of(base: &mut common::ClientBase, synced: &[&str], edited: &str) {
+    let mut ids = vec![];
+    for line in synced {
+        let id = cooklang_sync_client::chunk_id::blake3(line.as_bytes());
+        base.chunker
+            .save_chunk(&id, line.as_bytes().to_vec())
+            .unwrap();
+        ids.push(id);
+    }
+    seed_local_edit(base, edited.as_bytes()).await;
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let synced_row = registry::latest_synced(conn, NS, "a.cook")
+        .unwrap()
+        .unwrap();
+    registry::update_chunk_ids(conn, &synced_row, &ids.join(",")).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_merges_edits_to_different_lines() {
+    let server = MockServer::start().await;
+    mount_remote_lines(
+        &server,
+        &["Mix @flour.\n", "Fry.\n", "Serve with @syrup.\n"],
+        "Serve with @syrup.\n",
+    )
+    .await;
+
+    let mut base = common::client_base();
+    seed_local_edit_of(
+        &mut base,
+        &["Mix @flour.\n", "Fry.\n", "Serve.\n"],
+        "Mix @flour{250%g}.\nFry.\nServe.\n",
+    )
+    .await;
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    let merged = tokio::fs::read_to_string(base.dir.path().join("a.cook"))
+        .await
+        .unwrap();
+    assert_eq!(merged, "Mix @flour{250%g}.\nFry.\nServe with @syrup.\n");
+    assert!(
+        listener.statuses.lock().unwrap().is_empty(),
+        "no conflict to report"
+    );
+
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let synced = registry::latest_synced(conn, NS, "a.cook")
+        .unwrap()
+        .unwrap();
+    assert_eq!(synced.jid, Some(11), "the remote version is the new base");
+    let pending = registry::updated_locally(conn, NS).unwrap();
+    assert_eq!(pending.len(), 1, "the merge is committed as a new version");
+    assert_eq!(pending[0].size, merged.len() as i64);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_falls_back_to_conflicted_copy_when_edits_overlap() {
+    let server = MockServer::start().await;
+    mount_remote_lines(
+        &server,
+        &["Mix @flour{300%g}.\n", "Fry.\n", "Serve.\n"],
+        "Mix @flour{300%g}.\n",
+    )
+    .await;
+
+    let mut base = common::client_base();
+    seed_local_edit_of(
+        &mut base,
+        &["Mix @flour.\n", "Fry.\n", "Serve.\n"],
+        "Mix @flour{250%g}.\nFry.\nServe.\n",
+    )
+    .await;
+    let listener = RecordingListener::default();
+
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        Some(&listener),
+    )
+    .await
+    .expect("check_download_once");
+
+    assert_eq!(
+        tokio::fs::read_to_string(base.dir.path().join("a.cook"))
+            .await
+            .unwrap(),
+        "Mix @flour{300%g}.\nFry.\nServe.\n"
+    );
+    assert_eq!(
+        tokio::fs::read_to_string(base.dir.path().join("a (conflicted copy).cook"))
+            .await
+            .unwrap(),
+        "Mix @flour{250%g}.\nFry.\nServe.\n"
+    );
+    assert!(matches!(
+        listener.statuses.lock().unwrap().as_slice(),
+        [SyncStatus::Conflict { .. }]
+    ));
+}
+
+fn intent(jid: i32, path: &str, content: Option<&[u8]>) -> DownloadIntent {
+    DownloadIntent {
+        namespace_id: NS,
+        jid,
+        path: path.to_string(),
+        deleted: content.is_none(),
+        chunk_ids: content
+            .map(cooklang_sync_client::chunk_id::blake3)
+            .unwrap_or_default(),
+        checksum: content.map(|c| blake3::hash(c).to_hex().to_string()),
+    }
+}
+
+#[test]
+fn recover_interrupted_download_records_files_that_made_it_to_disk() {
+    let base = common::client_base();
+    std::fs::write(base.dir.path().join("a.cook"), b"Eggs\n").unwrap();
+    std::fs::write(base.dir.path().join("b.cook"), b"Flour\n").unwrap();
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    registry::begin_download(
+        conn,
+        &[
+            intent(11, "a.cook", Some(b"Eggs\n")),
+            intent(12, "c.cook", None),
+            intent(13, "b.cook", Some(b"Milk\n")),
+            intent(14, "d.cook", Some(b"Salt\n")),
+        ],
+    )
+    .expect("begin_download");
+
+    let recovered = recover_interrupted_download(
+        &base.pool,
+        base.dir.path(),
+        NS,
+        &FileFormatRegistry::default(),
+    )
+    .expect("recover");
+
+    assert_eq!(
+        recovered, 2,
+        "a.cook is written and c.cook gone, b.cook and d.cook weren't reached"
+    );
+    let rows = registry::non_deleted(conn, NS).unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!((rows[0].path.as_str(), rows[0].jid), ("a.cook", Some(11)));
+    assert_eq!(
+        rows[0].chunk_ids.as_deref(),
+        Some(cooklang_sync_client::chunk_id::blake3(b"Eggs\n").as_str())
+    );
+    assert_eq!(
+        registry::latest_jid(conn, NS).unwrap(),
+        12,
+        "b.cook and d.cook get listed again"
+    );
+    assert!(registry::download_intents(conn, NS).unwrap().is_empty());
+}
+
+fn chunks_download_response(chunks: &[(String, String)]) -> ResponseTemplate {
+    let boundary = "downloadbound";
+    let mut body = String::new();
+    for (id, content) in chunks {
+        body.push_str(&format!("--{boundary}\r\nX-Chunk-ID: {id}\r\nContent-Type: application/octet-stream\r\n\r\n{content}\r\n"));
+    }
+    body.push_str(&format!("--{boundary}--\r\n"));
+    ResponseTemplate::new(200)
+        .insert_header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", boundary).as_str(),
+        )
+        .set_body_bytes(body.into_bytes())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_writes_files_whose_batches_landed_when_a_later_one_fails() {
+    let server = MockServer::start().await;
+    let line = |content: String| {
+        (
+            cooklang_sync_client::chunk_id::blake3(content.as_bytes()),
+            content,
+        )
+    };
+    let eggs = line("Eggs\n".to_string());
+    let steps: Vec<(String, String)> = (0..500).map(|i| line(format!("Step {i}.\n"))).collect();
+    let step_ids: Vec<&str> = steps.iter().map(|(id, _)| id.as_str()).collect();
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "a.cook", "deleted": false, "chunk_ids": eggs.0 },
+            { "id": 12, "path": "b.cook", "deleted": false, "chunk_ids": step_ids.join(",") }
+        ])))
+        .mount(&server)
+        .await;
+
+    // 501 chunks make two requests, a.cook only needs the first one.
+    let first: Vec<(String, String)> = std::iter::once(eggs.clone())
+        .chain(steps[..499].iter().cloned())
+        .collect();
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(eggs.0.replace(':', "%3A")))
+        .respond_with(chunks_download_response(&first))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(steps[499].0.replace(':', "%3A")))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+    let err = check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+        matches!(err, SyncError::ServerUnavailable(_)),
+        "got {:?}",
+        err
+    );
+    assert_eq!(
+        std::fs::read(base.dir.path().join("a.cook")).unwrap(),
+        b"Eggs\n"
+    );
+    assert!(!base.dir.path().join("b.cook").exists());
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert_eq!(
+        registry::latest_jid(conn, NS).unwrap(),
+        11,
+        "b.cook gets listed again"
+    );
+    assert!(registry::download_intents(conn, NS).unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_keeps_batches_of_large_chunks_small() {
+    let server = MockServer::start().await;
+    let chunks: Vec<(String, String)> = (0..3)
+        .map(|i| {
+            let content = format!("block {i}");
+            (
+                cooklang_sync_client::chunk_id::blake3(content.as_bytes()),
+                content,
+            )
+        })
+        .collect();
+    let ids: Vec<&str> = chunks.iter().map(|(id, _)| id.as_str()).collect();
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "photo.jpg", "deleted": false, "chunk_ids": ids.join(",") }
+        ])))
+        .mount(&server)
+        .await;
+    // Chunks of up to 2 MB, only one fits in a request.
+    for chunk in &chunks {
+        Mock::given(method("POST"))
+            .and(path("/chunks/download"))
+            .and(body_string_contains(chunk.0.replace(':', "%3A")))
+            .respond_with(chunks_download_response(std::slice::from_ref(chunk)))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let mut base = common::client_base();
+    let mut formats = FileFormatRegistry::default();
+    formats.set_chunking(
+        FileFormat::Binary,
+        Chunking::content_defined(64 * 1_024, 256 * 1_024, 2 * 1_024 * 1_024).unwrap(),
+    );
+    base.chunker.set_formats(formats);
+    let remote = Remote::new(&server.uri(), TOKEN);
+    check_download_once(
+        &base.pool,
+        Arc::new(Mutex::new(base.chunker)),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+
+    let requests = server.received_requests().await.unwrap();
+    let downloads = requests
+        .iter()
+        .filter(|r| r.url.path() == "/chunks/download")
+        .count();
+    assert_eq!(downloads, 3);
+    assert_eq!(
+        std::fs::read(base.dir.path().join("photo.jpg")).unwrap(),
+        b"block 0block 1block 2"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn run_retries_transient_errors_instead_of_ending() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/poll"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(30)))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let listener = Arc::new(RecordingListener::default());
+    let token = tokio_util::sync::CancellationToken::new();
+    let (_tx, rx) = futures::channel::mpsc::channel(1);
+    let control = SyncControl::default();
+
+    let run = cooklang_sync_client::syncer::run(
+        token.clone(),
+        &control,
+        Some(listener.clone()),
+        &base.pool,
+        base.dir.path(),
+        NS,
+        base.chunker,
+        &remote,
+        rx,
+        true,
+    );
+    let watch = async {
+        for _ in 0..100 {
+            if listener
+                .statuses
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|s| matches!(s, SyncStatus::Idle))
+                .count()
+                > 0
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        token.cancel();
+    };
+    let (result, _) = tokio::join!(run, watch);
+
+    result.expect("a 503 doesn't end the run");
+    let statuses = listener.statuses.lock().unwrap().clone();
+    assert!(
+        matches!(
+            statuses.as_slice(),
+            [
+                SyncStatus::Downloading,
+                SyncStatus::Retrying { attempt: 1, .. },
+                SyncStatus::Downloading,
+                SyncStatus::Idle,
+                ..
+            ]
+        ),
+        "got {:?}",
+        statuses
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn run_ends_with_fatal_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let token = tokio_util::sync::CancellationToken::new();
+    let (_tx, rx) = futures::channel::mpsc::channel(1);
+    let control = SyncControl::default();
+
+    let run = cooklang_sync_client::syncer::run(
+        token.clone(),
+        &control,
+        None,
+        &base.pool,
+        base.dir.path(),
+        NS,
+        base.chunker,
+        &remote,
+        rx,
+        false,
+    );
+    let err = tokio::time::timeout(std::time::Duration::from_secs(5), run)
+        .await
+        .expect("run ends without being cancelled")
+        .unwrap_err();
+
+    assert!(matches!(err, SyncError::Unauthorized), "got {:?}", err);
+    assert!(!token.is_cancelled());
+}
+
+/// Asks for a sync right away whenever the sync says it's going to wait.
+struct ImpatientListener(Arc<SyncControl>);
+
+impl SyncStatusListener for ImpatientListener {
+    fn on_status_changed(&self, status: SyncStatus) {
+        if let SyncStatus::Retrying { .. } = status {
+            self.0.sync_now();
+        }
+    }
+
+    fn on_complete(&self, _success: bool, _message: Option<String>) {}
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn run_retries_right_away_on_sync_now() {
+    let server = MockServer::start().await;
+    // Backing off from these would take half a minute on average.
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(6)
+        .expect(6)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let token = tokio_util::sync::CancellationToken::new();
+    let (_tx, rx) = futures::channel::mpsc::channel(1);
+    let control = Arc::new(SyncControl::default());
+    let listener: Arc<dyn SyncStatusListener> = Arc::new(ImpatientListener(Arc::clone(&control)));
+
+    let run = cooklang_sync_client::syncer::run(
+        token,
+        &control,
+        Some(listener),
+        &base.pool,
+        base.dir.path(),
+        NS,
+        base.chunker,
+        &remote,
+        rx,
+        true,
+    );
+    let err = tokio::time::timeout(std::time::Duration::from_secs(5), run)
+        .await
+        .expect("retried without waiting out the backoff")
+        .unwrap_err();
+
+    assert!(matches!(err, SyncError::Unauthorized), "got {:?}", err);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_upload_once_leaves_binary_files_for_an_unmetered_network() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .and(body_string_contains("a.cook"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [20] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/metadata/commit_with_chunks"))
+        .and(body_string_contains("photo.jpg"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "Success": [21] })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    tokio::fs::write(base.dir.path().join("a.cook"), b"Eggs\n")
+        .await
+        .expect("write file");
+    tokio::fs::write(base.dir.path().join("photo.jpg"), b"JPEG")
+        .await
+        .expect("write file");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        registry::create(
+            conn,
+            &[sample_create("a.cook", 5), sample_create("photo.jpg", 4)],
+        )
+        .expect("create");
+    }
+
+    let mut remote = Remote::new(&server.uri(), TOKEN);
+    let network = Arc::new(NetworkPolicy::default());
+    network.set_metered(true);
+    remote.set_network(Arc::clone(&network));
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+
+    check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let waiting: Vec<String> = registry::updated_locally(conn, NS)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.path)
+            .collect();
+        assert_eq!(
+            waiting,
+            vec!["photo.jpg"],
+            "only the recipe goes up while metered"
+        );
+    }
+
+    network.set_metered(false);
+    check_upload_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_upload_once");
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(registry::updated_locally(conn, NS).unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_defers_binary_files_on_a_metered_network() {
+    let server = MockServer::start().await;
+    let eggs = cooklang_sync_client::chunk_id::blake3(b"Eggs\n");
+    let photo = cooklang_sync_client::chunk_id::blake3(b"JPEG");
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "photo.jpg", "deleted": false, "chunk_ids": photo },
+            { "id": 12, "path": "a.cook", "deleted": false, "chunk_ids": eggs }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "12"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(eggs.replace(':', "%3A")))
+        .respond_with(chunks_download_response(&[(
+            eggs.clone(),
+            "Eggs\n".to_string(),
+        )]))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(photo.replace(':', "%3A")))
+        .respond_with(chunks_download_response(&[(
+            photo.clone(),
+            "JPEG".to_string(),
+        )]))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let mut remote = Remote::new(&server.uri(), TOKEN);
+    let network = Arc::new(NetworkPolicy::default());
+    network.set_metered(true);
+    remote.set_network(Arc::clone(&network));
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+    assert_eq!(
+        std::fs::read(base.dir.path().join("a.cook")).unwrap(),
+        b"Eggs\n"
+    );
+    assert!(
+        !base.dir.path().join("photo.jpg").exists(),
+        "the photo waits"
+    );
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        assert_eq!(
+            registry::latest_jid(conn, NS).unwrap(),
+            12,
+            "the recipe is past the photo"
+        );
+        assert_eq!(registry::deferred_downloads(conn, NS).unwrap().len(), 1);
+    }
+
+    // Listed after the photo already, so it has to come from what was left.
+    network.set_metered(false);
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+    assert_eq!(
+        std::fs::read(base.dir.path().join("photo.jpg")).unwrap(),
+        b"JPEG"
+    );
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    assert!(registry::deferred_downloads(conn, NS).unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn check_download_once_defers_extensionless_files_by_their_synced_format() {
+    let server = MockServer::start().await;
+    let notes = cooklang_sync_client::chunk_id::blake3(b"Salt\n");
+    let scan = cooklang_sync_client::chunk_id::blake3(b"TIFF");
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .and(query_param("jid", "10"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 11, "path": "scan", "deleted": false, "chunk_ids": scan },
+            { "id": 12, "path": "notes", "deleted": false, "chunk_ids": notes }
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(notes.replace(':', "%3A")))
+        .respond_with(chunks_download_response(&[(
+            notes.clone(),
+            "Salt\n".to_string(),
+        )]))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/chunks/download"))
+        .and(body_string_contains(scan.replace(':', "%3A")))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    {
+        let conn = &mut get_connection(&base.pool).expect("checkout");
+        let mut scan = sample_create("scan", 4);
+        scan.jid = Some(9);
+        scan.format = Some(FileFormat::Binary);
+        let mut notes = sample_create("notes", 5);
+        notes.jid = Some(10);
+        notes.format = Some(FileFormat::Text);
+        registry::create(conn, &[scan, notes]).expect("create");
+    }
+    let mut remote = Remote::new(&server.uri(), TOKEN);
+    let network = Arc::new(NetworkPolicy::default());
+    network.set_metered(true);
+    remote.set_network(Arc::clone(&network));
+    let chunker_arc = Arc::new(Mutex::new(base.chunker));
+
+    // Neither name says what the file is, the registry does.
+    check_download_once(
+        &base.pool,
+        Arc::clone(&chunker_arc),
+        &remote,
+        base.dir.path(),
+        NS,
+        None,
+    )
+    .await
+    .expect("check_download_once");
+    assert_eq!(
+        std::fs::read(base.dir.path().join("notes")).unwrap(),
+        b"Salt\n"
+    );
+    let conn = &mut get_connection(&base.pool).expect("checkout");
+    let deferred = registry::deferred_downloads(conn, NS).unwrap();
+    assert_eq!(deferred.len(), 1);
+    assert_eq!(deferred[0].path, "scan");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn run_waits_while_paused_and_syncs_now_when_asked() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/metadata/poll"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(30)))
+        .mount(&server)
+        .await;
+
+    let base = common::client_base();
+    let remote = Remote::new(&server.uri(), TOKEN);
+    let listener = Arc::new(RecordingListener::default());
+    let token = tokio_util::sync::CancellationToken::new();
+    let (_tx, rx) = futures::channel::mpsc::channel(1);
+    let control = SyncControl::default();
+    control.pause();
+
+    let lists = || async {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path() == "/metadata/list")
+            .count()
+    };
+    let run = cooklang_sync_client::syncer::run(
+        token.clone(),
+        &control,
+        Some(listener.clone()),
+        &base.pool,
+        base.dir.path(),
+        NS,
+        base.chunker,
+        &remote,
+        rx,
+        true,
+    );
+    let drive = async {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(lists().await, 0, "nothing is synced while paused");
+        assert!(listener
+            .statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| matches!(s, SyncStatus::Paused)));
+
+        control.resume();
+        for _ in 0..100 {
+            if lists().await > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(lists().await, 1);
+
+        // Long before the next check or the end of the poll.
+        control.sync_now();
+        for _ in 0..100 {
+            if lists().await > 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(lists().await, 2, "sync_now checks the server again");
+        token.cancel();
+    };
+    let (result, _) = tokio::join!(run, drive);
+
+    result.expect("run ends when cancelled");
+}
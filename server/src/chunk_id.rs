@@ -4,33 +4,77 @@ use std::path::{Path, PathBuf};
 
 use rocket::request::FromParam;
 
-/// A _probably_ unique chunk ID.
+/// Prefix of IDs that are a full BLAKE3 digest.
+const BLAKE3_PREFIX: &str = "b3:";
+/// Same, keyed by the client. These chunks are encrypted end-to-end.
+const KEYED_BLAKE3_PREFIX: &str = "k3:";
+const BLAKE3_HEX_LEN: usize = 64;
+/// Older clients send truncated SHA-256 digests without a prefix.
+const LEGACY_TEXT_LEN: usize = 10;
+const LEGACY_BINARY_LEN: usize = 32;
+
+/// A chunk ID, either `<algorithm>:<full hex digest>` or a legacy bare
+/// SHA-256 prefix.
 #[derive(UriDisplayPath, PartialEq, FromForm, Debug, Clone)]
 pub struct ChunkId<'a>(pub(crate) Cow<'a, str>);
 
 impl ChunkId<'_> {
     /// Returns the path to the chunk in `upload/` corresponding to this ID.
+    ///
+    /// Chunks are sharded by the first two chars of the digest. Legacy IDs
+    /// keep their original location, tagged ones are stored with `:` swapped
+    /// for `_` so the name is valid on every filesystem.
     pub fn file_path(&self) -> PathBuf {
         let root = env::var("UPLOAD_DIR").unwrap_or(String::from("./upload"));
         let id_str = self.id();
+        let digest = self.digest();
 
-        if id_str.len() < 2 {
+        if digest.len() < 2 {
             return Path::new(&root).join("null").join(id_str);
         }
 
-        let first_char = &id_str[0..1];
-        let second_char = &id_str[1..2];
+        let first_char = &digest[0..1];
+        let second_char = &digest[1..2];
 
         Path::new(&root)
             .join(first_char)
             .join(second_char)
-            .join(id_str)
+            .join(id_str.replace(':', "_"))
     }
 
     pub fn id(&self) -> &str {
         self.0.as_ref()
     }
 
+    /// The hex digest without the algorithm prefix.
+    fn digest(&self) -> &str {
+        let id_str = self.id();
+
+        id_str
+            .split_once(':')
+            .map(|(_, digest)| digest)
+            .unwrap_or(id_str)
+    }
+
+    /// Whether the ID is empty, a legacy SHA-256 prefix or a tagged (keyed)
+    /// BLAKE3 digest. Anything else must not reach the filesystem.
+    pub fn is_valid(&self) -> bool {
+        let id_str = self.id();
+
+        if id_str.is_empty() {
+            return true;
+        }
+
+        if let Some(digest) = id_str
+            .strip_prefix(BLAKE3_PREFIX)
+            .or_else(|| id_str.strip_prefix(KEYED_BLAKE3_PREFIX))
+        {
+            digest.len() == BLAKE3_HEX_LEN && is_hex(digest)
+        } else {
+            (id_str.len() == LEGACY_TEXT_LEN || id_str.len() == LEGACY_BINARY_LEN) && is_hex(id_str)
+        }
+    }
+
     /// Returns the path to the chunk in `upload/` corresponding to this ID.
     pub fn is_present(&self) -> bool {
         if self.0.as_ref() == "" {
@@ -41,17 +85,19 @@ impl ChunkId<'_> {
     }
 }
 
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
 /// Returns an instance of `ChunkId` if the path segment is a valid ID.
 /// Otherwise returns the invalid ID as the `Err` value.
 impl<'a> FromParam<'a> for ChunkId<'a> {
     type Error = &'a str;
 
     fn from_param(param: &'a str) -> Result<Self, Self::Error> {
-        param
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric())
-            .then(|| ChunkId(param.into()))
-            .ok_or(param)
+        let chunk_id = ChunkId(param.into());
+
+        chunk_id.is_valid().then_some(chunk_id).ok_or(param)
     }
 }
 
@@ -63,3 +109,39 @@ impl<'a> From<&'a str> for ChunkId<'a> {
         ChunkId(Cow::from(file_name))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const B3: &str = "b3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+    #[test]
+    fn accepts_tagged_and_legacy_ids() {
+        assert!(ChunkId::from(B3).is_valid());
+        assert!(ChunkId::from(B3.replace("b3:", "k3:").as_str()).is_valid());
+        assert!(ChunkId::from("0123456789").is_valid());
+        assert!(ChunkId::from("0123456789abcdef0123456789abcdef").is_valid());
+        assert!(ChunkId::from("").is_valid());
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert!(!ChunkId::from("b3:abc").is_valid());
+        assert!(!ChunkId::from("md5:0123456789").is_valid());
+        assert!(!ChunkId::from("0123456789abcdef").is_valid());
+        assert!(!ChunkId::from("../../etc/pass").is_valid());
+        assert!(!ChunkId::from("ABCDEF0123").is_valid());
+        assert!(ChunkId::from_param("../../etc/pass").is_err());
+        assert!(ChunkId::from_param(B3).is_ok());
+    }
+
+    #[test]
+    fn file_path_shards_by_digest() {
+        let legacy = ChunkId::from("0123456789").file_path();
+        assert!(legacy.ends_with("0/1/0123456789"));
+
+        let tagged = ChunkId::from(B3).file_path();
+        assert!(tagged.ends_with(format!("a/f/{}", B3.replace(':', "_"))));
+    }
+}
@@ -40,6 +40,14 @@ async fn upload_chunks_deprecated(
             continue;
         }
 
+        if !chunk_id.is_valid() {
+            error!("Invalid chunk ID {:?}", chunk_id.id());
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid chunk ID",
+            ));
+        }
+
         let full_path = chunk_id.file_path();
 
         if let Some(parent) = full_path.parent() {
@@ -85,6 +93,14 @@ async fn upload_chunks(
             continue;
         }
 
+        if !chunk_id.is_valid() {
+            error!("Invalid chunk ID {:?}", chunk_id.id());
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid chunk ID",
+            ));
+        }
+
         let full_path = chunk_id.file_path();
 
         if let Some(parent) = full_path.parent() {
@@ -142,6 +158,7 @@ async fn download_chunks(
     let cloned_chunk_ids: Vec<_> = chunk_ids
         .0
         .iter()
+        .filter(|chunk_id| chunk_id.is_valid())
         .map(|chunk_id| {
             let id = chunk_id.id().to_string(); // Assuming id is of type &str
             let file_path = chunk_id.file_path(); // Assuming file_path is of type &str
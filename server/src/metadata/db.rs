@@ -47,6 +47,102 @@ pub fn latest_for_path(
         .optional()
 }
 
+/// Insert `record` unless its path has a record newer than `parent_jid` by
+/// now, which is returned instead. Checked in the same transaction, so only
+/// one of two commits on top of the same parent gets in.
+pub fn insert_on_parent(
+    conn: &mut DbConnection,
+    record: NewFileRecord,
+    parent_jid: i32,
+) -> Result<Result<i32, FileRecord>> {
+    conn.transaction(|conn| {
+        let latest = latest_for_path(conn, record.user_id, &record.path)?;
+
+        match latest {
+            Some(latest) if is_newer_than_parent(&latest, parent_jid) => Ok(Err(latest)),
+            _ => insert_new_record(conn, record).map(Ok),
+        }
+    })
+}
+
+/// Whether `latest` came after the record a commit on `parent_jid` is based
+/// on. Clients forget deletions they synced, so parent jid 0 fits a path
+/// whose latest record is a tombstone too.
+fn is_newer_than_parent(latest: &FileRecord, parent_jid: i32) -> bool {
+    latest.id > parent_jid && !(parent_jid == 0 && latest.deleted)
+}
+
+/// Insert `records`, each on top of its parent jid like `insert_on_parent`,
+/// in one transaction and so under consecutive ids. When any of their paths
+/// has a record newer than its parent by now, nothing is inserted and those
+/// records are returned instead.
+pub fn insert_batch(
+    conn: &mut DbConnection,
+    records: Vec<(NewFileRecord, Option<i32>)>,
+) -> Result<Result<Vec<i32>, Vec<FileRecord>>> {
+    conn.transaction(|conn| {
+        let mut conflicts = vec![];
+
+        for (record, parent_jid) in &records {
+            let Some(parent_jid) = parent_jid else {
+                continue;
+            };
+
+            match latest_for_path(conn, record.user_id, &record.path)? {
+                Some(latest) if is_newer_than_parent(&latest, *parent_jid) => {
+                    conflicts.push(latest)
+                }
+                _ => {}
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Ok(Err(conflicts));
+        }
+
+        records
+            .into_iter()
+            .map(|(record, _)| insert_new_record(conn, record))
+            .collect::<Result<Vec<_>>>()
+            .map(Ok)
+    })
+}
+
+/// Insert `tombstone` and `moved` in one transaction, moving the file at
+/// `tombstone.path` to `moved.path`. Returns None without inserting anything
+/// when the old path is deleted or no longer has the chunks of `moved`, and
+/// like `insert_on_parent` the latest record of `moved.path` when it's newer
+/// than `parent_jid`.
+pub fn move_record(
+    conn: &mut DbConnection,
+    tombstone: NewFileRecord,
+    moved: NewFileRecord,
+    parent_jid: Option<i32>,
+) -> Result<Option<Result<i32, FileRecord>>> {
+    conn.transaction(|conn| {
+        if tombstone.path == moved.path {
+            return Ok(None);
+        }
+
+        match latest_for_path(conn, tombstone.user_id, &tombstone.path)? {
+            Some(source) if !source.deleted && source.chunk_ids == moved.chunk_ids => {}
+            _ => return Ok(None),
+        }
+
+        if let Some(parent_jid) = parent_jid {
+            match latest_for_path(conn, moved.user_id, &moved.path)? {
+                Some(latest) if is_newer_than_parent(&latest, parent_jid) => {
+                    return Ok(Some(Err(latest)))
+                }
+                _ => {}
+            }
+        }
+
+        insert_new_record(conn, tombstone)?;
+        insert_new_record(conn, moved).map(|id| Some(Ok(id)))
+    })
+}
+
 pub fn has_files(conn: &mut DbConnection, user_id: i32) -> Result<bool> {
     let subquery = file_records::table
         .filter(file_records::user_id.eq(user_id))
@@ -0,0 +1,3 @@
+-- Old path of a file moved with /metadata/move, so other clients can
+-- rename their copy instead of downloading it again.
+ALTER TABLE file_records ADD COLUMN moved_from VARCHAR;
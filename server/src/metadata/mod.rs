@@ -2,12 +2,15 @@ use rocket::fairing::AdHoc;
 use rocket::form::Form;
 use rocket::response::Debug;
 use rocket::serde::json::Json;
+use rocket::tokio::fs;
 use rocket::{Shutdown, State};
 
+use std::path::Path;
 use std::sync::Mutex;
 use std::time::Duration;
 
 use crate::auth::user::User;
+use crate::chunk_id::ChunkId;
 
 mod db;
 mod middleware;
@@ -17,7 +20,10 @@ mod request;
 mod response;
 mod schema;
 
-use db::{has_files as db_has_files, insert_new_record, latest_for_path, list as db_list, Db};
+use db::{
+    has_files as db_has_files, insert_batch, insert_new_record, insert_on_parent, latest_for_path,
+    list as db_list, move_record, Db,
+};
 use models::{FileRecord, NewFileRecord};
 
 use notification::ActiveClients;
@@ -27,6 +33,8 @@ type Result<T, E = Debug<diesel::result::Error>> = std::result::Result<T, E>;
 // check if all hashes are present
 // if any not present return back need more and list of hashes
 // if present all insert into db path and chunk hashes and return back a new jid
+// with a parent jid, a path committed again since then is a conflict instead,
+// clients without it overwrite whatever is there
 #[post("/commit?<uuid>", data = "<commit_payload>")]
 async fn commit(
     user: User,
@@ -35,11 +43,12 @@ async fn commit(
     uuid: String,
     commit_payload: Form<request::CommitPayload<'_>>,
 ) -> Result<Json<response::CommitResultStatus>> {
+    let parent_jid = commit_payload.parent_jid;
     let to_be_uploaded = commit_payload.non_local_chunks();
 
     match to_be_uploaded.is_empty() {
         true => {
-            let r = NewFileRecord::from_payload_and_user_id(commit_payload, user.id);
+            let r = NewFileRecord::from_payload_and_user_id(&commit_payload, user.id);
 
             // Dedup: if the latest record for (user_id, path) already has the
             // same chunk_ids and deleted flag, this commit is a no-op. Return
@@ -65,7 +74,34 @@ async fn commit(
                 }
             }
 
-            let id: i32 = db.run(move |conn| insert_new_record(conn, r)).await?;
+            let inserted = match parent_jid {
+                Some(parent_jid) => {
+                    db.run(move |conn| insert_on_parent(conn, r, parent_jid))
+                        .await?
+                }
+                None => {
+                    db.run(move |conn| insert_new_record(conn, r).map(Ok))
+                        .await?
+                }
+            };
+
+            let id: i32 = match inserted {
+                Ok(id) => id,
+                Err(latest) => {
+                    rocket::info!(
+                        "conflict: stale commit user_id={} path={:?} parent_jid={:?} latest_id={}",
+                        latest.user_id,
+                        latest.path,
+                        parent_jid,
+                        latest.id
+                    );
+                    return Ok(Json(response::CommitResultStatus::Conflict(
+                        latest.id,
+                        latest.chunk_ids,
+                        latest.deleted,
+                    )));
+                }
+            };
 
             clients.lock().unwrap().notify(&uuid);
 
@@ -84,6 +120,168 @@ async fn commit(
     }
 }
 
+// commit many files at once, all or nothing
+// if chunks are missing for any of them, all missing ones are returned
+// if any path was committed again since its parent jid, the latest records
+// of those paths are returned
+// otherwise all are inserted in one transaction under consecutive jids, so
+// other clients never see some of them without the others
+// unlike `commit` there's no dedup, only clients that send just what changed
+// use it
+#[post("/commit_batch?<uuid>", data = "<batch_payload>")]
+async fn commit_batch(
+    user: User,
+    clients: &State<Mutex<ActiveClients>>,
+    db: Db,
+    uuid: String,
+    batch_payload: Form<request::CommitBatchPayload<'_>>,
+) -> Result<Json<response::CommitBatchResultStatus>> {
+    commit_entries(user, clients, db, uuid, &batch_payload.entries).await
+}
+
+// same as `commit_batch`, with chunks in the same multipart request, so
+// small files need one round trip instead of commit, upload and commit again
+// chunks that are here already are skipped, ones that can't be stored are
+// asked for as with `commit_batch`
+#[post(
+    "/commit_with_chunks?<uuid>",
+    format = "multipart/form-data",
+    data = "<payload>"
+)]
+async fn commit_with_chunks(
+    user: User,
+    clients: &State<Mutex<ActiveClients>>,
+    db: Db,
+    uuid: String,
+    payload: Form<request::CommitWithChunksPayload<'_>>,
+) -> Result<Json<response::CommitBatchResultStatus>> {
+    for chunk in &payload.chunks {
+        let chunk_id = ChunkId::from(chunk.id);
+
+        if chunk_id.is_present() {
+            continue;
+        }
+
+        let full_path = chunk_id.file_path();
+
+        if let Err(e) = store_chunk(&full_path, chunk.data).await {
+            error!("Error storing chunk {:?}: {:?}", chunk_id.id(), e);
+            fs::remove_file(&full_path).await.ok();
+        }
+    }
+
+    commit_entries(user, clients, db, uuid, &payload.entries).await
+}
+
+async fn store_chunk(full_path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::write(full_path, data).await
+}
+
+async fn commit_entries(
+    user: User,
+    clients: &State<Mutex<ActiveClients>>,
+    db: Db,
+    uuid: String,
+    entries: &[request::CommitPayload<'_>],
+) -> Result<Json<response::CommitBatchResultStatus>> {
+    let mut to_be_uploaded: Vec<String> = entries
+        .iter()
+        .flat_map(|entry| entry.non_local_chunks())
+        .map(|chunk_id| chunk_id.0.to_string())
+        .collect();
+    to_be_uploaded.sort();
+    to_be_uploaded.dedup();
+
+    if !to_be_uploaded.is_empty() {
+        return Ok(Json(response::CommitBatchResultStatus::NeedChunks(
+            to_be_uploaded.join(","),
+        )));
+    }
+
+    let records: Vec<(NewFileRecord, Option<i32>)> = entries
+        .iter()
+        .map(|entry| {
+            (
+                NewFileRecord::from_payload_and_user_id(entry, user.id),
+                entry.parent_jid,
+            )
+        })
+        .collect();
+
+    match db.run(move |conn| insert_batch(conn, records)).await? {
+        Ok(ids) => {
+            clients.lock().unwrap().notify(&uuid);
+
+            Ok(Json(response::CommitBatchResultStatus::Success(ids)))
+        }
+        Err(conflicts) => {
+            rocket::info!(
+                "conflict: stale batch commit user_id={} paths={:?}",
+                user.id,
+                conflicts.iter().map(|r| &r.path).collect::<Vec<_>>()
+            );
+
+            Ok(Json(response::CommitBatchResultStatus::Conflict(
+                conflicts
+                    .into_iter()
+                    .map(|r| (r.path, r.id, r.chunk_ids, r.deleted))
+                    .collect(),
+            )))
+        }
+    }
+}
+
+// move a file without uploading it again
+// the old path gets a tombstone and the new one the same chunks, with the old
+// path in moved_from so other clients can rename their copy
+// 404 if the old path is gone or has other chunks by now, clients then commit
+// both paths as usual
+// with a parent jid, a new path committed since then is a conflict like in
+// `commit`
+#[post("/move?<uuid>", data = "<move_payload>")]
+async fn move_file(
+    user: User,
+    clients: &State<Mutex<ActiveClients>>,
+    db: Db,
+    uuid: String,
+    move_payload: Form<request::MovePayload<'_>>,
+) -> Result<Option<Json<response::CommitResultStatus>>> {
+    let parent_jid = move_payload.parent_jid;
+    let (tombstone, moved) = NewFileRecord::from_move_payload_and_user_id(move_payload, user.id);
+
+    let id = db
+        .run(move |conn| move_record(conn, tombstone, moved, parent_jid))
+        .await?;
+
+    match id {
+        Some(Ok(id)) => {
+            clients.lock().unwrap().notify(&uuid);
+
+            Ok(Some(Json(response::CommitResultStatus::Success(id))))
+        }
+        Some(Err(latest)) => {
+            rocket::info!(
+                "conflict: stale move user_id={} path={:?} parent_jid={:?} latest_id={}",
+                latest.user_id,
+                latest.path,
+                parent_jid,
+                latest.id
+            );
+
+            Ok(Some(Json(response::CommitResultStatus::Conflict(
+                latest.id,
+                latest.chunk_ids,
+                latest.deleted,
+            ))))
+        }
+        None => Ok(None),
+    }
+}
+
 #[get("/has_files")]
 async fn has_files(db: Db, user: User) -> Result<Json<bool>> {
     let result = db.run(move |conn| db_has_files(conn, user.id)).await?;
@@ -133,7 +331,18 @@ pub fn stage() -> AdHoc {
                 "Diesel Migrations",
                 middleware::run_migrations,
             ))
-            .mount("/metadata", routes![commit, has_files, list, poll])
+            .mount(
+                "/metadata",
+                routes![
+                    commit,
+                    commit_batch,
+                    commit_with_chunks,
+                    move_file,
+                    has_files,
+                    list,
+                    poll
+                ],
+            )
             .manage(clients)
     })
 }
@@ -14,6 +14,8 @@ pub struct FileRecord {
     pub chunk_ids: String,
     pub deleted: bool,
     pub path: String,
+    /// Path the file had before it was moved here with `/metadata/move`.
+    pub moved_from: Option<String>,
 }
 
 #[derive(Insertable, Deserialize, Serialize, Debug)]
@@ -25,6 +27,7 @@ pub struct NewFileRecord {
     pub chunk_ids: String,
     pub deleted: bool,
     pub path: String,
+    pub moved_from: Option<String>,
 }
 
 #[cfg(test)]
@@ -38,6 +41,7 @@ mod tests {
             chunk_ids: "hash1,hash2,hash3".to_string(),
             deleted: false,
             path: "recipes/test.cook".to_string(),
+            moved_from: None,
         };
 
         assert_eq!(record.user_id, 123);
@@ -53,6 +57,7 @@ mod tests {
             chunk_ids: "".to_string(),
             deleted: true,
             path: "recipes/deleted.cook".to_string(),
+            moved_from: None,
         };
 
         assert_eq!(record.deleted, true);
@@ -67,6 +72,7 @@ mod tests {
             chunk_ids: "hash1,hash2".to_string(),
             deleted: false,
             path: "test/path.cook".to_string(),
+            moved_from: None,
         };
 
         assert_eq!(record.id, 1);
@@ -75,4 +81,20 @@ mod tests {
         assert_eq!(record.deleted, false);
         assert_eq!(record.path, "test/path.cook");
     }
+
+    #[test]
+    fn test_moved_file_record_serializes_old_path() {
+        let record = FileRecord {
+            id: 2,
+            user_id: 123,
+            chunk_ids: "hash1".to_string(),
+            deleted: false,
+            path: "Brunch/Pancakes.cook".to_string(),
+            moved_from: Some("Breakfast/Pancakes.cook".to_string()),
+        };
+
+        let json = rocket::serde::json::to_string(&record).unwrap();
+
+        assert!(json.contains(r#""moved_from":"Breakfast/Pancakes.cook""#));
+    }
 }
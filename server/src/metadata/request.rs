@@ -9,7 +9,51 @@ use crate::chunk_id::ChunkId;
 pub(crate) struct CommitPayload<'r> {
     path: &'r str,
     deleted: bool,
+    #[field(validate = with(|ids| valid_chunk_ids(ids), "invalid chunk ID"))]
     chunk_ids: &'r str,
+    /// jid of the version the client changed, if it sends one. The commit
+    /// is refused when the path has a newer record by now.
+    pub(crate) parent_jid: Option<i32>,
+}
+
+/// Files committed together with `/metadata/commit_batch`, e.g.
+/// `entries[0].path=...&entries[0].chunk_ids=...&entries[1].path=...`.
+#[derive(Debug, FromForm)]
+pub(crate) struct CommitBatchPayload<'r> {
+    #[field(validate = len(1..))]
+    pub(crate) entries: Vec<CommitPayload<'r>>,
+}
+
+/// A batch sent as `multipart/form-data` to `/metadata/commit_with_chunks`,
+/// together with chunks the server may not have yet, e.g.
+/// `chunks[0].id=...` and the chunk itself in `chunks[0].data`.
+#[derive(Debug, FromForm)]
+pub(crate) struct CommitWithChunksPayload<'r> {
+    #[field(validate = len(1..))]
+    pub(crate) entries: Vec<CommitPayload<'r>>,
+    pub(crate) chunks: Vec<InlineChunk<'r>>,
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct InlineChunk<'r> {
+    #[field(validate = with(|id| !id.is_empty() && ChunkId::from(*id).is_valid(), "invalid chunk ID"))]
+    pub(crate) id: &'r str,
+    pub(crate) data: &'r [u8],
+}
+
+#[derive(Debug, FromForm)]
+pub(crate) struct MovePayload<'r> {
+    from: &'r str,
+    path: &'r str,
+    #[field(validate = with(|ids| valid_chunk_ids(ids), "invalid chunk ID"))]
+    chunk_ids: &'r str,
+    /// jid of the version at `path` the client knows of, 0 for none. The
+    /// move is refused when `path` has a newer record by now.
+    pub(crate) parent_jid: Option<i32>,
+}
+
+fn valid_chunk_ids(chunk_ids: &str) -> bool {
+    chunk_ids.split(',').all(|c| ChunkId::from(c).is_valid())
 }
 
 impl<'a> CommitPayload<'a> {
@@ -25,12 +69,36 @@ impl<'a> CommitPayload<'a> {
 }
 
 impl NewFileRecord {
-    pub(crate) fn from_payload_and_user_id(payload: Form<CommitPayload<'_>>, user_id: i32) -> Self {
+    pub(crate) fn from_payload_and_user_id(payload: &CommitPayload<'_>, user_id: i32) -> Self {
         NewFileRecord {
             path: payload.path.into(),
             deleted: payload.deleted,
             chunk_ids: payload.chunk_ids.into(),
             user_id,
+            moved_from: None,
         }
     }
+
+    /// Tombstone for the old path and the record for the new one.
+    pub(crate) fn from_move_payload_and_user_id(
+        payload: Form<MovePayload<'_>>,
+        user_id: i32,
+    ) -> (Self, Self) {
+        let tombstone = NewFileRecord {
+            path: payload.from.into(),
+            deleted: true,
+            chunk_ids: "".into(),
+            user_id,
+            moved_from: None,
+        };
+        let moved = NewFileRecord {
+            path: payload.path.into(),
+            deleted: false,
+            chunk_ids: payload.chunk_ids.into(),
+            user_id,
+            moved_from: Some(payload.from.into()),
+        };
+
+        (tombstone, moved)
+    }
 }
@@ -5,4 +5,19 @@ use rocket::serde::{Deserialize, Serialize};
 pub(crate) enum CommitResultStatus {
     Success(i32),
     NeedChunks(String),
+    /// The path was committed again since the client's parent jid, with the
+    /// jid, chunk IDs and deleted flag of the latest record.
+    Conflict(i32, String, bool),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub(crate) enum CommitBatchResultStatus {
+    /// jids of the entries, in the order they were sent.
+    Success(Vec<i32>),
+    /// Chunks missing for any of the entries, nothing was committed.
+    NeedChunks(String),
+    /// Paths committed again since their parent jid, with the jid, chunk IDs
+    /// and deleted flag of their latest record. Nothing was committed.
+    Conflict(Vec<(String, i32, String, bool)>),
 }
@@ -7,5 +7,6 @@ diesel::table! {
         path -> Text,
         deleted -> Bool,
         chunk_ids -> Text,
+        moved_from -> Nullable<Text>,
     }
 }
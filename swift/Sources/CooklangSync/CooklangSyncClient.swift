@@ -422,6 +422,22 @@ private let UNIFFI_CALLBACK_SUCCESS: Int32 = 0
 private let UNIFFI_CALLBACK_ERROR: Int32 = 1
 private let UNIFFI_CALLBACK_UNEXPECTED_ERROR: Int32 = 2
 
+#if swift(>=5.8)
+@_documentation(visibility: private)
+#endif
+fileprivate struct FfiConverterUInt32: FfiConverterPrimitive {
+    typealias FfiType = UInt32
+    typealias SwiftType = UInt32
+
+    public static func read(from buf: inout (data: Data, offset: Data.Index)) throws -> UInt32 {
+        return try lift(readInt(&buf))
+    }
+
+    public static func write(_ value: UInt32, into buf: inout [UInt8]) {
+        writeInt(&buf, lower(value))
+    }
+}
+
 #if swift(>=5.8)
 @_documentation(visibility: private)
 #endif
@@ -438,6 +454,22 @@ fileprivate struct FfiConverterInt32: FfiConverterPrimitive {
     }
 }
 
+#if swift(>=5.8)
+@_documentation(visibility: private)
+#endif
+fileprivate struct FfiConverterUInt64: FfiConverterPrimitive {
+    typealias FfiType = UInt64
+    typealias SwiftType = UInt64
+
+    public static func read(from buf: inout (data: Data, offset: Data.Index)) throws -> UInt64 {
+        return try lift(readInt(&buf))
+    }
+
+    public static func write(_ value: UInt64, into buf: inout [UInt8]) {
+        writeInt(&buf, lower(value))
+    }
+}
+
 #if swift(>=5.8)
 @_documentation(visibility: private)
 #endif
@@ -511,16 +543,118 @@ fileprivate struct FfiConverterString: FfiConverter {
  */
 public protocol SyncContextProtocol: AnyObject, Sendable {
     
+    /**
+     * Syncs files with this extension (without the dot) as binary.
+     */
+    func addBinaryExtension(`extension`: String) 
+    
+    /**
+     * Syncs files with this exact name as binary.
+     */
+    func addBinaryFilename(filename: String) 
+    
+    /**
+     * Syncs files with this extension (without the dot) as text.
+     * Must be called before `run`, like all the format settings below.
+     */
+    func addTextExtension(`extension`: String) 
+    
+    /**
+     * Syncs files with this exact name as text, e.g. `.shopping-list`.
+     */
+    func addTextFilename(filename: String) 
+    
     /**
      * Cancels the sync operation
      */
     func cancel() 
     
+    /**
+     * Turns end-to-end encryption off for the next `run`.
+     */
+    func clearEncryptionPassphrase() 
+    
+    func isEncryptionEnabled()  -> Bool
+    
+    func isMetered()  -> Bool
+    
+    func isPaused()  -> Bool
+    
+    /**
+     * Stops syncing once whatever is under way is done, without ending
+     * `run`. The file watcher keeps going, so nothing is missed.
+     */
+    func pause() 
+    
+    /**
+     * Goes on with a paused sync, right away.
+     */
+    func resume() 
+    
+    /**
+     * Splits binary files at content-defined boundaries (sizes in bytes)
+     * instead of fixed 1 MB blocks, so edits re-upload only changed regions.
+     */
+    func setContentDefinedChunking(minSize: UInt32, avgSize: UInt32, maxSize: UInt32) throws 
+    
+    /**
+     * Classifies files with unknown names by their content.
+     */
+    func setContentSniffing(enabled: Bool) 
+    
+    /**
+     * Names this device in conflicted copies, e.g. "Pixel" gives
+     * `Pancakes (conflicted copy from Pixel).cook` when the recipe was
+     * edited here and elsewhere at the same time. Must be called before `run`.
+     */
+    func setDeviceName(name: String) 
+    
+    /**
+     * Caps downloads at this many bytes per second, 0 (the default) for no
+     * limit. Can be changed while syncing.
+     */
+    func setDownloadLimit(bytesPerSec: UInt64) 
+    
+    /**
+     * Turns on end-to-end encryption of file contents and paths. Every
+     * device syncing the namespace needs the same passphrase and salt (e.g.
+     * the account's email). Deriving the key is deliberately slow, so call
+     * this off the main thread. Must be called before `run`. Uploads refuse
+     * to start in a namespace that has files synced unencrypted.
+     */
+    func setEncryptionPassphrase(passphrase: String, salt: String) throws 
+    
     /**
      * Sets the status listener for this context
      */
     func setListener(listener: SyncStatusListener) 
     
+    /**
+     * Tells whether the device is on a metered network, e.g. cellular.
+     * Photos and other binary files wait until it isn't, recipes keep
+     * syncing. Call it whenever the network changes, also while syncing.
+     */
+    func setMetered(metered: Bool) 
+    
+    /**
+     * Uploads up to this many batches of chunks at the same time, 4 by
+     * default. Lower it on metered or slow connections. Must be called
+     * before `run`.
+     */
+    func setUploadConcurrency(limit: UInt32) 
+    
+    /**
+     * Caps uploads at this many bytes per second, 0 (the default) for no
+     * limit. Unlike the settings above, it can be changed while syncing.
+     */
+    func setUploadLimit(bytesPerSec: UInt64) 
+    
+    /**
+     * Looks for changes here and on the server right away, instead of
+     * waiting for the next check. Does nothing while paused.
+     */
+    func syncNow() 
+    
 }
 /**
  * Context for managing sync lifecycle, cancellation, and status updates
@@ -583,6 +717,51 @@ public convenience init() {
     
 
     
+    /**
+     * Syncs files with this extension (without the dot) as binary.
+     */
+open func addBinaryExtension(`extension`: String)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_add_binary_extension(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(`extension`),$0
+    )
+}
+}
+    
+    /**
+     * Syncs files with this exact name as binary.
+     */
+open func addBinaryFilename(filename: String)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_add_binary_filename(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(filename),$0
+    )
+}
+}
+    
+    /**
+     * Syncs files with this extension (without the dot) as text.
+     * Must be called before `run`, like all the format settings below.
+     */
+open func addTextExtension(`extension`: String)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_add_text_extension(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(`extension`),$0
+    )
+}
+}
+    
+    /**
+     * Syncs files with this exact name as text, e.g. `.shopping-list`.
+     */
+open func addTextFilename(filename: String)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_add_text_filename(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(filename),$0
+    )
+}
+}
+    
     /**
      * Cancels the sync operation
      */
@@ -591,6 +770,127 @@ open func cancel()  {try! rustCall() {
             self.uniffiCloneHandle(),$0
     )
 }
+}
+    
+    /**
+     * Turns end-to-end encryption off for the next `run`.
+     */
+open func clearEncryptionPassphrase()  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_clear_encryption_passphrase(
+            self.uniffiCloneHandle(),$0
+    )
+}
+}
+    
+open func isEncryptionEnabled() -> Bool  {
+    return try!  FfiConverterBool.lift(try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_is_encryption_enabled(
+            self.uniffiCloneHandle(),$0
+    )
+})
+}
+    
+open func isMetered() -> Bool  {
+    return try!  FfiConverterBool.lift(try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_is_metered(
+            self.uniffiCloneHandle(),$0
+    )
+})
+}
+    
+open func isPaused() -> Bool  {
+    return try!  FfiConverterBool.lift(try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_is_paused(
+            self.uniffiCloneHandle(),$0
+    )
+})
+}
+    
+    /**
+     * Stops syncing once whatever is under way is done, without ending
+     * `run`. The file watcher keeps going, so nothing is missed.
+     */
+open func pause()  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_pause(
+            self.uniffiCloneHandle(),$0
+    )
+}
+}
+    
+    /**
+     * Goes on with a paused sync, right away.
+     */
+open func resume()  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_resume(
+            self.uniffiCloneHandle(),$0
+    )
+}
+}
+    
+    /**
+     * Splits binary files at content-defined boundaries (sizes in bytes)
+     * instead of fixed 1 MB blocks, so edits re-upload only changed regions.
+     */
+open func setContentDefinedChunking(minSize: UInt32, avgSize: UInt32, maxSize: UInt32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_content_defined_chunking(
+            self.uniffiCloneHandle(),
+        FfiConverterUInt32.lower(minSize),
+        FfiConverterUInt32.lower(avgSize),
+        FfiConverterUInt32.lower(maxSize),$0
+    )
+}
+}
+    
+    /**
+     * Classifies files with unknown names by their content.
+     */
+open func setContentSniffing(enabled: Bool)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_content_sniffing(
+            self.uniffiCloneHandle(),
+        FfiConverterBool.lower(enabled),$0
+    )
+}
+}
+    
+    /**
+     * Names this device in conflicted copies, e.g. "Pixel" gives
+     * `Pancakes (conflicted copy from Pixel).cook` when the recipe was
+     * edited here and elsewhere at the same time. Must be called before `run`.
+     */
+open func setDeviceName(name: String)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_device_name(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(name),$0
+    )
+}
+}
+    
+    /**
+     * Caps downloads at this many bytes per second, 0 (the default) for no
+     * limit. Can be changed while syncing.
+     */
+open func setDownloadLimit(bytesPerSec: UInt64)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_download_limit(
+            self.uniffiCloneHandle(),
+        FfiConverterUInt64.lower(bytesPerSec),$0
+    )
+}
+}
+    
+    /**
+     * Turns on end-to-end encryption of file contents and paths. Every
+     * device syncing the namespace needs the same passphrase and salt (e.g.
+     * the account's email). Deriving the key is deliberately slow, so call
+     * this off the main thread. Must be called before `run`. Uploads refuse
+     * to start in a namespace that has files synced unencrypted.
+     */
+open func setEncryptionPassphrase(passphrase: String, salt: String)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_encryption_passphrase(
+            self.uniffiCloneHandle(),
+        FfiConverterString.lower(passphrase),
+        FfiConverterString.lower(salt),$0
+    )
+}
 }
     
     /**
@@ -604,6 +904,55 @@ open func setListener(listener: SyncStatusListener)  {try! rustCall() {
 }
 }
     
+    /**
+     * Tells whether the device is on a metered network, e.g. cellular.
+     * Photos and other binary files wait until it isn't, recipes keep
+     * syncing. Call it whenever the network changes, also while syncing.
+     */
+open func setMetered(metered: Bool)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_metered(
+            self.uniffiCloneHandle(),
+        FfiConverterBool.lower(metered),$0
+    )
+}
+}
+    
+    /**
+     * Uploads up to this many batches of chunks at the same time, 4 by
+     * default. Lower it on metered or slow connections. Must be called
+     * before `run`.
+     */
+open func setUploadConcurrency(limit: UInt32)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_upload_concurrency(
+            self.uniffiCloneHandle(),
+        FfiConverterUInt32.lower(limit),$0
+    )
+}
+}
+    
+    /**
+     * Caps uploads at this many bytes per second, 0 (the default) for no
+     * limit. Unlike the settings above, it can be changed while syncing.
+     */
+open func setUploadLimit(bytesPerSec: UInt64)  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_set_upload_limit(
+            self.uniffiCloneHandle(),
+        FfiConverterUInt64.lower(bytesPerSec),$0
+    )
+}
+}
+    
+    /**
+     * Looks for changes here and on the server right away, instead of
+     * waiting for the next check. Does nothing while paused.
+     */
+open func syncNow()  {try! rustCall() {
+    uniffi_cooklang_sync_client_fn_method_synccontext_sync_now(
+            self.uniffiCloneHandle(),$0
+    )
+}
+}
+    
 
     
 }
@@ -900,8 +1249,6 @@ public enum SyncError: Swift.Error, Equatable, Hashable, Foundation.LocalizedErr
     
     case ReqwestError(message: String)
     
-    case ReqwestWirhMiddlewareError(message: String)
-    
     case ChannelSendError(message: String)
     
     case ConnectionInitError(message: String)
@@ -918,6 +1265,18 @@ public enum SyncError: Swift.Error, Equatable, Hashable, Foundation.LocalizedErr
     
     case BatchDownloadError(message: String)
     
+    case InvalidChunkingConfig(message: String)
+    
+    case EncryptionError(message: String)
+    
+    case IntegrityError(message: String)
+    
+    case UnrepresentablePath(message: String)
+    
+    case InvalidRemotePath(message: String)
+    
+    case ServerUnavailable(message: String)
+    
 
     
 
@@ -977,39 +1336,59 @@ public struct FfiConverterTypeSyncError: FfiConverterRustBuffer {
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 9: return .ReqwestWirhMiddlewareError(
+        case 9: return .ChannelSendError(
+            message: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 10: return .ConnectionInitError(
+            message: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 11: return .Unauthorized(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 10: return .ChannelSendError(
+        case 12: return .BodyExtractError(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 11: return .ConnectionInitError(
+        case 13: return .GetFromCacheError(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 12: return .Unauthorized(
+        case 14: return .UnlistedFileFormat(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 13: return .BodyExtractError(
+        case 15: return .Unknown(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 14: return .GetFromCacheError(
+        case 16: return .BatchDownloadError(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 15: return .UnlistedFileFormat(
+        case 17: return .InvalidChunkingConfig(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 16: return .Unknown(
+        case 18: return .EncryptionError(
             message: try FfiConverterString.read(from: &buf)
         )
         
-        case 17: return .BatchDownloadError(
+        case 19: return .IntegrityError(
+            message: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 20: return .UnrepresentablePath(
+            message: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 21: return .InvalidRemotePath(
+            message: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 22: return .ServerUnavailable(
             message: try FfiConverterString.read(from: &buf)
         )
         
@@ -1040,24 +1419,34 @@ public struct FfiConverterTypeSyncError: FfiConverterRustBuffer {
             writeInt(&buf, Int32(7))
         case .ReqwestError(_ /* message is ignored*/):
             writeInt(&buf, Int32(8))
-        case .ReqwestWirhMiddlewareError(_ /* message is ignored*/):
-            writeInt(&buf, Int32(9))
         case .ChannelSendError(_ /* message is ignored*/):
-            writeInt(&buf, Int32(10))
+            writeInt(&buf, Int32(9))
         case .ConnectionInitError(_ /* message is ignored*/):
-            writeInt(&buf, Int32(11))
+            writeInt(&buf, Int32(10))
         case .Unauthorized(_ /* message is ignored*/):
-            writeInt(&buf, Int32(12))
+            writeInt(&buf, Int32(11))
         case .BodyExtractError(_ /* message is ignored*/):
-            writeInt(&buf, Int32(13))
+            writeInt(&buf, Int32(12))
         case .GetFromCacheError(_ /* message is ignored*/):
-            writeInt(&buf, Int32(14))
+            writeInt(&buf, Int32(13))
         case .UnlistedFileFormat(_ /* message is ignored*/):
-            writeInt(&buf, Int32(15))
+            writeInt(&buf, Int32(14))
         case .Unknown(_ /* message is ignored*/):
-            writeInt(&buf, Int32(16))
+            writeInt(&buf, Int32(15))
         case .BatchDownloadError(_ /* message is ignored*/):
+            writeInt(&buf, Int32(16))
+        case .InvalidChunkingConfig(_ /* message is ignored*/):
             writeInt(&buf, Int32(17))
+        case .EncryptionError(_ /* message is ignored*/):
+            writeInt(&buf, Int32(18))
+        case .IntegrityError(_ /* message is ignored*/):
+            writeInt(&buf, Int32(19))
+        case .UnrepresentablePath(_ /* message is ignored*/):
+            writeInt(&buf, Int32(20))
+        case .InvalidRemotePath(_ /* message is ignored*/):
+            writeInt(&buf, Int32(21))
+        case .ServerUnavailable(_ /* message is ignored*/):
+            writeInt(&buf, Int32(22))
 
         
         }
@@ -1112,6 +1501,36 @@ public enum SyncStatus: Equatable, Hashable {
      */
     case error(message: String
     )
+    /**
+     * The server can't be reached, e.g. there's no network. Sync carries
+     * on by itself once it's back.
+     */
+    case offline
+    /**
+     * A request failed for a reason that's likely to pass, e.g. a server
+     * error. It's tried again in `delay_secs`, `attempt` times so far.
+     */
+    case retrying(attempt: UInt32, delaySecs: UInt64
+    )
+    /**
+     * Paused with `SyncContext::pause`, until `resume` is called.
+     */
+    case paused
+    /**
+     * A downloaded file only differs in letter case from one that's already
+     * there, and the filesystem can't tell them apart. It was saved as
+     * `local_path` instead of `path`. Updates of `path` keep landing there,
+     * edits made to it get uploaded as a new file.
+     */
+    case caseConflict(path: String, localPath: String
+    )
+    /**
+     * A file was changed here and on another device since it was last
+     * synced. The other version is now at `path`, the local one was kept
+     * as `conflicted_copy` and gets uploaded as a new file.
+     */
+    case conflict(path: String, conflictedCopy: String
+    )
 
 
 
@@ -1144,6 +1563,19 @@ public struct FfiConverterTypeSyncStatus: FfiConverterRustBuffer {
         case 6: return .error(message: try FfiConverterString.read(from: &buf)
         )
         
+        case 7: return .offline
+        
+        case 8: return .retrying(attempt: try FfiConverterUInt32.read(from: &buf), delaySecs: try FfiConverterUInt64.read(from: &buf)
+        )
+        
+        case 9: return .paused
+        
+        case 10: return .caseConflict(path: try FfiConverterString.read(from: &buf), localPath: try FfiConverterString.read(from: &buf)
+        )
+        
+        case 11: return .conflict(path: try FfiConverterString.read(from: &buf), conflictedCopy: try FfiConverterString.read(from: &buf)
+        )
+        
         default: throw UniffiInternalError.unexpectedEnumCase
         }
     }
@@ -1176,6 +1608,32 @@ public struct FfiConverterTypeSyncStatus: FfiConverterRustBuffer {
             writeInt(&buf, Int32(6))
             FfiConverterString.write(message, into: &buf)
             
+        
+        case .offline:
+            writeInt(&buf, Int32(7))
+        
+        
+        case let .retrying(attempt,delaySecs):
+            writeInt(&buf, Int32(8))
+            FfiConverterUInt32.write(attempt, into: &buf)
+            FfiConverterUInt64.write(delaySecs, into: &buf)
+            
+        
+        case .paused:
+            writeInt(&buf, Int32(9))
+        
+        
+        case let .caseConflict(path,localPath):
+            writeInt(&buf, Int32(10))
+            FfiConverterString.write(path, into: &buf)
+            FfiConverterString.write(localPath, into: &buf)
+            
+        
+        case let .conflict(path,conflictedCopy):
+            writeInt(&buf, Int32(11))
+            FfiConverterString.write(path, into: &buf)
+            FfiConverterString.write(conflictedCopy, into: &buf)
+            
         }
     }
 }
@@ -1219,6 +1677,17 @@ fileprivate struct FfiConverterOptionString: FfiConverterRustBuffer {
         }
     }
 }
+/**
+ * Drops superseded rows from the local registry. `run` already does this
+ * periodically, this is for apps that only use the one-off functions.
+ */
+public func compactRegistry(dbFilePath: String, namespaceId: Int32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
+    uniffi_cooklang_sync_client_fn_func_compact_registry(
+        FfiConverterString.lower(dbFilePath),
+        FfiConverterInt32.lower(namespaceId),$0
+    )
+}
+}
 /**
  * Synchronous alias to async run function.
  * Intended to used by external (written in other languages) callers.
@@ -1236,12 +1705,14 @@ public func run(context: SyncContext, storageDir: String, dbFilePath: String, ap
 }
 }
 /**
- * Runs one-off download of updates from remote server.
+ * Runs one-off download of updates from remote server, with the formats,
+ * passphrase and network settings configured on `context`.
  * Note, it's not very efficient as requires to re-initialize DB connection,
  * chunker, remote client, etc every time it runs.
  */
-public func runDownloadOnce(storageDir: String, dbFilePath: String, apiEndpoint: String, remoteToken: String, namespaceId: Int32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
+public func runDownloadOnce(context: SyncContext, storageDir: String, dbFilePath: String, apiEndpoint: String, remoteToken: String, namespaceId: Int32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
     uniffi_cooklang_sync_client_fn_func_run_download_once(
+        FfiConverterTypeSyncContext_lower(context),
         FfiConverterString.lower(storageDir),
         FfiConverterString.lower(dbFilePath),
         FfiConverterString.lower(apiEndpoint),
@@ -1251,12 +1722,14 @@ public func runDownloadOnce(storageDir: String, dbFilePath: String, apiEndpoint:
 }
 }
 /**
- * Runs one-off upload of updates to remote server.
+ * Runs one-off upload of updates to remote server, with the formats,
+ * passphrase and network settings configured on `context`.
  * Note, it's not very efficient as requires to re-initialize DB connection,
  * chunker, remote client, etc every time it runs.
  */
-public func runUploadOnce(storageDir: String, dbFilePath: String, apiEndpoint: String, remoteToken: String, namespaceId: Int32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
+public func runUploadOnce(context: SyncContext, storageDir: String, dbFilePath: String, apiEndpoint: String, remoteToken: String, namespaceId: Int32)throws   {try rustCallWithError(FfiConverterTypeSyncError_lift) {
     uniffi_cooklang_sync_client_fn_func_run_upload_once(
+        FfiConverterTypeSyncContext_lower(context),
         FfiConverterString.lower(storageDir),
         FfiConverterString.lower(dbFilePath),
         FfiConverterString.lower(apiEndpoint),
@@ -1294,24 +1767,84 @@ private let initializationResult: InitializationResult = {
     if bindings_contract_version != scaffolding_contract_version {
         return InitializationResult.contractVersionMismatch
     }
+    if (uniffi_cooklang_sync_client_checksum_func_compact_registry() != 2415) {
+        return InitializationResult.apiChecksumMismatch
+    }
     if (uniffi_cooklang_sync_client_checksum_func_run() != 17439) {
         return InitializationResult.apiChecksumMismatch
     }
-    if (uniffi_cooklang_sync_client_checksum_func_run_download_once() != 16007) {
+    if (uniffi_cooklang_sync_client_checksum_func_run_download_once() != 44790) {
         return InitializationResult.apiChecksumMismatch
     }
-    if (uniffi_cooklang_sync_client_checksum_func_run_upload_once() != 50094) {
+    if (uniffi_cooklang_sync_client_checksum_func_run_upload_once() != 19255) {
         return InitializationResult.apiChecksumMismatch
     }
     if (uniffi_cooklang_sync_client_checksum_func_wait_remote_update() != 36540) {
         return InitializationResult.apiChecksumMismatch
     }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_add_binary_extension() != 50056) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_add_binary_filename() != 6503) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_add_text_extension() != 26285) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_add_text_filename() != 58213) {
+        return InitializationResult.apiChecksumMismatch
+    }
     if (uniffi_cooklang_sync_client_checksum_method_synccontext_cancel() != 60207) {
         return InitializationResult.apiChecksumMismatch
     }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_clear_encryption_passphrase() != 55197) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_is_encryption_enabled() != 38870) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_is_metered() != 15774) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_is_paused() != 12536) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_pause() != 21794) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_resume() != 279) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_content_defined_chunking() != 8895) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_content_sniffing() != 47127) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_device_name() != 24950) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_download_limit() != 3849) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_encryption_passphrase() != 28589) {
+        return InitializationResult.apiChecksumMismatch
+    }
     if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_listener() != 33349) {
         return InitializationResult.apiChecksumMismatch
     }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_metered() != 28252) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_upload_concurrency() != 32417) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_set_upload_limit() != 58787) {
+        return InitializationResult.apiChecksumMismatch
+    }
+    if (uniffi_cooklang_sync_client_checksum_method_synccontext_sync_now() != 8381) {
+        return InitializationResult.apiChecksumMismatch
+    }
     if (uniffi_cooklang_sync_client_checksum_method_syncstatuslistener_on_status_changed() != 44707) {
         return InitializationResult.apiChecksumMismatch
     }